@@ -1,9 +1,10 @@
 mod imp {
     use adw::prelude::*;
     use adw::subclass::prelude::*;
+    use chirp_protocol::{MessageStatus, Reaction};
     use glib::{derived_properties, object_subclass, Properties};
     use gtk::glib;
-    use std::cell::{OnceCell, RefCell};
+    use std::cell::{Cell, OnceCell, RefCell};
 
     use super::MessageData;
     use crate::message::MessageRow;
@@ -25,6 +26,19 @@ mod imp {
         pub message_number: OnceCell<u64>,
         #[property(get, set)]
         pub target_row: RefCell<Option<MessageRow>>,
+        // None until the server has saved the message
+        pub status: Cell<Option<MessageStatus>>,
+        // Whether the text was changed after sending
+        #[property(get, set)]
+        pub edited: Cell<bool>,
+        // Number of the earlier message this one replies to
+        #[property(get, set)]
+        pub reply_to: OnceCell<u64>,
+        // Text of the replied message or a placeholder if it was deleted
+        #[property(get, set)]
+        pub reply_text: RefCell<String>,
+        // Oldest first. A user can have a single reaction
+        pub reactions: RefCell<Vec<Reaction>>,
     }
 
     #[object_subclass]
@@ -37,10 +51,13 @@ mod imp {
     impl ObjectImpl for MessageObject {}
 }
 
+use adw::subclass::prelude::*;
+use chirp_protocol::{MessageStatus, Reaction};
 use glib::{wrapper, Object};
 use gtk::glib;
 
 use crate::user::UserObject;
+use crate::ws::RequestType;
 
 wrapper! {
     pub struct MessageObject(ObjectSubclass<imp::MessageObject>);
@@ -69,6 +86,75 @@ impl MessageObject {
 
         obj
     }
+
+    pub fn message_status(&self) -> Option<MessageStatus> {
+        self.imp().status.get()
+    }
+
+    /// Moves the status forward and shows it on the row. Older statuses are ignored
+    /// as receipts can arrive out of order
+    pub fn update_status(&self, status: MessageStatus) {
+        if self
+            .message_status()
+            .is_some_and(|current| current >= status)
+        {
+            return;
+        }
+
+        self.imp().status.set(Some(status));
+        if let Some(row) = self.target_row() {
+            row.show_status(Some(status));
+        }
+    }
+
+    /// Sets the reaction of the user or removes it if there is no emoji and shows it on the row
+    pub fn set_reaction(&self, user_id: u64, emoji: Option<String>) {
+        {
+            let mut reactions = self.imp().reactions.borrow_mut();
+            reactions.retain(|reaction| reaction.user_id != user_id);
+            if let Some(emoji) = emoji {
+                reactions.push(Reaction { user_id, emoji });
+            }
+        }
+
+        if let Some(row) = self.target_row() {
+            row.show_reactions();
+        }
+    }
+
+    /// Reacts with the emoji as the owner. Reacting with the current emoji again removes it
+    pub fn toggle_reaction(&self, emoji: String) {
+        // Only messages saved by the server can be reacted to
+        let Some(message_number) = self.imp().message_number.get().copied() else {
+            return;
+        };
+
+        let owner_id = self.sent_from().owner_id();
+        let already_reacted = self
+            .imp()
+            .reactions
+            .borrow()
+            .iter()
+            .any(|reaction| reaction.user_id == owner_id && reaction.emoji == emoji);
+        let emoji = if already_reacted { None } else { Some(emoji) };
+
+        let other_user = self.other_user();
+        other_user.add_to_queue(RequestType::React(
+            other_user.user_id(),
+            message_number,
+            emoji.to_owned(),
+        ));
+        self.set_reaction(owner_id, emoji);
+    }
+
+    /// The user of the chat that is not the owner
+    pub fn other_user(&self) -> UserObject {
+        if self.sent_from().user_id() == self.sent_from().owner_id() {
+            self.sent_to()
+        } else {
+            self.sent_from()
+        }
+    }
 }
 
 #[derive(Default, Clone)]
@@ -3,7 +3,7 @@ mod imp {
     use adw::Avatar;
     use glib::subclass::InitializingObject;
     use glib::{object_subclass, Binding};
-    use gtk::{glib, Box, Button, CompositeTemplate, Label, PopoverMenu, Revealer};
+    use gtk::{glib, Box, Button, CompositeTemplate, EmojiChooser, Label, PopoverMenu, Revealer};
     use std::cell::{OnceCell, RefCell};
 
     use crate::message::MessageObject;
@@ -20,8 +20,18 @@ mod imp {
         #[template_child]
         pub sent_by: TemplateChild<Label>,
         #[template_child]
+        pub reply_button: TemplateChild<Button>,
+        #[template_child]
+        pub reply_preview: TemplateChild<Label>,
+        #[template_child]
         pub message: TemplateChild<Label>,
         #[template_child]
+        pub message_status: TemplateChild<Label>,
+        #[template_child]
+        pub reactions_box: TemplateChild<Box>,
+        #[template_child]
+        pub edited_button: TemplateChild<Button>,
+        #[template_child]
         pub sender: TemplateChild<Avatar>,
         #[template_child]
         pub receiver: TemplateChild<Avatar>,
@@ -31,6 +41,8 @@ mod imp {
         pub receiver_avatar_button: TemplateChild<Button>,
         #[template_child]
         pub message_menu: TemplateChild<PopoverMenu>,
+        #[template_child]
+        pub reaction_chooser: TemplateChild<EmojiChooser>,
         pub bindings: RefCell<Vec<Binding>>,
         pub message_data: OnceCell<MessageObject>,
     }
@@ -46,6 +58,15 @@ mod imp {
             klass.install_action("message-row.copy", None, move |row, _, _| {
                 row.copy_message()
             });
+            klass.install_action("message-row.reply", None, move |row, _, _| {
+                row.reply_message()
+            });
+            klass.install_action("message-row.react", None, move |row, _, _| {
+                row.imp().reaction_chooser.popup()
+            });
+            klass.install_action("message-row.edit", None, move |row, _, _| {
+                row.edit_message()
+            });
             klass.install_action("message-row.delete", None, move |row, _, _| {
                 row.delete_message()
             });
@@ -65,17 +86,19 @@ mod imp {
 
 use adw::prelude::*;
 use adw::subclass::prelude::*;
+use adw::Toast;
+use chirp_protocol::MessageStatus;
 use gdk::{Cursor, Rectangle};
 use glib::{clone, timeout_add_local_once, wrapper, Object};
 use gtk::{
-    gdk, glib, Accessible, Box, Buildable, ConstraintTarget, GestureClick, Orientable,
-    RevealerTransitionType, Widget,
+    gdk, glib, Accessible, Align, Box, Buildable, Button, ConstraintTarget, GestureClick,
+    Orientable, RevealerTransitionType, Widget,
 };
 use std::time::Duration;
 use tracing::info;
 
 use crate::message::MessageObject;
-use crate::user::UserProfile;
+use crate::user::{UserProfile, UserPrompt};
 use crate::window::Window;
 use crate::ws::RequestType;
 
@@ -101,6 +124,9 @@ impl MessageRow {
             row.imp().message.set_xalign(1.0);
             row.imp().message_content.add_css_class("message-row-sent");
             row.imp().placeholder.set_visible(true);
+            row.imp().message_status.set_visible(true);
+            row.imp().reactions_box.set_halign(Align::End);
+            row.show_status(object.message_status());
             revealer.set_transition_type(RevealerTransitionType::SlideLeft)
         } else {
             let receiver = row.imp().receiver.get();
@@ -112,10 +138,18 @@ impl MessageRow {
             row.imp()
                 .message_content
                 .add_css_class("message-row-received");
+            // Only the sender can edit a message
+            row.action_set_enabled("message-row.edit", false);
             revealer.set_transition_type(RevealerTransitionType::SlideRight)
         }
 
+        if object.imp().reply_to.get().is_some() {
+            row.imp().reply_button.set_visible(true);
+            row.imp().reply_button.set_cursor(Some(&new_cursor));
+        }
+
         row.imp().message_data.set(object).unwrap();
+        row.show_reactions();
         row.bind();
         row.connect_button_signals(window);
 
@@ -171,6 +205,85 @@ impl MessageRow {
             .build();
 
         bindings.push(message_binding);
+
+        let edited_binding = message_object
+            .bind_property("edited", &self.imp().edited_button.get(), "visible")
+            .sync_create()
+            .build();
+
+        bindings.push(edited_binding);
+
+        let reply_binding = message_object
+            .bind_property("reply-text", &self.imp().reply_preview.get(), "label")
+            .sync_create()
+            .build();
+
+        bindings.push(reply_binding);
+    }
+
+    /// Shows how far the sent message got. None if the server has not saved it yet
+    pub fn show_status(&self, status: Option<MessageStatus>) {
+        let status_label = self.imp().message_status.get();
+
+        let text = match status {
+            None => "Sending",
+            Some(MessageStatus::Sent) => "Sent",
+            Some(MessageStatus::Delivered) => "Delivered",
+            Some(MessageStatus::Read) => "Read",
+        };
+        status_label.set_label(text);
+
+        if status == Some(MessageStatus::Read) {
+            status_label.add_css_class("message-status-read");
+        } else {
+            status_label.remove_css_class("message-status-read");
+        }
+    }
+
+    /// Shows a chip for every emoji used on the message with how many users reacted with it
+    pub fn show_reactions(&self) {
+        let reactions_box = self.imp().reactions_box.get();
+        while let Some(child) = reactions_box.first_child() {
+            reactions_box.remove(&child);
+        }
+
+        let message_data = self.imp().message_data.get().unwrap();
+        let owner_id = message_data.sent_from().owner_id();
+
+        // (Emoji, Reaction count, Whether the owner reacted with it) in the order they were first used
+        let mut chips: Vec<(String, u32, bool)> = Vec::new();
+        for reaction in message_data.imp().reactions.borrow().iter() {
+            let own = reaction.user_id == owner_id;
+            match chips
+                .iter_mut()
+                .find(|(emoji, _, _)| *emoji == reaction.emoji)
+            {
+                Some(chip) => {
+                    chip.1 += 1;
+                    chip.2 |= own;
+                }
+                None => chips.push((reaction.emoji.to_owned(), 1, own)),
+            }
+        }
+
+        reactions_box.set_visible(!chips.is_empty());
+
+        for (emoji, count, own) in chips {
+            let chip = Button::builder()
+                .label(format!("{emoji} {count}"))
+                .can_focus(false)
+                .css_classes(["reaction-chip"])
+                .build();
+
+            if own {
+                chip.add_css_class("reaction-chip-own");
+            }
+
+            chip.connect_clicked(clone!(@weak message_data => move |_| {
+                message_data.toggle_reaction(emoji.to_owned());
+            }));
+            reactions_box.append(&chip);
+        }
     }
 
     fn connect_button_signals(&self, window: &Window) {
@@ -187,6 +300,48 @@ impl MessageRow {
             UserProfile::new(sent_from, &window);
         }));
 
+        self.imp()
+            .edited_button
+            .connect_clicked(clone!(@weak self as row => move |_| {
+                let message_data = row.imp().message_data.get().unwrap();
+                // The history can only be asked for once the server has saved the message
+                let Some(message_number) = message_data.imp().message_number.get().copied() else {
+                    return;
+                };
+                let other_user = message_data.other_user();
+                other_user.add_to_queue(RequestType::GetMessageHistory(
+                    other_user.user_id(),
+                    message_number,
+                ));
+            }));
+
+        self.imp().reply_button.connect_clicked(
+            clone!(@weak self as row, @weak window => move |_| {
+                let message_data = row.imp().message_data.get().unwrap();
+                let Some(reply_to) = message_data.imp().reply_to.get().copied() else {
+                    return;
+                };
+
+                match message_data.other_user().find_message(reply_to) {
+                    Some(target) => window.scroll_to_message(&target),
+                    None => {
+                        let toast = Toast::builder()
+                            .title("The message was deleted")
+                            .timeout(2)
+                            .build();
+                        window.imp().toast_overlay.add_toast(toast);
+                    }
+                }
+            }),
+        );
+
+        self.imp().reaction_chooser.connect_emoji_picked(
+            clone!(@weak self as row => move |_, emoji| {
+                let message_data = row.imp().message_data.get().unwrap();
+                message_data.toggle_reaction(emoji.to_string());
+            }),
+        );
+
         let gesture = GestureClick::new();
         gesture.set_button(3);
         self.imp().message_content.add_controller(gesture.clone());
@@ -201,16 +356,39 @@ impl MessageRow {
         );
     }
 
+    fn reply_message(&self) {
+        let message_data = self.imp().message_data.get().unwrap();
+
+        // Only messages saved by the server have a number that can be replied to
+        if message_data.imp().message_number.get().is_none() {
+            return;
+        }
+
+        if let Some(window) = self.root().and_downcast::<Window>() {
+            window.start_reply(message_data);
+        }
+    }
+
+    fn edit_message(&self) {
+        let message_data = self.imp().message_data.get().unwrap();
+
+        // Messages that are still in the queue do not have a number to edit yet
+        if message_data.imp().message_number.get().is_none() {
+            return;
+        }
+
+        let Some(window) = self.root().and_downcast::<Window>() else {
+            return;
+        };
+
+        let prompt = UserPrompt::new("Confirm").edit_message(&window, message_data);
+        prompt.present();
+    }
+
     fn delete_message(&self) {
         info!("Deleting a message from the UI");
         let message_data = self.imp().message_data.get().unwrap();
-
-        let other_user =
-            if message_data.sent_from().user_id() == message_data.sent_from().owner_id() {
-                message_data.sent_to()
-            } else {
-                message_data.sent_from()
-            };
+        let other_user = message_data.other_user();
 
         let message_number = message_data.message_number();
 
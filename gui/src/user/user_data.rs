@@ -5,7 +5,7 @@ mod imp {
     use gio::glib::subclass::Signal;
     use gio::ListStore;
     use glib::once_cell::sync::Lazy;
-    use glib::{derived_properties, object_subclass, Properties};
+    use glib::{derived_properties, object_subclass, Properties, SignalHandlerId, SourceId};
     use gtk::{gdk, glib};
     use std::cell::{Cell, OnceCell, RefCell};
     use std::sync::Mutex;
@@ -28,15 +28,29 @@ mod imp {
         pub messages: OnceCell<ListStore>,
         #[property(get, set)]
         pub user_ws: OnceCell<WSObject>,
+        pub ws_signal_ids: RefCell<Vec<SignalHandlerId>>,
         pub request_queue: Mutex<RefCell<Vec<RequestType>>>,
         #[property(get, set)]
         pub request_processing: Cell<bool>,
         #[property(get, set)]
         pub owner_id: Cell<u64>,
         #[property(get, set)]
-        pub user_token: OnceCell<String>,
+        pub user_token: RefCell<String>,
         #[property(get, set)]
         pub message_number: Cell<u64>,
+        // The last message number a read receipt was sent for
+        #[property(get, set)]
+        pub read_number: Cell<u64>,
+        // Whether the user is typing to the owner right now
+        #[property(get, set)]
+        pub typing: Cell<bool>,
+        pub typing_timer: RefCell<Option<SourceId>>,
+        // online, away or offline. Empty until the server sends it
+        #[property(get, set)]
+        pub presence: RefCell<String>,
+        // The presence as shown to the user, including the last seen time
+        #[property(get, set)]
+        pub last_seen: RefCell<String>,
     }
 
     #[object_subclass]
@@ -66,13 +80,19 @@ mod imp {
 }
 
 use adw::prelude::*;
+use chirp_protocol::{
+    ClientRequest, DeleteMessage, EditMessage, FullUserData, ImageUpdate, MessageData,
+    MessageHistoryRequest, MessageReceipt, MessageStatus, MessageSyncRequest, NameUpdate,
+    PairDevice, Presence, PresenceStatus, ReactionRequest, TypingUpdate, UserIDs,
+};
+use chrono::{DateTime, Local};
 use gdk::{gdk_pixbuf, Paintable, Texture};
 use gdk_pixbuf::{InterpType, PixbufLoader};
 use gio::subclass::prelude::ObjectSubclassIsExt;
 use gio::{spawn_blocking, ListStore};
 use glib::{
     clone, closure_local, timeout_add_local_once, Bytes, ControlFlow, MainContext, Object,
-    Priority, Receiver, Sender,
+    Priority, Receiver,
 };
 use gtk::{gdk, glib};
 use std::time::Duration;
@@ -80,11 +100,10 @@ use tracing::{debug, info};
 
 use crate::message::MessageObject;
 use crate::utils::{generate_random_avatar_link, get_avatar, get_random_color};
-use crate::window::Window;
-use crate::ws::{
-    DeleteMessage, FullUserData, ImageUpdate, MessageSyncData, MessageSyncRequest, NameUpdate,
-    RequestType, UserIDs, WSObject,
-};
+use crate::ws::{RequestType, WSObject};
+
+// The typing status is hidden if no update comes in this long. The other side resends it while typing
+const TYPING_DISPLAY_TIMEOUT: Duration = Duration::from_secs(6);
 
 glib::wrapper! {
     pub struct UserObject(ObjectSubclass<imp::UserObject>);
@@ -97,6 +116,7 @@ impl UserObject {
         color_to_ignore: Option<&str>,
         user_id: Option<u64>,
         user_token: Option<String>,
+        ws: &WSObject,
     ) -> Self {
         let messages = ListStore::new::<MessageObject>();
         let random_color = get_random_color(color_to_ignore);
@@ -116,22 +136,29 @@ impl UserObject {
             obj.set_owner_id(id);
         }
 
-        let ws = WSObject::new();
         obj.check_image_link(image_link);
 
-        obj.set_user_ws(ws);
+        // Every user shares the same WS connection of the client
+        obj.set_user_ws(ws.clone());
         obj.set_message_number(0);
         let user_object = obj.clone();
 
         // This signal gets emitted when the connection is once lost but reconnected again
-        obj.user_ws().connect_closure(
+        let id = obj.user_ws().connect_closure(
             "ws-reconnect",
             false,
             closure_local!(move |_from: WSObject, _success: bool| {
+                // The receipt of the pending message can not arrive on the new connection
+                // so it is sent again after the user is reconnected
+                if let Some((message_data, message)) = user_object.take_pending_message() {
+                    user_object.insert_to_queue(RequestType::SendMessage(message_data, message));
+                }
+
                 // Until reconnection success is received, all queue process is stopped
                 user_object.add_queue_to_first(RequestType::ReconnectUser);
             }),
         );
+        obj.imp().ws_signal_ids.borrow_mut().push(id);
         obj
     }
 
@@ -215,21 +242,39 @@ impl UserObject {
 
         // The process must not start twice otherwise the same
         // request can get processed twice, creating disaster
-        if !self.request_processing() {
-            self.process_queue(None);
-        };
+        self.resume_queue();
         self
     }
 
     pub fn add_queue_to_first(&self, request_type: RequestType) {
+        self.insert_to_queue(request_type);
+        self.process_queue(Some(1));
+    }
+
+    /// Puts the request at the start of the queue without processing it
+    fn insert_to_queue(&self, request_type: RequestType) {
         debug!("adding to queue: {:#?}", request_type);
-        {
-            let locked_queue = self.imp().request_queue.lock().unwrap();
-            let mut queue = locked_queue.borrow_mut();
-            queue.insert(0, request_type);
+        let locked_queue = self.imp().request_queue.lock().unwrap();
+        let mut queue = locked_queue.borrow_mut();
+        queue.insert(0, request_type);
+    }
+
+    /// Sends the typing update if the WS is connected. Updates are never queued for later
+    /// as they would be outdated by then. Returns whether it was sent
+    pub fn send_typing(&self, typing: bool) -> bool {
+        if self.user_ws().ws_conn().is_none() {
+            debug!("Not connected. Dropping typing update");
+            return false;
         }
+        self.add_to_queue(RequestType::Typing(typing));
+        true
+    }
 
-        self.process_queue(Some(1));
+    /// Continues processing the queue if it is not being processed already
+    pub fn resume_queue(&self) {
+        if !self.request_processing() {
+            self.process_queue(None);
+        }
     }
 
     /// Processes queued stuff if ws conn is available
@@ -245,58 +290,102 @@ impl UserObject {
 
         for task in queue_list {
             if user_ws.ws_conn().is_some() {
+                // The next message can only be sent once the server gave a number to the last one
+                if matches!(task, RequestType::SendMessage(..))
+                    && user_ws.imp().pending_message.borrow().is_some()
+                {
+                    info!("Waiting for the receipt of the last sent message");
+                    break;
+                }
+
                 debug!("starting processing {task:#?}");
-                match task {
+                let request = match task {
                     RequestType::ReconnectUser => {
-                        let id_data = UserIDs::new_json(self.user_id(), self.user_token());
-                        user_ws.reconnect_user(id_data);
-                    }
-                    RequestType::CreateNewUser => {
-                        let user_data = FullUserData::new(self).to_json();
-                        user_ws.create_new_user(user_data);
+                        // The owner authenticates the session, other users are added as contacts
+                        if self.user_id() == self.owner_id() {
+                            ClientRequest::Authenticate(UserIDs::new(
+                                self.user_id(),
+                                self.user_token(),
+                            ))
+                        } else {
+                            ClientRequest::ReconnectUser(self.user_id())
+                        }
                     }
+                    RequestType::CreateNewUser => ClientRequest::CreateNewUser(self.to_user_data()),
                     RequestType::SendMessage(message_data, msg_obj) => {
-                        self.set_message_number(self.message_number() + 1);
-                        msg_obj.set_message_number(self.message_number());
-
-                        let data = message_data
-                            .update_token(self.user_token())
-                            .update_message_number(self.message_number())
-                            .to_json();
-                        user_ws.send_text_message(&data);
+                        // The server picks the number and sends it back with the receipt
+                        user_ws
+                            .imp()
+                            .pending_message
+                            .replace(Some((message_data.clone(), msg_obj)));
+                        ClientRequest::Message(message_data.update_message_number(0))
                     }
                     RequestType::ImageUpdated(link) => {
                         self.check_image_link(link.clone());
-                        let image_data = ImageUpdate::new_json(link, self.user_token());
-                        user_ws.image_link_updated(&image_data);
+                        ClientRequest::ImageUpdated(ImageUpdate { image_link: link })
                     }
                     RequestType::NameUpdated(name) => {
                         self.set_name(name.to_owned());
-                        let name_data = NameUpdate::new_json(name.to_string(), self.user_token());
-                        user_ws.name_updated(&name_data)
-                    }
-                    RequestType::GetUserData(id) => {
-                        let user_data = UserIDs::new_json(id.to_owned(), self.user_token());
-                        user_ws.get_user_data(&user_data)
+                        ClientRequest::NameUpdated(NameUpdate { new_name: name })
                     }
+                    RequestType::GetUserData(id) => ClientRequest::GetUserData(id),
                     RequestType::GetLastMessageNumber(user) => {
-                        let data = UserIDs::new_json(user.user_id(), self.user_token());
-                        user_ws.selection_update(data)
+                        ClientRequest::MessageNumber(user.user_id())
                     }
                     RequestType::SyncMessage(start_at, end_at) => {
-                        let data = MessageSyncRequest::new_json(
-                            self.user_id(),
+                        ClientRequest::SyncMessage(MessageSyncRequest {
+                            user_id: self.user_id(),
                             start_at,
                             end_at,
-                            self.user_token(),
-                        );
-                        user_ws.sync_message(data)
+                        })
                     }
+                    RequestType::RotateToken => ClientRequest::RotateToken,
+                    RequestType::CreatePairingCode => ClientRequest::CreatePairingCode,
+                    RequestType::PairDevice(code) => ClientRequest::PairDevice(PairDevice {
+                        code,
+                        device_name: glib::host_name().to_string(),
+                    }),
                     RequestType::DeleteMessage(user_id, number) => {
-                        let data = DeleteMessage::new_json(user_id, number, self.user_token());
-                        user_ws.delete_message(data)
+                        ClientRequest::DeleteMessage(DeleteMessage {
+                            user_id,
+                            message_number: number,
+                        })
                     }
-                }
+                    RequestType::EditMessage(user_id, number, message) => {
+                        ClientRequest::EditMessage(EditMessage {
+                            user_id,
+                            message_number: number,
+                            message,
+                        })
+                    }
+                    RequestType::GetMessageHistory(user_id, number) => {
+                        ClientRequest::MessageHistory(MessageHistoryRequest {
+                            user_id,
+                            message_number: number,
+                        })
+                    }
+                    RequestType::React(user_id, number, emoji) => {
+                        ClientRequest::React(ReactionRequest {
+                            user_id,
+                            message_number: number,
+                            emoji,
+                        })
+                    }
+                    RequestType::Typing(typing) => ClientRequest::Typing(TypingUpdate {
+                        user_id: self.user_id(),
+                        typing,
+                    }),
+                    RequestType::SetPresence(status) => ClientRequest::SetPresence(status),
+                    RequestType::HideLastSeen(hide) => ClientRequest::HideLastSeen(hide),
+                    RequestType::ReadMessages(number) => {
+                        ClientRequest::MessageReceipt(MessageReceipt {
+                            user_id: self.user_id(),
+                            message_number: number,
+                            status: MessageStatus::Read,
+                        })
+                    }
+                };
+                user_ws.send_request(&request);
                 highest_index += 1;
 
                 if let Some(limit) = process_limit {
@@ -318,6 +407,11 @@ impl UserObject {
             for _x in 0..highest_index {
                 queue_list.remove(0);
             }
+
+            // Typing updates would be outdated once the connection is back
+            if connection_lost {
+                queue_list.retain(|task| !matches!(task, RequestType::Typing(_)));
+            }
         }
 
         // In case connection lost, this will prevent further queue processing
@@ -359,6 +453,7 @@ impl UserObject {
                 let user_object = self.clone();
                 timeout_add_local_once(Duration::from_millis(500), move || {
                     user_object.messages().remove(index as u32);
+                    user_object.update_replies();
                 });
 
                 break;
@@ -366,97 +461,229 @@ impl UserObject {
         }
     }
 
-    pub fn handle_ws(&self, window: Window) {
+    /// Get the message of the chat with the number. None if it was not loaded or not sent yet
+    pub fn find_message(&self, target_number: u64) -> Option<MessageObject> {
+        self.messages()
+            .iter::<MessageObject>()
+            .filter_map(|message_data| message_data.ok())
+            .find(|message_content| {
+                message_content.imp().message_number.get() == Some(&target_number)
+            })
+    }
+
+    /// Shows the new text of an edited message
+    pub fn edit_message(&self, target_number: u64, new_text: String) {
+        if let Some(message_content) = self.find_message(target_number) {
+            message_content.set_message(new_text);
+            message_content.set_edited(true);
+            self.update_replies();
+        }
+    }
+
+    /// Shows the reaction a user set or removed on a message
+    pub fn update_reaction(&self, target_number: u64, reacted_by: u64, emoji: Option<String>) {
+        if let Some(message_content) = self.find_message(target_number) {
+            message_content.set_reaction(reacted_by, emoji);
+        }
+    }
+
+    /// Shows the text of the message the given message replies to
+    pub fn show_reply(&self, message_content: &MessageObject) {
+        let Some(reply_to) = message_content.imp().reply_to.get().copied() else {
+            return;
+        };
+
+        // Every message of the chat is synced so a missing one was deleted
+        let reply_text = self
+            .find_message(reply_to)
+            .map_or(String::from("Deleted message"), |target| target.message());
+        message_content.set_reply_text(reply_text);
+    }
+
+    /// Updates the replied text of every message of the chat
+    pub fn update_replies(&self) {
+        for message_data in self.messages().iter::<MessageObject>() {
+            self.show_reply(&message_data.unwrap());
+        }
+    }
+
+    /// Takes the sent message that is waiting for a number if it belongs to this chat
+    fn take_pending_message(&self) -> Option<(MessageData, MessageObject)> {
+        let user_ws = self.user_ws();
+        let mut pending_message = user_ws.imp().pending_message.borrow_mut();
+        if pending_message
+            .as_ref()
+            .is_some_and(|(message_data, _)| message_data.to_user == self.user_id())
+        {
+            pending_message.take()
+        } else {
+            None
+        }
+    }
+
+    /// Gives the pending message of this chat the number from its receipt.
+    /// Returns whether the receipt was for the pending message
+    pub fn handle_sent_number(&self, message_number: u64, status: MessageStatus) -> bool {
+        // Read receipts and receipts of known messages are never for the pending message
+        if status == MessageStatus::Read || self.find_message(message_number).is_some() {
+            return false;
+        }
+
+        let Some((_, message)) = self.take_pending_message() else {
+            return false;
+        };
+
+        message.set_message_number(message_number);
+        if message_number > self.message_number() {
+            self.set_message_number(message_number);
+        }
+        true
+    }
+
+    /// Removes the pending message of this chat after the server refused it.
+    /// Returns whether this chat had the pending message
+    pub fn drop_pending_message(&self) -> bool {
+        let Some((_, message)) = self.take_pending_message() else {
+            return false;
+        };
+
+        let position = self
+            .messages()
+            .iter::<MessageObject>()
+            .position(|message_data| message_data.is_ok_and(|content| content == message));
+        if let Some(index) = position {
+            self.messages().remove(index as u32);
+        }
+        true
+    }
+
+    /// Updates the status of every message sent to this user up to the message number
+    pub fn update_message_status(&self, up_to: u64, status: MessageStatus) {
+        for message_data in self.messages().iter::<MessageObject>() {
+            let message_content = message_data.unwrap();
+            // Messages that are still in the queue do not have a number yet
+            let Some(number) = message_content.imp().message_number.get().copied() else {
+                continue;
+            };
+
+            if message_content.is_send() && number <= up_to {
+                message_content.update_status(status);
+            }
+        }
+    }
+
+    /// Shows or hides that the user is typing. Hidden automatically if the user stops sending updates
+    pub fn show_typing(&self, typing: bool) {
+        if let Some(source) = self.imp().typing_timer.take() {
+            source.remove();
+        }
+        self.set_typing(typing);
+
+        if typing {
+            let source = timeout_add_local_once(
+                TYPING_DISPLAY_TIMEOUT,
+                clone!(@weak self as user_object => move || {
+                    user_object.imp().typing_timer.take();
+                    user_object.set_typing(false);
+                }),
+            );
+            self.imp().typing_timer.replace(Some(source));
+        }
+    }
+
+    /// Shows the presence of the user. The last seen time is shown in the local time zone
+    pub fn update_presence(&self, presence: Presence) {
+        let (status, last_seen) = match presence.status {
+            PresenceStatus::Online => ("online", String::from("Online")),
+            PresenceStatus::Away => ("away", String::from("Away")),
+            PresenceStatus::Offline => {
+                let last_seen = presence
+                    .last_seen
+                    .and_then(|time| DateTime::parse_from_rfc3339(&time).ok())
+                    .map(|time| {
+                        let local_time = time.with_timezone(&Local);
+                        format!("Last seen {}", local_time.format("%Y-%m-%d %H:%M"))
+                    })
+                    .unwrap_or_else(|| String::from("Offline"));
+                ("offline", last_seen)
+            }
+        };
+
+        self.set_presence(String::from(status));
+        self.set_last_seen(last_seen);
+    }
+
+    /// The data of this user that is sent to the WS or saved locally
+    pub fn to_user_data(&self) -> FullUserData {
+        FullUserData::new(self.user_id(), self.name(), self.image_link())
+    }
+
+    /// Starts the first request of the user once the WS connection is available
+    pub fn handle_ws(&self) {
         let user_object = self.clone();
         let user_ws = self.user_ws();
 
-        user_ws.connect_closure(
+        let id = user_ws.connect_closure(
             "ws-success",
             false,
-            closure_local!(move |_from: WSObject, _success: bool| {
-                let (sender, receiver) = MainContext::channel(Priority::DEFAULT);
-                user_object.start_listening(sender.clone(), window.clone());
-                window.handle_ws_message(&user_object, receiver);
+            closure_local!(move |from: WSObject, _success: bool| {
+                if !from.is_reconnecting() {
+                    user_object.start_connection();
+                }
             }),
         );
+        self.imp().ws_signal_ids.borrow_mut().push(id);
+
+        // Users added after the connection was made will never get the signal
+        if user_ws.ws_conn().is_some() {
+            self.start_connection();
+        }
     }
 
-    fn start_listening(&self, sender: Sender<String>, window: Window) {
-        let user_ws = self.user_ws();
+    fn start_connection(&self) {
         info!(
-            "Starting listening for user {} with {}",
+            "Starting connection for user {} with {}",
             self.name(),
             self.user_id()
         );
-        if !user_ws.is_reconnecting() {
-            if self.user_id() == 0 {
-                self.add_queue_to_first(RequestType::CreateNewUser);
-            } else {
-                self.add_queue_to_first(RequestType::ReconnectUser);
-            }
+        if self.user_id() == 0 {
+            self.add_queue_to_first(RequestType::CreateNewUser);
+        } else {
+            self.add_queue_to_first(RequestType::ReconnectUser);
         }
+    }
 
-        let id = user_ws.ws_conn().unwrap().connect_message(
-            clone!(@weak self as user_object => move |_ws, _s, bytes| {
-                let byte_slice = bytes.to_vec();
-                let text = String::from_utf8(byte_slice).unwrap();
-                debug!("{} {} Received from WS: {text}", user_object.name(), user_object.user_id());
-
-                if text.starts_with('/') {
-                    let splitted_data: Vec<&str> = text.splitn(2, ' ').collect();
-                    match splitted_data[0] {
-                        "/reconnect-success" => {
-                            let user_data = FullUserData::from_json(splitted_data[1]);
-                            user_object.set_name(user_data.user_name);
-                            user_object.check_image_link(user_data.image_link);
-                            user_object.add_queue_to_first(RequestType::GetLastMessageNumber(user_object.clone()))
-                        }
-                        "/update-user-id" => {
-                            let id_data = UserIDs::from_json(splitted_data[1]);
-                            user_object.set_user_id(id_data.user_id);
-                            user_object.set_user_token(id_data.user_token);
-                            sender.send(text).unwrap();
-                            user_object.process_queue(None);
-                        }
-                        "/image-updated" => {
-                            let image_data = ImageUpdate::new_from_json(splitted_data[1]);
-                            user_object.check_image_link(image_data.image_link);
-                        }
-                        "/name-updated" => user_object.set_name(splitted_data[1]),
-                        "/message-number" => {
-                            let message_number = splitted_data[1].parse::<u64>().unwrap();
-                            info!(
-                                "Current message_number number {}, gotten number {}",
-                                user_object.message_number(),
-                                message_number
-                            );
-                            if message_number > user_object.message_number() {
-                                user_object.add_to_queue(RequestType::SyncMessage(
-                                    user_object.message_number(),
-                                    message_number,
-                                ));
-                                user_object.set_message_number(message_number);
-                            }
-                            user_object.process_queue(None);
-                        }
-                        "/sync-message" => {
-                            let chat_data = MessageSyncData::from_json(splitted_data[1]);
+    /// Stops listening to the shared WS connection. Used when the user gets deleted
+    pub fn disconnect_ws(&self) {
+        let user_ws = self.user_ws();
+        for id in self.imp().ws_signal_ids.take() {
+            user_ws.disconnect(id);
+        }
+    }
 
-                            for message in chat_data.message_data.into_iter() {
-                                window.receive_message(message, user_object.clone(), false)
-                            }
-                        }
-                        "/delete-message" => {
-                            let deletion_data = DeleteMessage::from_json(splitted_data[1]);
-                            user_object.remove_message(deletion_data.message_number)
-                        }
-                        "/message" | "/get-user-data" | "/new-user-message" => sender.send(text).unwrap(),
-                        _ => {}
-                    }
-                }
-            }),
+    /// Updates the message number with the last number on the server and syncs the missing messages
+    pub fn handle_message_number(&self, message_number: u64) {
+        info!(
+            "Current message_number number {}, gotten number {}",
+            self.message_number(),
+            message_number
         );
-        self.user_ws().set_signal_id(id);
+        if message_number > self.message_number() {
+            self.add_to_queue(RequestType::SyncMessage(
+                self.message_number(),
+                message_number,
+            ));
+            self.set_message_number(message_number);
+        }
+        self.process_queue(None);
+    }
+
+    /// Saves the User ID and token the server has given to a newly created user
+    pub fn handle_new_id(&self, id_data: UserIDs) {
+        self.set_user_id(id_data.user_id);
+        self.set_user_token(id_data.user_token);
+        self.set_owner_id(id_data.user_id);
+        self.process_queue(None);
     }
 }
 
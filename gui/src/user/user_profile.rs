@@ -21,12 +21,20 @@ mod imp {
         #[template_child]
         pub name_copy: TemplateChild<Button>,
         #[template_child]
+        pub last_seen_row: TemplateChild<ActionRow>,
+        #[template_child]
         pub id_row: TemplateChild<ActionRow>,
         #[template_child]
         pub id_warning: TemplateChild<Image>,
         #[template_child]
         pub id_copy: TemplateChild<Button>,
         #[template_child]
+        pub token_rotate: TemplateChild<Button>,
+        #[template_child]
+        pub device_pair: TemplateChild<Button>,
+        #[template_child]
+        pub device_join: TemplateChild<Button>,
+        #[template_child]
         pub image_link_row: TemplateChild<ActionRow>,
         #[template_child]
         pub image_link_copy: TemplateChild<Button>,
@@ -44,6 +52,14 @@ mod imp {
         pub conn_timer: TemplateChild<Label>,
         #[template_child]
         pub conn_reload: TemplateChild<Button>,
+        #[template_child]
+        pub read_receipts_row: TemplateChild<ActionRow>,
+        #[template_child]
+        pub read_receipts_switch: TemplateChild<Switch>,
+        #[template_child]
+        pub last_seen_privacy_row: TemplateChild<ActionRow>,
+        #[template_child]
+        pub last_seen_switch: TemplateChild<Switch>,
         pub user_data: OnceCell<UserObject>,
         pub bindings: RefCell<Vec<Binding>>,
     }
@@ -77,6 +93,7 @@ use std::env;
 use adw::prelude::*;
 use adw::subclass::prelude::*;
 use adw::Toast;
+use gio::Settings;
 use glib::closure_local;
 use glib::{clone, timeout_add_seconds_local_once, wrapper, Object};
 use gtk::{
@@ -88,6 +105,7 @@ use tracing::info;
 use crate::user::{UserObject, UserPrompt};
 use crate::window;
 use crate::ws::RequestType;
+use crate::APP_ID;
 
 wrapper! {
     pub struct UserProfile(ObjectSubclass<imp::UserProfile>)
@@ -145,6 +163,7 @@ impl UserProfile {
         let profile_avatar = self.imp().profile_avatar.get();
         let name_row = self.imp().name_row.get();
         let id_row = self.imp().id_row.get();
+        let last_seen_row = self.imp().last_seen_row.get();
         let image_link_row = self.imp().image_link_row.get();
         let id_warning = self.imp().id_warning.get();
         let user_data = self.imp().user_data.get().unwrap();
@@ -169,6 +188,17 @@ impl UserProfile {
             .sync_create()
             .build();
 
+        let last_seen_subtitle_binding = user_data
+            .bind_property("last-seen", &last_seen_row, "subtitle")
+            .sync_create()
+            .build();
+
+        let last_seen_visible_binding = user_data
+            .bind_property("last-seen", &last_seen_row, "visible")
+            .transform_to(|_, last_seen: String| Some((!last_seen.is_empty()).to_value()))
+            .sync_create()
+            .build();
+
         let id_subtitle_binding = user_data
             .bind_property("user-id", &id_row, "subtitle")
             .sync_create()
@@ -263,6 +293,8 @@ impl UserProfile {
         bindings.push(avatar_text_binding);
         bindings.push(avatar_image_binding);
         bindings.push(name_subtitle_binding);
+        bindings.push(last_seen_subtitle_binding);
+        bindings.push(last_seen_visible_binding);
         bindings.push(id_subtitle_binding);
         bindings.push(id_warning_binding);
         bindings.push(image_link_subtitle_binding);
@@ -272,6 +304,23 @@ impl UserProfile {
         bindings.push(conn_reload_binding);
         bindings.push(conn_timer_label_binding);
         bindings.push(conn_timer_visible_binding);
+
+        // Saved right away when toggled
+        let settings = Settings::new(APP_ID);
+        settings
+            .bind(
+                "send-read-receipts",
+                &self.imp().read_receipts_switch.get(),
+                "active",
+            )
+            .build();
+        settings
+            .bind(
+                "show-last-seen",
+                &self.imp().last_seen_switch.get(),
+                "active",
+            )
+            .build();
     }
 
     fn hide_editing_buttons(&self) {
@@ -279,7 +328,12 @@ impl UserProfile {
         self.imp().image_link_edit.set_visible(false);
         self.imp().image_link_reload.set_visible(false);
         self.imp().image_link_delete.set_visible(false);
+        self.imp().token_rotate.set_visible(false);
+        self.imp().device_pair.set_visible(false);
+        self.imp().device_join.set_visible(false);
         self.imp().conn_row.set_visible(false);
+        self.imp().read_receipts_row.set_visible(false);
+        self.imp().last_seen_privacy_row.set_visible(false);
 
         let user_data = self.imp().user_data.get().unwrap();
         user_data
@@ -293,6 +347,9 @@ impl UserProfile {
         let name_edit = self.imp().name_edit.get();
         let image_link_edit = self.imp().image_link_edit.get();
         let id_copy = self.imp().id_copy.get();
+        let token_rotate = self.imp().token_rotate.get();
+        let device_pair = self.imp().device_pair.get();
+        let device_join = self.imp().device_join.get();
         let image_link_copy = self.imp().image_link_copy.get();
         let image_link_reload = self.imp().image_link_reload.get();
         let image_link_delete = self.imp().image_link_delete.get();
@@ -327,6 +384,34 @@ impl UserProfile {
             toast_overlay.add_toast(toast);
         }));
 
+        token_rotate.connect_clicked(clone!(@weak self as profile => move |_| {
+            info!("Requesting a new user token");
+
+            let user_data = profile.imp().user_data.get().unwrap();
+            user_data.add_to_queue(RequestType::RotateToken);
+
+            let toast_overlay = profile.imp().toast_overlay.get();
+            let toast = Toast::builder()
+                .title("Rotating user token...")
+                .timeout(1)
+                .build();
+            toast_overlay.add_toast(toast);
+        }));
+
+        device_pair.connect_clicked(clone!(@weak self as profile => move |_| {
+            info!("Requesting a device pairing code");
+
+            let user_data = profile.imp().user_data.get().unwrap();
+            user_data.add_to_queue(RequestType::CreatePairingCode);
+        }));
+
+        device_join.connect_clicked(clone!(@weak self as profile => move |_| {
+            info!("Opening prompt to get a pairing code");
+            let user_data = profile.imp().user_data.get().unwrap();
+            let prompt = UserPrompt::new("Pair").pair_device(&profile, user_data);
+            prompt.present();
+        }));
+
         image_link_copy.connect_clicked(clone!(@weak self as profile => move |_| {
             let text = profile.imp().image_link_row.get().subtitle().unwrap();
             info!("Copying Image Link {text} to clipboard.");
@@ -49,6 +49,7 @@ mod imp {
 use adw::prelude::*;
 use adw::subclass::prelude::*;
 use adw::Toast;
+use chirp_protocol::ContentLimits;
 use gio::glib::closure_local;
 use glib::{clone, wrapper, Object};
 use gtk::{
@@ -56,6 +57,7 @@ use gtk::{
 };
 use tracing::{error, info};
 
+use crate::message::MessageObject;
 use crate::user::{UserObject, UserProfile};
 use crate::window;
 use crate::ws::RequestType;
@@ -109,6 +111,37 @@ impl UserPrompt {
             }));
     }
 
+    /// Bind the GtkEntry to only accept text that passes the same content check as the server
+    fn bind_content(&self, validate: fn(&ContentLimits, &str) -> Result<(), String>) {
+        self.imp()
+            .user_entry
+            .connect_changed(clone!(@weak self as prompt => move |entry| {
+                let entry_text = entry.text();
+                let result = validate(&ContentLimits::default(), entry_text.trim());
+
+                prompt.imp().confirm_button.set_sensitive(result.is_ok());
+
+                match result {
+                    Ok(_) => {
+                        entry.remove_css_class("error");
+                        entry.add_css_class("blue-entry");
+                        prompt.imp().error_text.set_label(&String::new());
+                    }
+                    Err(e) => {
+                        entry.remove_css_class("blue-entry");
+                        entry.add_css_class("error");
+                        // No need to tell that an empty entry is empty
+                        let error_text = if entry_text.is_empty() {
+                            String::new()
+                        } else {
+                            format!("Error: {}", e)
+                        };
+                        prompt.imp().error_text.set_label(&error_text);
+                    }
+                }
+            }));
+    }
+
     /// Bind the GtkEntry to ensure input is u64 parsable
     fn bind_int(&self) {
         self.imp()
@@ -178,7 +211,7 @@ impl UserPrompt {
 
     /// Open prompt to take a new name for the user
     pub fn edit_name(self, profile: &UserProfile, user_data: &UserObject) -> Self {
-        self.bind();
+        self.bind_content(ContentLimits::validate_user_name);
         self.set_transient_for(Some(profile));
         self.set_modal(true);
 
@@ -190,7 +223,7 @@ impl UserPrompt {
 
         self.imp().confirm_button.connect_clicked(
             clone!(@weak self as prompt, @weak profile, @weak user_data => move |_| {
-                let entry_data = prompt.imp().user_entry.text();
+                let entry_data = prompt.imp().user_entry.text().trim().to_string();
                 info!("Updating name to: {}", entry_data);
                 let over_lay = profile.imp().toast_overlay.get();
                 let toast = Toast::builder()
@@ -198,7 +231,7 @@ impl UserPrompt {
                     .timeout(1)
                     .build();
                 over_lay.add_toast(toast);
-                user_data.add_to_queue(RequestType::NameUpdated(entry_data.to_string()));
+                user_data.add_to_queue(RequestType::NameUpdated(entry_data));
                 prompt.destroy()
             }),
         );
@@ -236,7 +269,7 @@ impl UserPrompt {
             );
         }
 
-        self.bind();
+        self.bind_content(ContentLimits::validate_image_link);
         self.set_transient_for(Some(profile));
         self.set_modal(true);
 
@@ -250,7 +283,7 @@ impl UserPrompt {
 
         self.imp().confirm_button.connect_clicked(
             clone!(@weak self as prompt, @weak profile, @weak user_data => move |_| {
-                let entry_data = prompt.imp().user_entry.text();
+                let entry_data = prompt.imp().user_entry.text().trim().to_string();
                 info!("Updating image link to: {}", entry_data);
                 let over_lay = profile.imp().toast_overlay.get();
                 let toast = Toast::builder()
@@ -258,7 +291,7 @@ impl UserPrompt {
                     .timeout(1)
                     .build();
                 over_lay.add_toast(toast);
-                user_data.add_to_queue(RequestType::ImageUpdated(Some(entry_data.to_string())));
+                user_data.add_to_queue(RequestType::ImageUpdated(Some(entry_data)));
                 prompt.imp().loading_spinner.set_spinning(true);
                 prompt.set_buttons_insensitive();
             }),
@@ -267,6 +300,73 @@ impl UserPrompt {
         self
     }
 
+    /// Open prompt to take the new text of a sent message
+    pub fn edit_message(self, window: &window::Window, message_data: &MessageObject) -> Self {
+        self.bind_content(ContentLimits::validate_message);
+        self.set_transient_for(Some(window));
+        self.set_modal(true);
+
+        self.imp()
+            .user_entry
+            .get()
+            .set_placeholder_text(Some("Message"));
+        self.imp().user_entry.set_text(&message_data.message());
+        self.imp().prompt_text.set_label("Enter the new message");
+
+        self.imp().confirm_button.connect_clicked(
+            clone!(@weak self as prompt, @weak message_data => move |_| {
+                let entry_data = prompt.imp().user_entry.text().trim().to_string();
+
+                if entry_data != message_data.message() {
+                    info!("Editing message to: {}", entry_data);
+                    let other_user = message_data.other_user();
+                    message_data.set_message(entry_data.to_owned());
+                    message_data.set_edited(true);
+                    other_user.add_to_queue(RequestType::EditMessage(
+                        other_user.user_id(),
+                        message_data.message_number(),
+                        entry_data,
+                    ));
+                }
+                prompt.destroy()
+            }),
+        );
+
+        self
+    }
+
+    /// Open prompt to take a pairing code generated by another device of an existing user
+    pub fn pair_device(self, profile: &UserProfile, user_data: &UserObject) -> Self {
+        self.bind();
+        self.set_transient_for(Some(profile));
+        self.set_modal(true);
+
+        self.imp()
+            .user_entry
+            .get()
+            .set_placeholder_text(Some("Pairing Code"));
+        self.imp()
+            .prompt_text
+            .set_label("Enter the pairing code shown on your other device");
+
+        self.imp().confirm_button.connect_clicked(
+            clone!(@weak self as prompt, @weak profile, @weak user_data => move |_| {
+                let entry_data = prompt.imp().user_entry.text().trim().to_uppercase();
+                info!("Pairing device with code: {}", entry_data);
+                let over_lay = profile.imp().toast_overlay.get();
+                let toast = Toast::builder()
+                    .title("Pairing device...")
+                    .timeout(1)
+                    .build();
+                over_lay.add_toast(toast);
+                user_data.add_to_queue(RequestType::PairDevice(entry_data));
+                prompt.destroy()
+            }),
+        );
+
+        self
+    }
+
     fn set_buttons_insensitive(&self) {
         self.imp().confirm_button.set_sensitive(false);
         self.imp().cancel_button.set_sensitive(false);
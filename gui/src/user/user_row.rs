@@ -20,6 +20,8 @@ mod imp {
         pub popover_label: TemplateChild<Label>,
         #[template_child]
         pub user_menu: TemplateChild<PopoverMenu>,
+        #[template_child]
+        pub presence_dot: TemplateChild<Box>,
         pub popover_visible: Cell<bool>,
         pub bindings: RefCell<Vec<Binding>>,
         pub user_data: OnceCell<UserObject>,
@@ -149,6 +151,7 @@ impl UserRow {
     pub fn bind(&self) {
         let mut bindings = self.imp().bindings.borrow_mut();
         let user_avatar = self.imp().user_avatar.get();
+        let presence_dot = self.imp().presence_dot.get();
 
         let user_object = self.imp().user_data.get().unwrap();
 
@@ -161,9 +164,35 @@ impl UserRow {
             .bind_property("small-image", &user_avatar, "custom-image")
             .sync_create()
             .build();
+        // The presence is empty until the server sends it
+        let presence_class_binding = user_object
+            .bind_property("presence", &presence_dot, "css-classes")
+            .transform_to(|_, presence: String| {
+                Some(vec![
+                    String::from("presence-dot"),
+                    format!("presence-{presence}"),
+                ])
+            })
+            .sync_create()
+            .build();
+
+        let presence_visible_binding = user_object
+            .bind_property("presence", &presence_dot, "visible")
+            .transform_to(|_, presence: String| Some(!presence.is_empty()))
+            .sync_create()
+            .build();
+
+        let presence_tooltip_binding = user_object
+            .bind_property("last-seen", &presence_dot, "tooltip-text")
+            .sync_create()
+            .build();
+
         bindings.push(avatar_image_binding);
 
         bindings.push(avatar_text_binding);
+        bindings.push(presence_class_binding);
+        bindings.push(presence_visible_binding);
+        bindings.push(presence_tooltip_binding);
     }
 
     fn view_profile(&self) {
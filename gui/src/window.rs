@@ -1,17 +1,20 @@
 mod imp {
     use adw::subclass::prelude::*;
-    use adw::ApplicationWindow;
+    use adw::{ApplicationWindow, ToastOverlay};
     use gio::{ListStore, Settings};
     use glib::subclass::InitializingObject;
-    use glib::{object_subclass, Binding, Propagation};
+    use glib::{object_subclass, Binding, Propagation, SourceId};
     use gtk::{
         gio, glib, Button, CompositeTemplate, EmojiChooser, Label, ListBox, Revealer,
         ScrolledWindow, Stack, TextView,
     };
     use std::cell::{Cell, OnceCell, RefCell};
     use std::rc::Rc;
+    use std::time::Instant;
 
+    use crate::message::MessageObject;
     use crate::user::UserObject;
+    use crate::ws::WSObject;
 
     #[derive(CompositeTemplate, Default)]
     #[template(resource = "/com/github/therustypickle/chirp/window.xml")]
@@ -27,6 +30,8 @@ mod imp {
         #[template_child]
         pub user_list: TemplateChild<ListBox>,
         #[template_child]
+        pub toast_overlay: TemplateChild<ToastOverlay>,
+        #[template_child]
         pub stack: TemplateChild<Stack>,
         #[template_child]
         pub my_profile: TemplateChild<Button>,
@@ -40,12 +45,31 @@ mod imp {
         pub emoji_button: TemplateChild<Button>,
         #[template_child]
         pub emoji_chooser: TemplateChild<EmojiChooser>,
+        #[template_child]
+        pub typing_revealer: TemplateChild<Revealer>,
+        #[template_child]
+        pub typing_label: TemplateChild<Label>,
+        #[template_child]
+        pub reply_revealer: TemplateChild<Revealer>,
+        #[template_child]
+        pub reply_label: TemplateChild<Label>,
+        #[template_child]
+        pub reply_cancel: TemplateChild<Button>,
         pub users: OnceCell<ListStore>,
         pub chatting_with: Rc<RefCell<Option<UserObject>>>,
         pub own_profile: Rc<RefCell<Option<UserObject>>>,
         pub last_selected_user: Cell<i32>,
         pub bindings: RefCell<Vec<Binding>>,
         pub settings: OnceCell<Settings>,
+        pub ws: OnceCell<WSObject>,
+        // The user the last typing update was sent to and when
+        pub typing_sent: RefCell<Option<(UserObject, Instant)>>,
+        pub typing_timer: RefCell<Option<SourceId>>,
+        // Whether the session was marked as away because the window was not focused
+        pub is_away: Cell<bool>,
+        pub away_timer: RefCell<Option<SourceId>>,
+        // The message the next sent message replies to
+        pub replying_to: RefCell<Option<MessageObject>>,
     }
 
     #[object_subclass]
@@ -90,26 +114,37 @@ mod imp {
 }
 
 use adw::subclass::prelude::*;
-use adw::{prelude::*, Application};
+use adw::{prelude::*, Application, MessageDialog, ResponseAppearance, Toast};
+use chirp_protocol::{
+    ContentLimits, ErrorData, FullUserData, MessageData, MessageHistory, PresenceStatus,
+    ServerEvent, UserIDs,
+};
 use chrono::{Local, NaiveDateTime};
 use gio::{ActionGroup, ActionMap, ListStore, Settings, SimpleAction};
-use glib::{clone, timeout_add_local_once, wrapper, ControlFlow, Object, Receiver};
+use glib::{clone, closure_local, timeout_add_local_once, wrapper, Object};
 use gtk::{
-    gio, glib, Accessible, ApplicationWindow, Buildable, ConstraintTarget, ListBox, ListBoxRow,
-    Native, PositionType, Root, ShortcutManager, Widget,
+    gio, glib, graphene::Point, Accessible, ApplicationWindow, Buildable, ConstraintTarget,
+    ListBox, ListBoxRow, Native, PositionType, Root, ShortcutManager, Widget,
 };
 use std::fs;
 use std::fs::File;
 use std::io::{Read, Write};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tracing::{debug, error, info};
 
 use crate::message::{MessageObject, MessageRow};
 use crate::user::{UserObject, UserProfile, UserPrompt, UserRow};
 use crate::utils::generate_random_avatar_link;
-use crate::ws::{FullUserData, MessageData, RequestType, UserIDs};
+use crate::ws::{format_fingerprint, pin_certificate, RequestType, WSObject};
 use crate::APP_ID;
 
+// How often typing is sent again while the user keeps typing
+const TYPING_RESEND_INTERVAL: Duration = Duration::from_secs(3);
+// Typing is stopped if nothing is typed this long
+const TYPING_STOP_DELAY: Duration = Duration::from_secs(5);
+// The session is marked as away once the window is not focused this long
+const AWAY_DELAY: Duration = Duration::from_secs(300);
+
 wrapper! {
     pub struct Window(ObjectSubclass<imp::Window>)
         @extends adw::ApplicationWindow, ApplicationWindow, gtk::Window, Widget,
@@ -184,27 +219,51 @@ impl Window {
         }));
 
         // If the message typing space is empty, show the background text + disable the send button
-        // else remove the text and enable the send button
+        // else remove the text and enable the send button. Messages the server would reject can not be sent
+        let max_message_length = ContentLimits::default().max_message_length;
         self.imp().message_entry.get().buffer().connect_changed(
             clone!(@weak self as window => move |buffer| {
                 let char_count = buffer.char_count();
                 let should_be_enabled = char_count != 0;
-                window.imp().send_button.set_sensitive(should_be_enabled);
+                window
+                    .imp()
+                    .send_button
+                    .set_sensitive(should_be_enabled && char_count as usize <= max_message_length);
                 if should_be_enabled {
                     window.imp().placeholder.set_visible(false);
+                    window.notify_typing();
                 } else {
                     window.imp().placeholder.set_visible(true);
+                    window.stop_typing();
                 }
 
             }),
         );
 
+        // Messages of the open chat that arrived while the window was not focused are read now
+        self.connect_is_active_notify(|window| {
+            window.update_away();
+            if !window.is_active() {
+                return;
+            }
+            let chatting_with = window.imp().chatting_with.borrow().clone();
+            if let Some(user) = chatting_with {
+                window.send_read_receipt(&user);
+            }
+        });
+
         // Timeout half a second before revealing the textview
         let window = self.clone();
         timeout_add_local_once(Duration::from_millis(500), move || {
             window.imp().entry_revealer.set_reveal_child(true);
         });
 
+        self.imp()
+            .reply_cancel
+            .connect_clicked(clone!(@weak self as window => move |_| {
+                window.cancel_reply();
+            }));
+
         // Set emoji chooser to visible on click
         self.imp()
             .emoji_button
@@ -236,6 +295,16 @@ impl Window {
 
     fn setup_settings(&self) {
         let settings = Settings::new(APP_ID);
+
+        // Contacts stop seeing the last seen time right after it gets hidden
+        settings.connect_changed(
+            Some("show-last-seen"),
+            clone!(@weak self as window => move |settings, key| {
+                window
+                    .get_chatting_from()
+                    .add_to_queue(RequestType::HideLastSeen(!settings.boolean(key)));
+            }),
+        );
         self.imp().settings.set(settings).unwrap();
     }
 
@@ -255,7 +324,7 @@ impl Window {
         let saving_location = self.settings().string("location");
         info!("Saving new user id info on {}", saving_location);
         let owner_id = self.get_chatting_from();
-        let id_data = UserIDs::new_json(owner_id.user_id(), owner_id.user_token());
+        let id_data = UserIDs::new(owner_id.user_id(), owner_id.user_token()).to_json();
 
         let mut file = File::create(saving_location).unwrap();
         file.write_all(id_data.as_bytes()).unwrap();
@@ -271,7 +340,7 @@ impl Window {
                 file.read_to_string(&mut file_contents)
                     .expect("Failed to read file");
 
-                let id_data = UserIDs::from_json(&file_contents);
+                let id_data = UserIDs::from_json(&file_contents).unwrap();
                 return Some(id_data);
             }
         }
@@ -303,7 +372,7 @@ impl Window {
                 user_object.name(),
                 user_object.image_link()
             );
-            let user_data = FullUserData::new(&user_object).empty_token();
+            let user_data = user_object.to_user_data();
             save_list.push(user_data)
         }
 
@@ -311,7 +380,7 @@ impl Window {
         self.settings().set_string("users", &to_save).unwrap();
     }
 
-    /// Bind the main window header bar's title to the selected chat
+    /// Bind the main window header bar's title and the typing status to the selected chat
     fn bind(&self) {
         let mut bindings = self.imp().bindings.borrow_mut();
         let chatting_with = self.get_chatting_with();
@@ -320,12 +389,26 @@ impl Window {
             .transform_to(|_, name: String| Some(format!("Chirp - {}", name)))
             .sync_create()
             .build();
+
+        let typing_label_binding = chatting_with
+            .bind_property("name", &self.imp().typing_label.get(), "label")
+            .transform_to(|_, name: String| Some(format!("{} is typing…", name)))
+            .sync_create()
+            .build();
+
+        let typing_binding = chatting_with
+            .bind_property("typing", &self.imp().typing_revealer.get(), "reveal-child")
+            .sync_create()
+            .build();
+
         bindings.push(title_binding);
+        bindings.push(typing_label_binding);
+        bindings.push(typing_binding);
     }
 
-    /// Disconnect the last header bar title binding
+    /// Disconnect the bindings of the last selected chat
     fn remove_last_binding(&self) {
-        if let Some(binding) = self.imp().bindings.borrow_mut().pop() {
+        for binding in self.imp().bindings.borrow_mut().drain(..) {
             binding.unbind();
         }
     }
@@ -349,6 +432,8 @@ impl Window {
             }),
         );
 
+        self.setup_ws();
+
         info!("Setting own profile");
         let saved_user_id = self.check_owner_id();
         let data: UserObject = self.create_owner(saved_user_id.clone());
@@ -380,11 +465,139 @@ impl Window {
             data.check_image_link(owner_data.image_link);
 
             for user_data in saved_users {
-                self.create_user(user_data)
+                self.create_user(user_data);
             }
         }
     }
 
+    /// Create the WS connection that is shared by every UserObject
+    fn setup_ws(&self) {
+        let ws = WSObject::new();
+        let window = self.clone();
+
+        // A new connection is created on every reconnection so the listener is set up every time
+        ws.connect_closure(
+            "ws-success",
+            false,
+            closure_local!(move |from: WSObject, _success: bool| {
+                window.start_listening(&from);
+            }),
+        );
+        let window = self.clone();
+        ws.connect_closure(
+            "certificate-unknown",
+            false,
+            closure_local!(move |from: WSObject, host: String, fingerprint: String| {
+                window.ask_certificate_trust(&from, host, fingerprint);
+            }),
+        );
+        let window = self.clone();
+        ws.connect_closure(
+            "certificate-changed",
+            false,
+            closure_local!(move |_from: WSObject, host: String, fingerprint: String| {
+                let body = format!(
+                    "The certificate of {} does not match the one that was trusted before. \
+                    Someone may be intercepting the connection so it has been refused.\n\n\
+                    New fingerprint:\n{}",
+                    host,
+                    format_fingerprint(&fingerprint)
+                );
+                window.show_certificate_error("Server Certificate Changed", &body);
+            }),
+        );
+        let window = self.clone();
+        ws.connect_closure(
+            "certificate-invalid",
+            false,
+            closure_local!(move |_from: WSObject, host: String, reason: String| {
+                let body = format!(
+                    "The certificate of {} could not be verified ({}) so the connection has been refused.",
+                    host, reason
+                );
+                window.show_certificate_error("Invalid Server Certificate", &body);
+            }),
+        );
+        self.imp().ws.set(ws).unwrap();
+    }
+
+    /// Asks the user whether a self-signed certificate that was not seen before should be trusted
+    fn ask_certificate_trust(&self, ws: &WSObject, host: String, fingerprint: String) {
+        let body = format!(
+            "The certificate of {} is not signed by a trusted authority. \
+            Only trust it if the fingerprint matches the one of the server.\n\n\
+            Fingerprint:\n{}",
+            host,
+            format_fingerprint(&fingerprint)
+        );
+
+        let dialog = MessageDialog::builder()
+            .transient_for(self)
+            .modal(true)
+            .heading("Unknown Server Certificate")
+            .body(body)
+            .build();
+        dialog.add_responses(&[("cancel", "Cancel"), ("trust", "Trust")]);
+        dialog.set_response_appearance("trust", ResponseAppearance::Suggested);
+        dialog.set_default_response(Some("cancel"));
+        dialog.set_close_response("cancel");
+
+        dialog.connect_response(
+            None,
+            clone!(@weak ws => move |_, response| {
+                if response == "trust" {
+                    pin_certificate(&host, &fingerprint);
+                    ws.clear_certificate_warning();
+                    ws.reload_manually();
+                }
+            }),
+        );
+        dialog.present();
+    }
+
+    /// Shows why the server certificate was refused
+    fn show_certificate_error(&self, heading: &str, body: &str) {
+        let dialog = MessageDialog::builder()
+            .transient_for(self)
+            .modal(true)
+            .heading(heading)
+            .body(body)
+            .build();
+        dialog.add_response("close", "Close");
+        dialog.present();
+    }
+
+    /// Shows every earlier version of an edited message along with the current one
+    fn show_message_history(&self, history: MessageHistory) {
+        let Some(message_object) = self
+            .find_user(history.user_id)
+            .and_then(|user_object| user_object.find_message(history.message_number))
+        else {
+            return;
+        };
+
+        let mut body: Vec<String> = history
+            .revisions
+            .into_iter()
+            .map(|revision| format!("{} UTC\n{}", revision.created_at, revision.message))
+            .collect();
+        body.push(format!("Current\n{}", message_object.message()));
+
+        let dialog = MessageDialog::builder()
+            .transient_for(self)
+            .modal(true)
+            .heading("Edit History")
+            .body(body.join("\n\n"))
+            .build();
+        dialog.add_response("close", "Close");
+        dialog.present();
+    }
+
+    /// Get the WS connection of the client
+    pub fn get_ws(&self) -> WSObject {
+        self.imp().ws.get().unwrap().clone()
+    }
+
     /// Get the UserObject that is currently selected/chatting with
     pub fn get_chatting_with(&self) -> UserObject {
         self.imp().chatting_with.borrow().clone().unwrap()
@@ -393,6 +606,8 @@ impl Window {
     /// Set chatting with the given user
     fn set_chatting_with(&self, user: UserObject) {
         info!("Setting chatting with {}", user.name());
+        self.stop_typing();
+        self.cancel_reply();
         user.add_queue_to_first(RequestType::GetLastMessageNumber(user.clone()));
 
         // Bind the model to a specific ListStore so if any new message gets added there
@@ -407,7 +622,148 @@ impl Window {
                 row.upcast()
             }),
         );
-        self.imp().chatting_with.replace(Some(user));
+        self.imp().chatting_with.replace(Some(user.clone()));
+        self.send_read_receipt(&user);
+    }
+
+    /// Shows the message above the entry. The next sent message replies to it
+    pub fn start_reply(&self, message: &MessageObject) {
+        self.imp().reply_label.set_label(&format!(
+            "Replying to {}: {}",
+            message.sent_from().name(),
+            message.message()
+        ));
+        self.imp().reply_revealer.set_reveal_child(true);
+        self.imp().replying_to.replace(Some(message.clone()));
+        self.grab_focus();
+    }
+
+    /// Hides the replied message. The next sent message is not a reply
+    fn cancel_reply(&self) {
+        self.imp().replying_to.take();
+        self.imp().reply_revealer.set_reveal_child(false);
+    }
+
+    /// Scrolls the message list to the row of the message
+    pub fn scroll_to_message(&self, message: &MessageObject) {
+        let Some(row) = message.target_row() else {
+            return;
+        };
+
+        let message_list = self.imp().message_list.get();
+        if let Some(point) = row.compute_point(&message_list, &Point::new(0.0, 0.0)) {
+            self.imp()
+                .message_scroller
+                .vadjustment()
+                .set_value(point.y() as f64);
+        }
+    }
+
+    /// Tells the selected user that the owner is typing. Sent again every few seconds while typing
+    /// continues and stopped once nothing is typed for a while
+    fn notify_typing(&self) {
+        let Some(user) = self.imp().chatting_with.borrow().clone() else {
+            return;
+        };
+
+        // Nobody to tell when chatting with self
+        if user.user_id() == user.owner_id() {
+            return;
+        }
+
+        let resend = self
+            .imp()
+            .typing_sent
+            .borrow()
+            .as_ref()
+            .map_or(true, |(_, sent_at)| {
+                sent_at.elapsed() >= TYPING_RESEND_INTERVAL
+            });
+
+        if resend && user.send_typing(true) {
+            self.imp().typing_sent.replace(Some((user, Instant::now())));
+        }
+
+        if let Some(source) = self.imp().typing_timer.take() {
+            source.remove();
+        }
+        let source = timeout_add_local_once(
+            TYPING_STOP_DELAY,
+            clone!(@weak self as window => move || {
+                window.imp().typing_timer.take();
+                window.stop_typing();
+            }),
+        );
+        self.imp().typing_timer.replace(Some(source));
+    }
+
+    /// Tells the user typing was last sent to that it stopped
+    fn stop_typing(&self) {
+        if let Some(source) = self.imp().typing_timer.take() {
+            source.remove();
+        }
+
+        if let Some((user, _)) = self.imp().typing_sent.take() {
+            user.send_typing(false);
+        }
+    }
+
+    /// Tells the user that the received messages were read if the chat is open and the window
+    /// is focused. Nothing is sent if read receipts are turned off in the settings
+    fn send_read_receipt(&self, user: &UserObject) {
+        let is_open = self.imp().chatting_with.borrow().as_ref() == Some(user);
+        if !is_open || !self.is_active() || !self.settings().boolean("send-read-receipts") {
+            return;
+        }
+
+        // Messages to self do not need receipts
+        let message_number = user.message_number();
+        if user.user_id() == user.owner_id() || message_number <= user.read_number() {
+            return;
+        }
+
+        user.set_read_number(message_number);
+        user.add_to_queue(RequestType::ReadMessages(message_number));
+    }
+
+    /// Marks the session as away once the window was not focused for a while and as online
+    /// again when it gets focused
+    fn update_away(&self) {
+        if let Some(source) = self.imp().away_timer.take() {
+            source.remove();
+        }
+
+        if self.is_active() {
+            if self.imp().is_away.replace(false) {
+                self.get_chatting_from()
+                    .add_to_queue(RequestType::SetPresence(PresenceStatus::Online));
+            }
+            return;
+        }
+
+        let source = timeout_add_local_once(
+            AWAY_DELAY,
+            clone!(@weak self as window => move || {
+                window.imp().away_timer.take();
+                window.imp().is_away.set(true);
+                window
+                    .get_chatting_from()
+                    .add_to_queue(RequestType::SetPresence(PresenceStatus::Away));
+            }),
+        );
+        self.imp().away_timer.replace(Some(source));
+    }
+
+    /// Sends the presence settings of this client once the session is authenticated.
+    /// The server starts every new session as online with the last seen time visible
+    fn sync_presence(&self) {
+        let owner = self.get_chatting_from();
+        let hide_last_seen = !self.settings().boolean("show-last-seen");
+        owner.add_to_queue(RequestType::HideLastSeen(hide_last_seen));
+
+        if self.imp().is_away.get() {
+            owner.add_to_queue(RequestType::SetPresence(PresenceStatus::Away));
+        }
     }
 
     /// Get the UserObject of the owner/chatting from
@@ -443,6 +799,13 @@ impl Window {
             return;
         }
 
+        if let Err(e) = ContentLimits::default().validate_message(&content) {
+            error!("Not sending the message: {}", e);
+            let toast = Toast::builder().title(e).timeout(2).build();
+            self.imp().toast_overlay.add_toast(toast);
+            return;
+        }
+
         let sender = self.get_chatting_from();
         let receiver = self.get_chatting_with();
 
@@ -461,9 +824,17 @@ impl Window {
             None,
         );
 
-        let send_message_data =
+        let mut send_message_data =
             MessageData::new_incomplete(created_at, self.get_owner_id(), receiver_id, content);
 
+        if let Some(target) = self.imp().replying_to.take() {
+            let reply_to = target.message_number();
+            send_message_data.reply_to = Some(reply_to);
+            message.set_reply_to(reply_to);
+            message.set_reply_text(target.message());
+            self.imp().reply_revealer.set_reveal_child(false);
+        }
+
         // Receiver gets the queue because the receiver itself saves the message number variable
         // if it was sender, it would send the message number of owner_id@owner_id group which is invalid
         receiver.add_to_queue(RequestType::SendMessage(send_message_data, message.clone()));
@@ -481,6 +852,10 @@ impl Window {
         add_css: bool,
     ) {
         let current_message_number = other_user.message_number();
+        let status = message_data.status;
+        let edited = message_data.edited_at.is_some();
+        let reply_to = message_data.reply_to;
+        let reactions = message_data.reactions;
         if current_message_number < message_data.message_number {
             // Less than current number means it's an old message
             other_user.set_message_number(other_user.message_number() + 1);
@@ -509,6 +884,17 @@ impl Window {
             Some(message_data.message_number),
         );
 
+        message.set_edited(edited);
+
+        if let Some(reply_to) = reply_to {
+            message.set_reply_to(reply_to);
+        }
+        message.imp().reactions.replace(reactions);
+
+        if is_send {
+            message.update_status(status);
+        }
+
         // Sync messages are gotten in reverse order so they are pushed to the start
         if current_message_number > message_data.message_number {
             other_user.messages().insert(0, &message);
@@ -516,6 +902,8 @@ impl Window {
             other_user.messages().append(&message);
         }
 
+        other_user.show_reply(&message);
+
         // Pending message color should not be added when syncing messages
         if add_css {
             let target_user = if is_send { receiver } else { sender };
@@ -557,63 +945,279 @@ impl Window {
     fn create_owner(&self, id_data: Option<UserIDs>) -> UserObject {
         let user_data = if let Some(data) = id_data {
             info!("Saved user data found");
-            UserObject::new("Me", None, None, Some(data.user_id), Some(data.user_token))
+            UserObject::new(
+                "Me",
+                None,
+                None,
+                Some(data.user_id),
+                Some(data.user_token),
+                &self.get_ws(),
+            )
         } else {
-            UserObject::new("Me", Some(generate_random_avatar_link()), None, None, None)
+            UserObject::new(
+                "Me",
+                Some(generate_random_avatar_link()),
+                None,
+                None,
+                None,
+                &self.get_ws(),
+            )
         };
 
-        user_data.handle_ws(self.clone());
+        user_data.handle_ws();
         self.get_users_liststore().append(&user_data);
 
         user_data
     }
 
-    /// Function that handles some WS requests. Every added UserObject will have an instance of this to
-    /// process their requests.
-    pub fn handle_ws_message(&self, user: &UserObject, receiver: Receiver<String>) {
-        receiver.attach(None, clone!(@weak user as user_object, @weak self as window => @default-return ControlFlow::Break, move |response| {
-            let response_data: Vec<&str> = response.splitn(2, ' ').collect();
-            match response_data[0] {
-                "/get-user-data" | "/new-user-message" => {
-                    let user_data = FullUserData::from_json(response_data[1]);
-
-                    if response_data[0] == "/get-user-data" {
-                        if user_data.user_id != 0 {
-                            user_object.emit_by_name::<()>("user-exists", &[&true]);
-                        } else {
-                            user_object.emit_by_name::<()>("user-exists", &[&false]);
-                            return ControlFlow::Continue;
-                        }
-                    }
+    /// Listen to every message received on the WS connection
+    fn start_listening(&self, ws: &WSObject) {
+        info!("Starting listening to the WS connection");
+        ws.ws_conn().unwrap().connect_message(
+            clone!(@weak self as window => move |_ws, _s, bytes| {
+                let byte_slice = bytes.to_vec();
+                let text = String::from_utf8(byte_slice).unwrap();
+                debug!("Received from WS: {text}");
+
+                match ServerEvent::from_json(&text) {
+                    Ok(event) => window.handle_ws_message(event),
+                    Err(e) => error!("Failed to parse WS event: {e}"),
+                }
+            }),
+        );
+    }
 
-                    if window.find_user(user_data.user_id).is_some() {
-                        info!("User {} has already been added. Dismissing the request", user_data.user_id);
-                        return ControlFlow::Continue;
+    /// Processes an event received from the WS and passes it to the relevant UserObject
+    fn handle_ws_message(&self, event: ServerEvent) {
+        match event {
+            ServerEvent::UpdateUserId(id_data) => {
+                self.get_chatting_from().handle_new_id(id_data);
+                self.save_user_data();
+                self.sync_presence();
+            }
+            ServerEvent::Authenticated(user_data) | ServerEvent::ReconnectSuccess(user_data) => {
+                if let Some(user_object) = self.find_user(user_data.user_id) {
+                    user_object.set_name(user_data.user_name);
+                    user_object.check_image_link(user_data.image_link);
+                    user_object
+                        .add_queue_to_first(RequestType::GetLastMessageNumber(user_object.clone()));
+
+                    if user_object.user_id() == user_object.owner_id() {
+                        self.sync_presence();
                     }
+                }
+            }
+            ServerEvent::GetUserData(user_data) => {
+                let chatting_from = self.get_chatting_from();
+                if user_data.user_id == 0 {
+                    chatting_from.emit_by_name::<()>("user-exists", &[&false]);
+                    return;
+                }
+                chatting_from.emit_by_name::<()>("user-exists", &[&true]);
+
+                if self.find_user(user_data.user_id).is_some() {
+                    info!(
+                        "User {} has already been added. Dismissing the request",
+                        user_data.user_id
+                    );
+                    return;
+                }
+
+                self.create_user(user_data);
+            }
+            ServerEvent::Message(message_data) => {
+                let other_user_id = if message_data.from_user == self.get_owner_id() {
+                    message_data.to_user
+                } else {
+                    message_data.from_user
+                };
 
-                    window.create_user(user_data);
+                if let Some(user_object) = self.find_user(other_user_id) {
+                    if other_user_id == message_data.from_user {
+                        user_object.show_typing(false);
+                    }
+                    self.receive_message(message_data, user_object.clone(), true);
+                    self.send_read_receipt(&user_object);
+                } else {
+                    // The profile and the message get synced once the server accepts the new contact
+                    info!("Message received from User {other_user_id} that was not added");
+                    let user_object = self.create_user(FullUserData::new(
+                        other_user_id,
+                        other_user_id.to_string(),
+                        None,
+                    ));
+                    self.add_pending_avatar_css(user_object);
+                }
+            }
+            ServerEvent::NameUpdated { user_id, new_name } => {
+                if let Some(user_object) = self.find_user(user_id) {
+                    user_object.set_name(new_name);
+                }
+            }
+            ServerEvent::ImageUpdated {
+                user_id,
+                image_link,
+            } => {
+                if let Some(user_object) = self.find_user(user_id) {
+                    user_object.check_image_link(image_link);
+                }
+            }
+            ServerEvent::MessageNumber {
+                user_id,
+                message_number,
+            } => {
+                if let Some(user_object) = self.find_user(user_id) {
+                    user_object.handle_message_number(message_number);
+                    self.send_read_receipt(&user_object);
                 }
-                "/message" => {
-                    let message_data = MessageData::from_json(response_data[1]);
-                    window.receive_message(message_data, user_object, true)
-                },
-                "/update-user-id" => {
-                    let chatting_from = window.get_chatting_from();
-                    if user_object == chatting_from {
-                        let id_data = UserIDs::from_json(response_data[1]);
-                        chatting_from.set_owner_id(id_data.user_id);
-                        window.save_user_data();
+            }
+            ServerEvent::SyncMessage {
+                user_id,
+                message_data,
+            } => {
+                if let Some(user_object) = self.find_user(user_id) {
+                    for message in message_data.into_iter() {
+                        self.receive_message(message, user_object.clone(), false)
                     }
+                    // Sync messages come newest first so replies can arrive before the message
+                    user_object.update_replies();
                 }
-                _ => {}
             }
-            ControlFlow::Continue
-        }));
+            ServerEvent::DeleteMessage(deletion_data) => {
+                if let Some(user_object) = self.find_user(deletion_data.user_id) {
+                    user_object.remove_message(deletion_data.message_number)
+                }
+            }
+            ServerEvent::EditMessage(edit_data) => {
+                if let Some(user_object) = self.find_user(edit_data.user_id) {
+                    user_object.edit_message(edit_data.message_number, edit_data.message)
+                }
+            }
+            ServerEvent::MessageHistory(history) => self.show_message_history(history),
+            ServerEvent::Reaction(update) => {
+                if let Some(user_object) = self.find_user(update.user_id) {
+                    user_object.update_reaction(
+                        update.message_number,
+                        update.reacted_by,
+                        update.emoji,
+                    );
+                }
+            }
+            ServerEvent::MessageReceipt(receipt) => {
+                if let Some(user_object) = self.find_user(receipt.user_id) {
+                    if user_object.handle_sent_number(receipt.message_number, receipt.status) {
+                        self.resume_queues();
+                    }
+                    user_object.update_message_status(receipt.message_number, receipt.status);
+                }
+            }
+            ServerEvent::Typing(update) => {
+                if let Some(user_object) = self.find_user(update.user_id) {
+                    user_object.show_typing(update.typing);
+                }
+            }
+            ServerEvent::Presence(presence) => {
+                if let Some(user_object) = self.find_user(presence.user_id) {
+                    user_object.update_presence(presence);
+                }
+            }
+            ServerEvent::TokenRotated(id_data) => {
+                info!("User token has been rotated");
+                self.get_chatting_from().set_user_token(id_data.user_token);
+                self.save_user_data();
+                let toast = Toast::builder()
+                    .title("User token has been rotated")
+                    .timeout(2)
+                    .build();
+                self.imp().toast_overlay.add_toast(toast);
+            }
+            ServerEvent::PairingCode(pairing_data) => {
+                info!("Pairing code {} has been created", pairing_data.code);
+                self.clipboard().set(&pairing_data.code);
+                let toast = Toast::builder()
+                    .title(format!(
+                        "Pairing code {} has been copied to clipboard. Valid for {} minutes",
+                        pairing_data.code,
+                        pairing_data.expires_in / 60
+                    ))
+                    .timeout(10)
+                    .build();
+                self.imp().toast_overlay.add_toast(toast);
+            }
+            ServerEvent::DevicePaired(id_data) => self.handle_device_paired(id_data),
+            ServerEvent::Error(error_data) => {
+                if error_data.command.as_deref() == Some("message") {
+                    self.drop_pending_message();
+                }
+                self.show_error(error_data)
+            }
+        }
+    }
+
+    /// Sends the requests that were waiting for the pending message to get a number
+    fn resume_queues(&self) {
+        for user_data in self.get_users_liststore().iter::<UserObject>() {
+            user_data.unwrap().resume_queue();
+        }
+    }
+
+    /// The server refused the last sent message so it will never get a number
+    fn drop_pending_message(&self) {
+        for user_data in self.get_users_liststore().iter::<UserObject>() {
+            if user_data.unwrap().drop_pending_message() {
+                break;
+            }
+        }
+        self.resume_queues();
+    }
+
+    /// Switches the owner to the user this device was paired with.
+    /// Chats of the previous user are removed as they belong to a different account
+    fn handle_device_paired(&self, id_data: UserIDs) {
+        info!("Device has been paired with User ID {}", id_data.user_id);
+        let owner = self.get_chatting_from();
+
+        let other_users: Vec<u64> = self
+            .get_users_liststore()
+            .iter::<UserObject>()
+            .map(|user_data| user_data.unwrap().user_id())
+            .filter(|user_id| *user_id != owner.user_id())
+            .collect();
+
+        for user_id in other_users {
+            self.delete_user(user_id);
+        }
+
+        owner.messages().remove_all();
+        owner.set_message_number(0);
+        owner.set_user_id(id_data.user_id);
+        owner.set_owner_id(id_data.user_id);
+        owner.set_user_token(id_data.user_token);
+        self.save_user_data();
+
+        let toast = Toast::builder()
+            .title("Device has been paired")
+            .timeout(2)
+            .build();
+        self.imp().toast_overlay.add_toast(toast);
+    }
+
+    /// Show an error that was sent by the server as a toast
+    fn show_error(&self, error_data: ErrorData) {
+        error!(
+            "Server failed to process {:?} request: {}",
+            error_data.command, error_data.message
+        );
+        let toast = Toast::builder()
+            .title(format!("Error: {}", error_data.message))
+            .timeout(2)
+            .build();
+        self.imp().toast_overlay.add_toast(toast);
     }
 
     /// Used to create all UserObject for the self's users ListStore except for the owner UserObject.
     /// Called when New Chat button is used or a message is received but the user was not added
-    fn create_user(&self, user_data: FullUserData) {
+    fn create_user(&self, user_data: FullUserData) -> UserObject {
         info!(
             "Creating new user with name: {}, id: {}",
             user_data.user_name, user_data.user_id
@@ -625,6 +1229,7 @@ impl Window {
             Some(&self.get_owner_name_color()),
             Some(user_data.user_id),
             None,
+            &self.get_ws(),
         );
 
         // Every single user in the UserList of the client will have the owner User ID for reference
@@ -635,14 +1240,10 @@ impl Window {
             .sync_create()
             .build();
 
-        chatting_from
-            .bind_property("user-token", &new_user_data, "user-token")
-            .sync_create()
-            .build();
-
-        new_user_data.handle_ws(self.clone());
+        new_user_data.handle_ws();
         self.get_users_liststore().append(&new_user_data);
         self.save_user_list();
+        new_user_data
     }
 
     /// Get the users ListBox
@@ -695,15 +1296,13 @@ impl Window {
         }
     }
 
-    /// Iters through every UserObject and tries to reconnect to the WebSocket server
+    /// Tries to reconnect to the WebSocket server
     pub fn reload_user_ws(&self) {
         info!("Reloading websocket connection");
-        let user_list = self.get_users_liststore();
-
-        for user_data in user_list.iter() {
-            let user_data: UserObject = user_data.unwrap();
-            user_data.user_ws().reload_manually();
-        }
+        let ws = self.get_ws();
+        // A manual reload asks about a refused certificate again
+        ws.clear_certificate_warning();
+        ws.reload_manually();
     }
 
     /// Find a UserObject based on the User ID
@@ -729,9 +1328,7 @@ impl Window {
                     self.get_user_list().row_at_index(0).unwrap().activate();
                 }
                 self.get_users_liststore().remove(index as u32);
-                user_data
-                    .user_ws()
-                    .emit_by_name::<()>("stop-processing", &[&true]);
+                user_data.disconnect_ws();
                 self.save_user_list();
                 break;
             }
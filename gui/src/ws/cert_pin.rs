@@ -0,0 +1,109 @@
+use gio::{Settings, TlsCertificate, TlsCertificateFlags};
+use glib::{compute_checksum_for_data, ChecksumType};
+use gtk::{gio, glib, prelude::*};
+use std::collections::HashMap;
+use tracing::{error, info};
+
+use crate::APP_ID;
+
+/// What to do with a certificate that failed the normal verification
+pub enum CertificateCheck {
+    // Pinned earlier by the user
+    Trusted,
+    // Self-signed and not seen before. The user has to decide whether to trust it
+    Unknown(String),
+    // Different from the one the user pinned for this server
+    Changed(String),
+    // Not acceptable even if pinned. Contains the reason
+    Invalid(String),
+}
+
+/// Saved certificate fingerprints of the servers the user has trusted. {host:port: SHA256 fingerprint}
+fn pinned_certificates(settings: &Settings) -> HashMap<String, String> {
+    let pinned = settings.string("pinned-certificates");
+    if pinned.is_empty() {
+        HashMap::new()
+    } else {
+        serde_json::from_str(&pinned).unwrap_or_default()
+    }
+}
+
+/// The SHA256 fingerprint of the DER data of the certificate in uppercase hex
+pub fn certificate_fingerprint(certificate: &TlsCertificate) -> String {
+    let der_data = certificate
+        .certificate()
+        .map(|data| data.to_vec())
+        .unwrap_or_default();
+    compute_checksum_for_data(ChecksumType::Sha256, &der_data)
+        .unwrap()
+        .to_uppercase()
+}
+
+/// Decides whether a certificate the system store or the CA file did not accept can be used
+pub fn check_certificate(
+    host: &str,
+    certificate: &TlsCertificate,
+    errors: TlsCertificateFlags,
+) -> CertificateCheck {
+    // Only certificates that are valid apart from not being signed by a trusted CA can be pinned
+    if errors != TlsCertificateFlags::UNKNOWN_CA {
+        return CertificateCheck::Invalid(format!("{errors:?}"));
+    }
+
+    let fingerprint = certificate_fingerprint(certificate);
+    let settings = Settings::new(APP_ID);
+
+    match pinned_certificates(&settings).get(host) {
+        Some(pinned) if pinned == &fingerprint => CertificateCheck::Trusted,
+        Some(_) => CertificateCheck::Changed(fingerprint),
+        None => CertificateCheck::Unknown(fingerprint),
+    }
+}
+
+/// Trust the certificate with the fingerprint for all future connections to the host
+pub fn pin_certificate(host: &str, fingerprint: &str) {
+    info!("Pinning certificate {} for {}", fingerprint, host);
+    let settings = Settings::new(APP_ID);
+
+    let mut pinned = pinned_certificates(&settings);
+    pinned.insert(host.to_string(), fingerprint.to_string());
+
+    settings
+        .set_string(
+            "pinned-certificates",
+            &serde_json::to_string(&pinned).unwrap(),
+        )
+        .unwrap();
+}
+
+/// Groups the fingerprint in pairs so it is easier to compare by eye
+pub fn format_fingerprint(fingerprint: &str) -> String {
+    fingerprint
+        .as_bytes()
+        .chunks(2)
+        .map(|pair| String::from_utf8_lossy(pair).to_string())
+        .collect::<Vec<String>>()
+        .join(":")
+}
+
+/// The client certificate configured in the settings, if both the certificate and key path are set
+pub fn client_certificate() -> Option<TlsCertificate> {
+    let settings = Settings::new(APP_ID);
+    let cert_path = settings.string("client-certificate");
+    let key_path = settings.string("client-key");
+
+    if cert_path.is_empty() || key_path.is_empty() {
+        return None;
+    }
+
+    match TlsCertificate::from_files(&cert_path, &key_path) {
+        Ok(certificate) => Some(certificate),
+        Err(e) => {
+            error!(
+                "Failed to load client certificate {} with key {}: {}",
+                cert_path, key_path, e
+            );
+            None
+        }
+    }
+}
@@ -1,5 +1,7 @@
+mod cert_pin;
 pub mod ws_data;
 mod ws_models;
 
+pub use cert_pin::{format_fingerprint, pin_certificate};
 pub use ws_data::WSObject;
 pub use ws_models::*;
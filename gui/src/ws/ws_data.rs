@@ -1,20 +1,22 @@
 mod imp {
     use adw::prelude::*;
     use adw::subclass::prelude::*;
+    use chirp_protocol::MessageData;
     use gio::glib::Sender;
     use glib::once_cell::sync::Lazy;
     use glib::subclass::Signal;
-    use glib::{derived_properties, object_subclass, Properties, SignalHandlerId};
+    use glib::{derived_properties, object_subclass, Properties};
     use gtk::glib;
     use soup::WebsocketConnection;
     use std::cell::{Cell, OnceCell, RefCell};
 
+    use crate::message::MessageObject;
+
     #[derive(Properties, Default)]
     #[properties(wrapper_type = super::WSObject)]
     pub struct WSObject {
         #[property(get, set, nullable)]
         pub ws_conn: RefCell<Option<WebsocketConnection>>,
-        pub ws_signal_id: RefCell<Option<SignalHandlerId>>,
         pub ws_sender: OnceCell<Sender<Option<WebsocketConnection>>>,
         pub notifier: OnceCell<Sender<bool>>,
         #[property(get, set)]
@@ -27,6 +29,11 @@ mod imp {
         pub manually_reloaded: Cell<bool>,
         #[property(get, set)]
         pub stop_processing: Cell<bool>,
+        // Fingerprint of the last certificate the user was warned about so it is not repeated every reconnection
+        pub warned_certificate: RefCell<String>,
+        // The sent message that is waiting for the server to give it a number. Only one message
+        // is sent at a time so the receipt can be matched with it
+        pub pending_message: RefCell<Option<(MessageData, MessageObject)>>,
     }
 
     #[object_subclass]
@@ -49,6 +56,18 @@ mod imp {
                     Signal::builder("stop-processing")
                         .param_types([bool::static_type()])
                         .build(),
+                    // Host and fingerprint of a self-signed certificate that has not been pinned
+                    Signal::builder("certificate-unknown")
+                        .param_types([String::static_type(), String::static_type()])
+                        .build(),
+                    // Host and fingerprint of a certificate that does not match the pinned one
+                    Signal::builder("certificate-changed")
+                        .param_types([String::static_type(), String::static_type()])
+                        .build(),
+                    // Host and the reason of a certificate that can not be accepted
+                    Signal::builder("certificate-invalid")
+                        .param_types([String::static_type(), String::static_type()])
+                        .build(),
                 ]
             });
             SIGNALS.as_ref()
@@ -57,16 +76,19 @@ mod imp {
 }
 
 use adw::subclass::prelude::*;
-use gio::Cancellable;
+use chirp_protocol::ClientRequest;
+use gio::{Cancellable, TlsFileDatabase};
 use glib::{
     clone, closure_local, timeout_add_seconds_local, wrapper, ControlFlow, MainContext, Object,
-    Priority, SignalHandlerId,
+    Priority,
 };
 use gtk::{glib, prelude::*};
 use soup::{prelude::*, Message, Session, WebsocketConnection};
 use std::env;
 use tracing::{debug, error, info};
 
+use crate::ws::cert_pin::{check_certificate, client_certificate, CertificateCheck};
+
 wrapper! {
     pub struct WSObject(ObjectSubclass<imp::WSObject>);
 }
@@ -86,9 +108,47 @@ impl WSObject {
 
         let websocket_url = env::var("WEBSOCKET_URL").expect("WEBSOCKET_URL must be set");
 
+        // Certificates are verified with the system store unless a CA file is given
+        if let Ok(ca_file) = env::var("CA_FILE") {
+            match TlsFileDatabase::new(&ca_file) {
+                Ok(database) => session.set_tls_database(Some(&database)),
+                Err(e) => error!("Failed to load CA file {}: {}", ca_file, e),
+            }
+        }
+
         let message = Message::new("GET", &websocket_url).unwrap();
 
-        message.connect_accept_certificate(move |_, _, _| true);
+        // Only used if the server asks for a client certificate
+        if let Some(certificate) = client_certificate() {
+            message.set_tls_client_certificate(Some(&certificate));
+        }
+
+        // Only called when the certificate fails the verification
+        message.connect_accept_certificate(
+            clone!(@weak self as ws => @default-return false, move |message, certificate, errors| {
+                let uri = message.uri();
+                let host = match uri.port() {
+                    -1 => uri.host().unwrap_or_default().to_string(),
+                    port => format!("{}:{}", uri.host().unwrap_or_default(), port),
+                };
+
+                match check_certificate(&host, certificate, errors) {
+                    CertificateCheck::Trusted => true,
+                    CertificateCheck::Unknown(fingerprint) => {
+                        ws.warn_certificate("certificate-unknown", host, fingerprint);
+                        false
+                    }
+                    CertificateCheck::Changed(fingerprint) => {
+                        ws.warn_certificate("certificate-changed", host, fingerprint);
+                        false
+                    }
+                    CertificateCheck::Invalid(reason) => {
+                        ws.warn_certificate("certificate-invalid", host, reason);
+                        false
+                    }
+                }
+            }),
+        );
 
         let cancel = Cancellable::new();
 
@@ -201,11 +261,25 @@ impl WSObject {
                 if let Some(sender) = from.imp().notifier.get() {
                     sender.send(true).unwrap();
                 }
-                from.imp().ws_signal_id.replace(None);
             }),
         );
     }
 
+    /// Emits the certificate signal unless the user was already warned about the same certificate
+    fn warn_certificate(&self, signal: &str, host: String, detail: String) {
+        if self.imp().warned_certificate.borrow().as_str() == detail {
+            return;
+        }
+        error!("Refusing certificate of {}: {}", host, signal);
+        self.imp().warned_certificate.replace(detail.to_owned());
+        self.emit_by_name::<()>(signal, &[&host, &detail]);
+    }
+
+    /// Lets the user be warned again about the same certificate on the next connection
+    pub fn clear_certificate_warning(&self) {
+        self.imp().warned_certificate.replace(String::new());
+    }
+
     /// Pings and follows if the connection was closed
     pub fn start_pinging(&self) {
         let conn = self.ws_conn().unwrap();
@@ -231,78 +305,9 @@ impl WSObject {
         self.connect_to_ws();
     }
 
-    /// Sends a message
-    pub fn send_text_message(&self, message: &str) {
-        info!("Sending request to WS to process message");
-        self.ws_conn()
-            .unwrap()
-            .send_text(&format!("/message {}", message));
-    }
-
-    /// Calls the server to create a new user with the given data
-    pub fn create_new_user(&self, user_data: String) {
-        info!("Sending request to WS to create a new user");
-        self.ws_conn()
-            .unwrap()
-            .send_text(&format!("/create-new-user {}", user_data));
-    }
-
-    /// Calls the server to get profile data of a user
-    pub fn get_user_data(&self, data: &str) {
-        info!("Sending request for getting UserObject Data");
-        self.ws_conn()
-            .unwrap()
-            .send_text(&format!("/get-user-data {}", data))
-    }
-
-    /// Calls the server to update the user image link
-    pub fn image_link_updated(&self, link: &str) {
-        info!("Sending request to WS to update image link");
-        self.ws_conn()
-            .unwrap()
-            .send_text(&format!("/image-updated {}", link))
-    }
-
-    /// Calls the server to update the user name
-    pub fn name_updated(&self, name: &str) {
-        info!("Sending request to WS update name");
-        self.ws_conn()
-            .unwrap()
-            .send_text(&format!("/name-updated {}", name))
-    }
-
-    /// Connects to the WS to reconnect with previously server deleted user data
-    pub fn reconnect_user(&self, id_data: String) {
-        info!("Sending request to WS to reconnect");
-        self.ws_conn()
-            .unwrap()
-            .send_text(&format!("/reconnect-user {}", id_data))
-    }
-
-    /// Calls the server to send last chat message number of a user
-    pub fn selection_update(&self, data: String) {
-        info!("Sending request to WS get the last message number");
-        self.ws_conn()
-            .unwrap()
-            .send_text(&format!("/message-number {}", data))
-    }
-
-    pub fn sync_message(&self, data: String) {
-        info!("Sending request to WS sync messages");
-        self.ws_conn()
-            .unwrap()
-            .send_text(&format!("/sync-message {}", data))
-    }
-
-    pub fn delete_message(&self, data: String) {
-        info!("Sending request to WS delete a message");
-        self.ws_conn()
-            .unwrap()
-            .send_text(&format!("/delete-message {}", data))
-    }
-
-    /// Saves the signal ID of the Websocket Message Signal
-    pub fn set_signal_id(&self, id: SignalHandlerId) {
-        self.imp().ws_signal_id.replace(Some(id));
+    /// Sends a request to the WS server
+    pub fn send_request(&self, request: &ClientRequest) {
+        info!("Sending {} request to WS", request.command_name());
+        self.ws_conn().unwrap().send_text(&request.to_json());
     }
 }
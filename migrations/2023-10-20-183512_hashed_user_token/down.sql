@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+-- The original tokens cannot be recovered from the hashes so every user will need a new token
+ALTER TABLE users
+RENAME COLUMN token_hash TO user_token;
+
+ALTER TABLE users
+DROP COLUMN token_salt;
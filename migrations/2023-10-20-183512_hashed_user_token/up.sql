@@ -0,0 +1,16 @@
+-- Your SQL goes here
+ALTER TABLE users
+ADD COLUMN token_salt VARCHAR(40) NOT NULL DEFAULT '';
+
+-- Hash the existing tokens the same way the server does so they keep working
+UPDATE users
+SET token_salt = UPPER(MD5(RANDOM()::TEXT));
+
+UPDATE users
+SET user_token = UPPER(ENCODE(SHA256(CONVERT_TO(token_salt || user_token, 'UTF8')), 'hex'));
+
+ALTER TABLE users
+ALTER COLUMN token_salt DROP DEFAULT;
+
+ALTER TABLE users
+RENAME COLUMN user_token TO token_hash;
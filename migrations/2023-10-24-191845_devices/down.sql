@@ -0,0 +1,20 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE users
+ADD COLUMN token_hash VARCHAR(70) NOT NULL DEFAULT '',
+ADD COLUMN token_salt VARCHAR(40) NOT NULL DEFAULT '';
+
+-- Only the oldest device of every user keeps working
+UPDATE users
+SET token_hash = devices.token_hash, token_salt = devices.token_salt
+FROM (
+    SELECT DISTINCT ON (user_id) user_id, token_hash, token_salt
+    FROM devices
+    ORDER BY user_id, device_id
+) AS devices
+WHERE users.user_id = devices.user_id;
+
+ALTER TABLE users
+ALTER COLUMN token_hash DROP DEFAULT,
+ALTER COLUMN token_salt DROP DEFAULT;
+
+DROP TABLE devices;
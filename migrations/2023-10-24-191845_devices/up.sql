@@ -0,0 +1,20 @@
+-- Your SQL goes here
+CREATE TABLE devices (
+    device_id SERIAL PRIMARY KEY,
+    user_id INT NOT NULL,
+    device_name VARCHAR(250) NOT NULL,
+    token_hash VARCHAR(70) NOT NULL,
+    token_salt VARCHAR(40) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    FOREIGN KEY (user_id) REFERENCES users (user_id) ON DELETE CASCADE
+);
+CREATE INDEX devices_user_id_idx ON devices (user_id);
+
+-- The existing token of every user becomes their first device
+INSERT INTO devices (user_id, device_name, token_hash, token_salt)
+SELECT user_id, 'Primary device', token_hash, token_salt
+FROM users;
+
+ALTER TABLE users
+DROP COLUMN token_hash,
+DROP COLUMN token_salt;
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX messages_undelivered_idx;
+ALTER TABLE messages
+DROP COLUMN delivered;
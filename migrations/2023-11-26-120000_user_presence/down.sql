@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE users
+DROP COLUMN hide_last_seen,
+DROP COLUMN last_seen;
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+ALTER TABLE users
+ADD COLUMN last_seen TIMESTAMPTZ,
+ADD COLUMN hide_last_seen BOOLEAN NOT NULL DEFAULT FALSE;
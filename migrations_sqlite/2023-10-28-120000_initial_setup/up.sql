@@ -0,0 +1,31 @@
+-- Same tables as the Postgres migrations in migrations/ after all of them are applied
+CREATE TABLE users (
+    user_id INTEGER PRIMARY KEY NOT NULL,
+    user_name VARCHAR(250) NOT NULL,
+    image_link TEXT
+);
+
+CREATE TABLE devices (
+    device_id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    user_id INTEGER NOT NULL,
+    device_name VARCHAR(250) NOT NULL,
+    token_hash VARCHAR(70) NOT NULL,
+    token_salt VARCHAR(40) NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users (user_id) ON DELETE CASCADE
+);
+CREATE INDEX devices_user_id_idx ON devices (user_id);
+
+CREATE TABLE messages (
+    message_id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    message_group VARCHAR(40) NOT NULL,
+    message_number INTEGER NOT NULL,
+    message_text TEXT,
+    message_sender INTEGER NOT NULL,
+    message_receiver INTEGER NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+    FOREIGN KEY (message_sender) REFERENCES users (user_id),
+    FOREIGN KEY (message_receiver) REFERENCES users (user_id),
+    UNIQUE (message_group, message_number)
+);
+CREATE INDEX messages_message_group_idx ON messages (message_group);
@@ -0,0 +1,6 @@
+-- Your SQL goes here
+ALTER TABLE messages
+ADD COLUMN delivered BOOLEAN NOT NULL DEFAULT FALSE;
+-- Messages saved before delivery was tracked are not pushed again
+UPDATE messages SET delivered = TRUE;
+CREATE INDEX messages_undelivered_idx ON messages (message_receiver) WHERE NOT delivered;
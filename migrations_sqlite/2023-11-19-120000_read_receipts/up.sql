@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE messages
+ADD COLUMN read BOOLEAN NOT NULL DEFAULT FALSE;
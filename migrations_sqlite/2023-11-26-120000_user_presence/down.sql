@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE users
+DROP COLUMN hide_last_seen;
+
+ALTER TABLE users
+DROP COLUMN last_seen;
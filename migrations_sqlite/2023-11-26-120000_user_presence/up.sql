@@ -0,0 +1,6 @@
+-- Your SQL goes here
+ALTER TABLE users
+ADD COLUMN last_seen TIMESTAMP;
+
+ALTER TABLE users
+ADD COLUMN hide_last_seen BOOLEAN NOT NULL DEFAULT FALSE;
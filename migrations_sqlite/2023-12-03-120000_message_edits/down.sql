@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE message_edits;
+
+ALTER TABLE messages
+DROP COLUMN edited_at;
@@ -0,0 +1,14 @@
+-- Your SQL goes here
+ALTER TABLE messages
+ADD COLUMN edited_at TIMESTAMP;
+
+-- Every earlier version of an edited message
+CREATE TABLE message_edits (
+    edit_id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    message_id INTEGER NOT NULL,
+    message_text TEXT NOT NULL,
+    -- When this version of the message was written
+    created_at TIMESTAMP NOT NULL,
+    FOREIGN KEY (message_id) REFERENCES messages (message_id) ON DELETE CASCADE
+);
+CREATE INDEX message_edits_message_id_idx ON message_edits (message_id);
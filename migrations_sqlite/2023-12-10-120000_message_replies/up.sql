@@ -0,0 +1,4 @@
+-- Your SQL goes here
+-- The message number in the same group that the message replies to
+ALTER TABLE messages
+ADD COLUMN reply_to INTEGER;
@@ -0,0 +1,11 @@
+-- Your SQL goes here
+-- A user can have a single reaction on a message
+CREATE TABLE message_reactions (
+    message_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    emoji VARCHAR(16) NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+    FOREIGN KEY (message_id) REFERENCES messages (message_id) ON DELETE CASCADE,
+    FOREIGN KEY (user_id) REFERENCES users (user_id),
+    PRIMARY KEY (message_id, user_id)
+);
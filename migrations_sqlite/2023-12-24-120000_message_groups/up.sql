@@ -0,0 +1,6 @@
+-- Your SQL goes here
+-- The highest message number a group has used. Kept once messages are removed so their numbers are not reused
+CREATE TABLE message_groups (
+    message_group VARCHAR(40) PRIMARY KEY NOT NULL,
+    last_message_number INTEGER NOT NULL
+);
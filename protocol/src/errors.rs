@@ -0,0 +1,64 @@
+use serde::{Deserialize, Serialize};
+
+/// Types of errors the WS server can reply with
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ErrorCode {
+    // The request could not be parsed
+    InvalidRequest,
+    // The request was parsed but contains invalid data
+    InvalidData,
+    // The user token did not match any user
+    InvalidToken,
+    // The session has not authenticated yet
+    NotAuthenticated,
+    // The target user or message does not exist
+    NotFound,
+    // The account was disabled by an admin
+    AccountDisabled,
+    // Too many requests were sent. retry_after says when to try again
+    RateLimited,
+    // The server failed to process a valid request
+    ServerError,
+}
+
+/// Sent back to the client when a request fails
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ErrorData {
+    pub code: ErrorCode,
+    pub message: String,
+    // The command that caused the error if it could be determined
+    pub command: Option<String>,
+    // Seconds to wait before sending the request again. Only set for RateLimited
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_after: Option<u64>,
+}
+
+impl ErrorData {
+    pub fn new(code: ErrorCode, message: &str) -> Self {
+        ErrorData {
+            code,
+            message: message.to_string(),
+            command: None,
+            retry_after: None,
+        }
+    }
+
+    pub fn rate_limited(retry_after: u64) -> Self {
+        ErrorData {
+            code: ErrorCode::RateLimited,
+            message: format!("Too many requests. Retry after {retry_after} seconds"),
+            command: None,
+            retry_after: Some(retry_after),
+        }
+    }
+
+    pub fn for_command(self, command: &str) -> Self {
+        ErrorData {
+            code: self.code,
+            message: self.message,
+            command: Some(command.to_string()),
+            retry_after: self.retry_after,
+        }
+    }
+}
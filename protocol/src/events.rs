@@ -0,0 +1,75 @@
+use serde::{Deserialize, Serialize};
+
+use crate::errors::ErrorData;
+use crate::models::{
+    DeleteMessage, EditMessage, FullUserData, MessageData, MessageHistory, MessageReceipt,
+    PairingCode, Presence, ReactionUpdate, TypingUpdate, UserIDs,
+};
+
+/// Every event the WS server can send to a client
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "event", content = "data", rename_all = "kebab-case")]
+pub enum ServerEvent {
+    // A new user was created for this client and the session is authenticated
+    UpdateUserId(UserIDs),
+    // The session is authenticated. Contains the profile of the authenticated user
+    Authenticated(FullUserData),
+    // Reconnected with an existing user. The user is now a contact of the session
+    ReconnectSuccess(FullUserData),
+    // Profile data of a requested user. Empty data if the user does not exist
+    GetUserData(FullUserData),
+    // A message was received
+    Message(MessageData),
+    // Name of a contact got updated
+    NameUpdated {
+        user_id: u64,
+        new_name: String,
+    },
+    // Image of a contact got updated
+    ImageUpdated {
+        user_id: u64,
+        image_link: Option<String>,
+    },
+    // Last message number of the chat with a user
+    MessageNumber {
+        user_id: u64,
+        message_number: u64,
+    },
+    // Messages of the chat with a user to sync, in reverse order
+    SyncMessage {
+        user_id: u64,
+        message_data: Vec<MessageData>,
+    },
+    // A message was deleted. The user id is the other user of the chat
+    DeleteMessage(DeleteMessage),
+    // A message was edited by its sender. The user id is the other user of the chat
+    EditMessage(EditMessage),
+    // The earlier versions of a message that were asked for
+    MessageHistory(MessageHistory),
+    // A user reacted to a message or removed the reaction
+    Reaction(ReactionUpdate),
+    // Messages sent to a user were saved, delivered or read. The user id is the receiver of them
+    MessageReceipt(MessageReceipt),
+    // A contact started or stopped typing. The user id is the typing user
+    Typing(TypingUpdate),
+    // The presence of a contact changed or the contact was just added
+    Presence(Presence),
+    // The user token was rotated. Contains the new token
+    TokenRotated(UserIDs),
+    // A pairing code was created for a new device
+    PairingCode(PairingCode),
+    // The device joined an account. Contains the new credentials of the device
+    DevicePaired(UserIDs),
+    // A request sent by the client failed
+    Error(ErrorData),
+}
+
+impl ServerEvent {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    pub fn from_json(data: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(data)
+    }
+}
@@ -0,0 +1,11 @@
+mod errors;
+mod events;
+mod models;
+mod requests;
+mod validation;
+
+pub use errors::{ErrorCode, ErrorData};
+pub use events::ServerEvent;
+pub use models::*;
+pub use requests::ClientRequest;
+pub use validation::{ContentLimits, NAME_COLUMN_LENGTH};
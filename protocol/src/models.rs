@@ -0,0 +1,239 @@
+use serde::{Deserialize, Serialize};
+
+/// Used for sending or receiving relevant data to create an user
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FullUserData {
+    pub user_id: u64,
+    pub user_name: String,
+    pub image_link: Option<String>,
+}
+
+impl FullUserData {
+    pub fn new(user_id: u64, user_name: String, image_link: Option<String>) -> Self {
+        FullUserData {
+            user_id,
+            user_name,
+            image_link,
+        }
+    }
+}
+
+/// Used for authenticating a session or saving the owner data locally
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UserIDs {
+    pub user_id: u64,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub user_token: String,
+}
+
+impl UserIDs {
+    pub fn new(user_id: u64, user_token: String) -> Self {
+        UserIDs {
+            user_id,
+            user_token,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    pub fn from_json(data: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(data)
+    }
+}
+
+/// How far a message got. Each status includes the ones before it
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum MessageStatus {
+    // Saved on the server
+    #[default]
+    Sent,
+    // Reached a session of the receiver
+    Delivered,
+    // The receiver opened the chat
+    Read,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MessageData {
+    pub created_at: String,
+    pub from_user: u64,
+    pub to_user: u64,
+    pub message: String,
+    pub message_number: u64,
+    #[serde(default)]
+    pub status: MessageStatus,
+    // When the message was last edited in UTC. None if it was never edited
+    #[serde(default)]
+    pub edited_at: Option<String>,
+    // Number of the earlier message of the same chat this one replies to
+    #[serde(default)]
+    pub reply_to: Option<u64>,
+    #[serde(default)]
+    pub reactions: Vec<Reaction>,
+}
+
+impl MessageData {
+    pub fn new_incomplete(
+        created_at: String,
+        from_user: u64,
+        to_user: u64,
+        message: String,
+    ) -> Self {
+        MessageData {
+            created_at,
+            from_user,
+            to_user,
+            message,
+            message_number: 0,
+            status: MessageStatus::Sent,
+            edited_at: None,
+            reply_to: None,
+            reactions: Vec::new(),
+        }
+    }
+
+    pub fn update_message_number(self, message_number: u64) -> Self {
+        MessageData {
+            created_at: self.created_at,
+            from_user: self.from_user,
+            to_user: self.to_user,
+            message: self.message,
+            message_number,
+            status: self.status,
+            edited_at: self.edited_at,
+            reply_to: self.reply_to,
+            reactions: self.reactions,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NameUpdate {
+    pub new_name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ImageUpdate {
+    pub image_link: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MessageSyncRequest {
+    pub user_id: u64,
+    pub start_at: u64,
+    pub end_at: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeleteMessage {
+    pub user_id: u64,
+    pub message_number: u64,
+}
+
+/// The new text of a message sent by the owner. The user id is the other user of the chat
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EditMessage {
+    pub user_id: u64,
+    pub message_number: u64,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MessageHistoryRequest {
+    pub user_id: u64,
+    pub message_number: u64,
+}
+
+/// An earlier version of an edited message. The time is when this version was written in UTC
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MessageRevision {
+    pub message: String,
+    pub created_at: String,
+}
+
+/// Every earlier version of a message, oldest first. The user id is the other user of the chat
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MessageHistory {
+    pub user_id: u64,
+    pub message_number: u64,
+    pub revisions: Vec<MessageRevision>,
+}
+
+/// An emoji a user reacted to a message with
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Reaction {
+    pub user_id: u64,
+    pub emoji: String,
+}
+
+/// Sets the reaction of the owner on a message. No emoji removes it.
+/// The user id is the other user of the chat
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReactionRequest {
+    pub user_id: u64,
+    pub message_number: u64,
+    pub emoji: Option<String>,
+}
+
+/// The reaction of a user on a message was set or removed. The user id is the other user of the chat
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReactionUpdate {
+    pub user_id: u64,
+    pub message_number: u64,
+    pub reacted_by: u64,
+    pub emoji: Option<String>,
+}
+
+/// Every message sent to the user of the chat up to the message number reached the status.
+/// The user id is the other user of the chat
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MessageReceipt {
+    pub user_id: u64,
+    pub message_number: u64,
+    pub status: MessageStatus,
+}
+
+/// A user started or stopped typing in a chat. Only relayed, never saved
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TypingUpdate {
+    pub user_id: u64,
+    pub typing: bool,
+}
+
+/// Whether a user has an active session and is using it
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum PresenceStatus {
+    // At least one session of the user is in use
+    Online,
+    // Every session of the user is idle
+    Away,
+    // The user has no active session
+    Offline,
+}
+
+/// The presence of a user. The last seen time is an RFC 3339 UTC time that is only included
+/// when the user is offline and does not hide it
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Presence {
+    pub user_id: u64,
+    pub status: PresenceStatus,
+    pub last_seen: Option<String>,
+}
+
+/// A short lived code that lets a new device join an account
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PairingCode {
+    pub code: String,
+    // Seconds until the code expires
+    pub expires_in: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PairDevice {
+    pub code: String,
+    pub device_name: String,
+}
@@ -0,0 +1,108 @@
+use serde::{Deserialize, Serialize};
+
+use crate::models::{
+    DeleteMessage, EditMessage, FullUserData, ImageUpdate, MessageData, MessageHistoryRequest,
+    MessageReceipt, MessageSyncRequest, NameUpdate, PairDevice, PresenceStatus, ReactionRequest,
+    TypingUpdate, UserIDs,
+};
+
+/// Every request a client can send to the WS server
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "command", content = "data", rename_all = "kebab-case")]
+pub enum ClientRequest {
+    // Authenticate the session as an existing user. Must be sent before anything else
+    Authenticate(UserIDs),
+    // Create a new user and authenticate the session as it
+    CreateNewUser(FullUserData),
+    // Reconnect with an existing user and add it as a contact of the session
+    ReconnectUser(u64),
+    // Get profile data of a specific user
+    GetUserData(u64),
+    // Send a message to another user
+    Message(MessageData),
+    // Broadcast name updates to relevant sessions
+    NameUpdated(NameUpdate),
+    // Broadcast image updates to relevant sessions
+    ImageUpdated(ImageUpdate),
+    // Get the last message number of a user group
+    MessageNumber(u64),
+    // Get message data to sync messages
+    SyncMessage(MessageSyncRequest),
+    // Broadcast message deletion
+    DeleteMessage(DeleteMessage),
+    // Change the text of a message the owner sent and broadcast it
+    EditMessage(EditMessage),
+    // Get the earlier versions of an edited message
+    MessageHistory(MessageHistoryRequest),
+    // Set or remove the reaction of the owner on a message and broadcast it
+    React(ReactionRequest),
+    // Tell the sender that the messages of a chat were read. Only the read status can be sent
+    MessageReceipt(MessageReceipt),
+    // Tell the other user of the chat that typing started or stopped
+    Typing(TypingUpdate),
+    // Mark this session as online or away. Offline can not be set
+    SetPresence(PresenceStatus),
+    // Choose whether contacts can see when the user was last online
+    HideLastSeen(bool),
+    // Get a new token for this device. Other sessions using the old token get closed
+    RotateToken,
+    // Remove this device from the account and close every session using it
+    RevokeToken,
+    // Get a code that a new device can use to join the account
+    CreatePairingCode,
+    // Join an account with a pairing code as a new device
+    PairDevice(PairDevice),
+}
+
+impl ClientRequest {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    pub fn from_json(data: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(data)
+    }
+
+    /// Tries to get the command name from a request that failed to parse
+    pub fn command_from_raw(data: &str) -> Option<String> {
+        let value: serde_json::Value = serde_json::from_str(data).ok()?;
+        value.get("command")?.as_str().map(|c| c.to_string())
+    }
+
+    /// Whether the request can be processed before the session is authenticated
+    pub fn is_auth_request(&self) -> bool {
+        matches!(
+            self,
+            ClientRequest::Authenticate(_)
+                | ClientRequest::CreateNewUser(_)
+                | ClientRequest::PairDevice(_)
+        )
+    }
+
+    /// The wire name of the request, used for logging
+    pub fn command_name(&self) -> &'static str {
+        match self {
+            ClientRequest::Authenticate(_) => "authenticate",
+            ClientRequest::CreateNewUser(_) => "create-new-user",
+            ClientRequest::ReconnectUser(_) => "reconnect-user",
+            ClientRequest::GetUserData(_) => "get-user-data",
+            ClientRequest::Message(_) => "message",
+            ClientRequest::NameUpdated(_) => "name-updated",
+            ClientRequest::ImageUpdated(_) => "image-updated",
+            ClientRequest::MessageNumber(_) => "message-number",
+            ClientRequest::SyncMessage(_) => "sync-message",
+            ClientRequest::DeleteMessage(_) => "delete-message",
+            ClientRequest::EditMessage(_) => "edit-message",
+            ClientRequest::MessageHistory(_) => "message-history",
+            ClientRequest::React(_) => "react",
+            ClientRequest::MessageReceipt(_) => "message-receipt",
+            ClientRequest::Typing(_) => "typing",
+            ClientRequest::SetPresence(_) => "set-presence",
+            ClientRequest::HideLastSeen(_) => "hide-last-seen",
+            ClientRequest::RotateToken => "rotate-token",
+            ClientRequest::RevokeToken => "revoke-token",
+            ClientRequest::CreatePairingCode => "create-pairing-code",
+            ClientRequest::PairDevice(_) => "pair-device",
+        }
+    }
+}
@@ -0,0 +1,208 @@
+use serde::Deserialize;
+
+// The user_name column is VARCHAR(250)
+pub const NAME_COLUMN_LENGTH: usize = 250;
+
+// The emoji column is VARCHAR(16). Enough for emoji that are joined from several characters
+const REACTION_COLUMN_LENGTH: usize = 16;
+
+/// Limits on the content users can send. The server rejects anything outside of them and the
+/// GUI checks the same limits before sending
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ContentLimits {
+    // In characters
+    pub max_message_length: usize,
+    // In characters. Can not be more than NAME_COLUMN_LENGTH
+    pub max_name_length: usize,
+    // Characters allowed in names on top of letters and numbers
+    pub name_extra_characters: String,
+    pub max_image_link_length: usize,
+    // Lowercase URL schemes an image link can use
+    pub image_link_schemes: Vec<String>,
+}
+
+impl Default for ContentLimits {
+    fn default() -> Self {
+        ContentLimits {
+            max_message_length: 5000,
+            max_name_length: 50,
+            name_extra_characters: String::from(" -_.'"),
+            max_image_link_length: 2048,
+            image_link_schemes: vec![String::from("https"), String::from("http")],
+        }
+    }
+}
+
+impl ContentLimits {
+    pub fn validate_message(&self, message: &str) -> Result<(), String> {
+        if message.trim().is_empty() {
+            return Err(String::from("The message is empty"));
+        }
+
+        if message.chars().count() > self.max_message_length {
+            return Err(format!(
+                "The message is longer than {} characters",
+                self.max_message_length
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn validate_user_name(&self, name: &str) -> Result<(), String> {
+        if name.trim().is_empty() {
+            return Err(String::from("The name is empty"));
+        }
+
+        if name.chars().count() > self.max_name_length {
+            return Err(format!(
+                "The name is longer than {} characters",
+                self.max_name_length
+            ));
+        }
+
+        if let Some(invalid) = name
+            .chars()
+            .find(|c| !c.is_alphanumeric() && !self.name_extra_characters.contains(*c))
+        {
+            return Err(format!("The name can not contain '{invalid}'"));
+        }
+        Ok(())
+    }
+
+    pub fn validate_image_link(&self, link: &str) -> Result<(), String> {
+        if link.chars().count() > self.max_image_link_length {
+            return Err(format!(
+                "The image link is longer than {} characters",
+                self.max_image_link_length
+            ));
+        }
+
+        if link.chars().any(|c| c.is_whitespace() || c.is_control()) {
+            return Err(String::from("The image link can not contain spaces"));
+        }
+
+        let Some((scheme, rest)) = link.split_once("://") else {
+            return Err(String::from("The image link is not a valid URL"));
+        };
+
+        let scheme = scheme.to_lowercase();
+        if !self.image_link_schemes.contains(&scheme) {
+            return Err(format!(
+                "The image link must start with one of: {}",
+                self.image_link_schemes
+                    .iter()
+                    .map(|scheme| format!("{scheme}://"))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ));
+        }
+
+        let host = rest.split(['/', '?', '#']).next().unwrap_or_default();
+        if host.is_empty() {
+            return Err(String::from("The image link has no host"));
+        }
+        Ok(())
+    }
+
+    pub fn validate_reaction(&self, emoji: &str) -> Result<(), String> {
+        if emoji.is_empty() {
+            return Err(String::from("The reaction is empty"));
+        }
+
+        if emoji.chars().count() > REACTION_COLUMN_LENGTH {
+            return Err(String::from("The reaction is too long"));
+        }
+
+        if emoji
+            .chars()
+            .any(|c| c.is_whitespace() || c.is_control() || c.is_ascii_alphabetic())
+        {
+            return Err(String::from("The reaction must be an emoji"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ContentLimits, REACTION_COLUMN_LENGTH};
+
+    #[test]
+    fn messages_need_text_within_the_limit() {
+        let limits = ContentLimits::default();
+        assert!(limits.validate_message("Hello").is_ok());
+        assert!(limits.validate_message("").is_err());
+        assert!(limits.validate_message(" \n\t").is_err());
+
+        // The limit is in characters, not bytes
+        let longest = "é".repeat(limits.max_message_length);
+        assert!(limits.validate_message(&longest).is_ok());
+        assert!(limits.validate_message(&format!("{longest}é")).is_err());
+    }
+
+    #[test]
+    fn names_need_allowed_characters_within_the_limit() {
+        let limits = ContentLimits::default();
+        assert!(limits.validate_user_name("Zoë O'Neil-Smith_2.0").is_ok());
+        assert!(limits.validate_user_name("").is_err());
+        assert!(limits.validate_user_name("   ").is_err());
+        assert!(limits.validate_user_name("<script>").is_err());
+        assert!(limits.validate_user_name("new\nline").is_err());
+
+        let longest = "a".repeat(limits.max_name_length);
+        assert!(limits.validate_user_name(&longest).is_ok());
+        assert!(limits.validate_user_name(&format!("{longest}a")).is_err());
+    }
+
+    #[test]
+    fn image_links_need_an_allowed_scheme_and_a_host() {
+        let limits = ContentLimits::default();
+        assert!(limits
+            .validate_image_link("https://example.com/image.png")
+            .is_ok());
+        assert!(limits.validate_image_link("HTTP://example.com").is_ok());
+
+        for link in [
+            "",
+            "example.com/image.png",
+            "ftp://example.com/image.png",
+            "javascript://alert(1)",
+            "file:///etc/passwd",
+            "https://",
+            "https:///image.png",
+            "https://example.com/an image.png",
+            " https://example.com/image.png",
+        ] {
+            assert!(
+                limits.validate_image_link(link).is_err(),
+                "{link} was accepted"
+            );
+        }
+
+        let prefix = "https://example.com/";
+        let longest = format!(
+            "{prefix}{}",
+            "a".repeat(limits.max_image_link_length - prefix.len())
+        );
+        assert!(limits.validate_image_link(&longest).is_ok());
+        assert!(limits.validate_image_link(&format!("{longest}a")).is_err());
+    }
+
+    #[test]
+    fn reactions_must_be_short_emoji() {
+        let limits = ContentLimits::default();
+        assert!(limits.validate_reaction("👍").is_ok());
+        // Emoji joined from several characters
+        assert!(limits.validate_reaction("👨‍👩‍👧‍👦").is_ok());
+
+        assert!(limits.validate_reaction("").is_err());
+        assert!(limits.validate_reaction(" ").is_err());
+        assert!(limits.validate_reaction("ok").is_err());
+        assert!(limits.validate_reaction("👍\n").is_err());
+
+        let longest = "👍".repeat(REACTION_COLUMN_LENGTH);
+        assert!(limits.validate_reaction(&longest).is_ok());
+        assert!(limits.validate_reaction(&format!("{longest}👍")).is_err());
+    }
+}
@@ -0,0 +1,359 @@
+use actix::Addr;
+use actix_web::dev::Payload;
+use actix_web::http::header::AUTHORIZATION;
+use actix_web::{web, FromRequest, HttpRequest, HttpResponse};
+use chirp_protocol::{ErrorCode, ErrorData};
+use serde::{Deserialize, Serialize};
+use std::future::{ready, Ready};
+use tracing::info;
+
+use crate::api::{run_query, ApiError};
+use crate::db::{db_error, DbExecutor, Device, User};
+use crate::server::{ChatServer, CloseUserSessions, MessagesRemoved};
+use crate::utils::{generate_token_salt, generate_user_token, hash_user_token, token_matches};
+
+// Number of users returned when the request does not set a limit
+const DEFAULT_USER_LIMIT: u64 = 50;
+const MAX_USER_LIMIT: u64 = 500;
+
+/// Bearer token every admin request has to be sent with
+struct AdminToken(String);
+
+/// Extracted on every admin route. The request is refused unless it has the admin token
+struct Admin;
+
+impl FromRequest for Admin {
+    type Error = ApiError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let admin_token = req
+            .app_data::<web::Data<AdminToken>>()
+            .map(|token| token.0.as_str())
+            .unwrap_or_default();
+
+        let given_token = req
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .unwrap_or_default();
+
+        // Both are hashed first so the comparison takes the same time for any length
+        let authorized = !admin_token.is_empty()
+            && token_matches(
+                &hash_user_token(given_token, ""),
+                &hash_user_token(admin_token, ""),
+            );
+
+        ready(if authorized {
+            Ok(Admin)
+        } else {
+            Err(ApiError(ErrorData::new(
+                ErrorCode::NotAuthenticated,
+                "Invalid admin token",
+            )))
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct UserSearch {
+    search: Option<String>,
+    offset: Option<u64>,
+    limit: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct AdminUser {
+    user_id: u64,
+    user_name: String,
+    image_link: Option<String>,
+    disabled: bool,
+    last_seen: Option<String>,
+}
+
+impl From<User> for AdminUser {
+    fn from(user: User) -> Self {
+        AdminUser {
+            user_id: user.user_id as u64,
+            user_name: user.user_name,
+            image_link: user.image_link,
+            disabled: user.disabled,
+            last_seen: user.last_seen.map(|time| time.to_string()),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct AdminDevice {
+    device_id: u64,
+    device_name: String,
+    created_at: String,
+}
+
+impl From<Device> for AdminDevice {
+    fn from(device: Device) -> Self {
+        AdminDevice {
+            device_id: device.device_id as u64,
+            device_name: device.device_name,
+            created_at: device.created_at.to_string(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct AdminUserDetails {
+    #[serde(flatten)]
+    user: AdminUser,
+    devices: Vec<AdminDevice>,
+}
+
+/// The new token of a device after a reset. Has to be given to the user to log in again
+#[derive(Serialize)]
+struct ResetToken {
+    device_id: u64,
+    device_name: String,
+    user_token: String,
+}
+
+#[derive(Serialize)]
+struct Deleted {
+    deleted: usize,
+}
+
+fn user_not_found() -> ErrorData {
+    ErrorData::new(ErrorCode::NotFound, "The user does not exist")
+}
+
+/// Adds the admin routes under /admin if an admin token is set
+pub fn configure_admin(cfg: &mut web::ServiceConfig, admin_token: Option<String>) {
+    let Some(admin_token) = admin_token else {
+        return;
+    };
+
+    cfg.service(
+        web::scope("/admin")
+            .app_data(web::Data::new(AdminToken(admin_token)))
+            .route("/users", web::get().to(list_users))
+            .route("/users/{user_id}", web::get().to(get_user))
+            .route("/users/{user_id}/disable", web::post().to(disable_user))
+            .route("/users/{user_id}/enable", web::post().to(enable_user))
+            .route(
+                "/users/{user_id}/reset-tokens",
+                web::post().to(reset_tokens),
+            )
+            .route("/messages/{group}", web::delete().to(delete_message_group))
+            .route(
+                "/messages/{group}/{number}",
+                web::delete().to(delete_message),
+            ),
+    );
+}
+
+/// Users ordered by ID. Filtered by name if search is given
+async fn list_users(
+    _: Admin,
+    db: web::Data<Addr<DbExecutor>>,
+    query: web::Query<UserSearch>,
+) -> Result<HttpResponse, ApiError> {
+    let query = query.into_inner();
+    let offset = query.offset.unwrap_or(0);
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_USER_LIMIT)
+        .min(MAX_USER_LIMIT);
+
+    let users = run_query(&db, move |storage| {
+        storage
+            .search_users(query.search, offset, limit)
+            .map_err(|e| db_error(e, "Failed to get the users"))
+    })
+    .await?;
+
+    let users: Vec<AdminUser> = users.into_iter().map(AdminUser::from).collect();
+    Ok(HttpResponse::Ok().json(users))
+}
+
+async fn get_user(
+    _: Admin,
+    db: web::Data<Addr<DbExecutor>>,
+    user_id: web::Path<u64>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = user_id.into_inner();
+
+    let (user, devices) = run_query(&db, move |storage| {
+        let user = storage
+            .get_user_with_id(user_id)
+            .ok_or_else(user_not_found)?;
+        let devices = storage
+            .get_user_devices(user_id)
+            .map_err(|e| db_error(e, "Failed to get the devices"))?;
+        Ok((user, devices))
+    })
+    .await?;
+
+    Ok(HttpResponse::Ok().json(AdminUserDetails {
+        user: AdminUser::from(user),
+        devices: devices.into_iter().map(AdminDevice::from).collect(),
+    }))
+}
+
+/// Disables or enables the user. Every live session of a disabled user gets closed
+async fn set_user_disabled(
+    db: &Addr<DbExecutor>,
+    server: &Addr<ChatServer>,
+    user_id: u64,
+    disabled: bool,
+) -> Result<HttpResponse, ApiError> {
+    let updated = run_query(db, move |storage| {
+        storage
+            .update_user_disabled(user_id, disabled)
+            .map_err(|e| db_error(e, "Failed to update the user"))
+    })
+    .await?;
+
+    if updated == 0 {
+        return Err(user_not_found().into());
+    }
+
+    if disabled {
+        info!("Admin disabled User ID {}", user_id);
+        server.do_send(CloseUserSessions {
+            user_id,
+            reason: ErrorData::new(ErrorCode::AccountDisabled, "The account was disabled"),
+        });
+    } else {
+        info!("Admin enabled User ID {}", user_id);
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+async fn disable_user(
+    _: Admin,
+    db: web::Data<Addr<DbExecutor>>,
+    server: web::Data<Addr<ChatServer>>,
+    user_id: web::Path<u64>,
+) -> Result<HttpResponse, ApiError> {
+    set_user_disabled(&db, &server, user_id.into_inner(), true).await
+}
+
+async fn enable_user(
+    _: Admin,
+    db: web::Data<Addr<DbExecutor>>,
+    server: web::Data<Addr<ChatServer>>,
+    user_id: web::Path<u64>,
+) -> Result<HttpResponse, ApiError> {
+    set_user_disabled(&db, &server, user_id.into_inner(), false).await
+}
+
+/// Issues a new token to every device of the user and closes the sessions using the old ones
+async fn reset_tokens(
+    _: Admin,
+    db: web::Data<Addr<DbExecutor>>,
+    server: web::Data<Addr<ChatServer>>,
+    user_id: web::Path<u64>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = user_id.into_inner();
+
+    let new_tokens = run_query(&db, move |storage| {
+        storage
+            .get_user_with_id(user_id)
+            .ok_or_else(user_not_found)?;
+        let devices = storage
+            .get_user_devices(user_id)
+            .map_err(|e| db_error(e, "Failed to get the devices"))?;
+
+        let mut new_tokens = Vec::new();
+        let mut token_hashes = Vec::new();
+        for device in devices {
+            let user_token = generate_user_token();
+            let token_salt = generate_token_salt();
+            let token_hash = hash_user_token(&user_token, &token_salt);
+
+            token_hashes.push((device.device_id as u64, token_hash, token_salt));
+            new_tokens.push(ResetToken {
+                device_id: device.device_id as u64,
+                device_name: device.device_name,
+                user_token,
+            });
+        }
+
+        storage
+            .update_device_tokens(token_hashes)
+            .map_err(|e| db_error(e, "Failed to reset the tokens"))?;
+        Ok(new_tokens)
+    })
+    .await?;
+
+    info!("Admin reset the tokens of User ID {}", user_id);
+    server.do_send(CloseUserSessions {
+        user_id,
+        reason: ErrorData::new(ErrorCode::InvalidToken, "The user token was reset"),
+    });
+
+    Ok(HttpResponse::Ok().json(new_tokens))
+}
+
+/// Removes the message completely instead of only its text
+async fn delete_message(
+    _: Admin,
+    db: web::Data<Addr<DbExecutor>>,
+    server: web::Data<Addr<ChatServer>>,
+    path: web::Path<(String, u64)>,
+) -> Result<HttpResponse, ApiError> {
+    let (group, number) = path.into_inner();
+    info!("Admin deleting message {} of group {}", number, group);
+
+    let message_group = group.to_owned();
+    let deleted = run_query(&db, move |storage| {
+        storage
+            .remove_message_with_number(message_group, number)
+            .map_err(|e| db_error(e, "Failed to delete the message"))
+    })
+    .await?;
+
+    if deleted == 0 {
+        return Err(ApiError(ErrorData::new(
+            ErrorCode::NotFound,
+            "The message does not exist",
+        )));
+    }
+
+    server.do_send(MessagesRemoved {
+        group,
+        message_numbers: vec![number],
+    });
+
+    Ok(HttpResponse::Ok().json(Deleted { deleted }))
+}
+
+/// Removes every message of the conversation
+async fn delete_message_group(
+    _: Admin,
+    db: web::Data<Addr<DbExecutor>>,
+    server: web::Data<Addr<ChatServer>>,
+    group: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let group = group.into_inner();
+    info!("Admin deleting every message of group {}", group);
+
+    let message_group = group.to_owned();
+    let message_numbers = run_query(&db, move |storage| {
+        storage
+            .remove_message_group(message_group)
+            .map_err(|e| db_error(e, "Failed to delete the messages"))
+    })
+    .await?;
+
+    let deleted = message_numbers.len();
+    if deleted > 0 {
+        server.do_send(MessagesRemoved {
+            group,
+            message_numbers,
+        });
+    }
+
+    Ok(HttpResponse::Ok().json(Deleted { deleted }))
+}
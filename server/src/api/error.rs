@@ -0,0 +1,42 @@
+use actix_web::http::header::RETRY_AFTER;
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use chirp_protocol::{ErrorCode, ErrorData};
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+/// Error of a failed HTTP API request. Sent back as the JSON of the ErrorData
+#[derive(Debug)]
+pub struct ApiError(pub ErrorData);
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{}", self.0.message)
+    }
+}
+
+impl From<ErrorData> for ApiError {
+    fn from(error_data: ErrorData) -> Self {
+        ApiError(error_data)
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self.0.code {
+            ErrorCode::InvalidRequest | ErrorCode::InvalidData => StatusCode::BAD_REQUEST,
+            ErrorCode::InvalidToken | ErrorCode::NotAuthenticated => StatusCode::UNAUTHORIZED,
+            ErrorCode::AccountDisabled => StatusCode::FORBIDDEN,
+            ErrorCode::NotFound => StatusCode::NOT_FOUND,
+            ErrorCode::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+            ErrorCode::ServerError => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        if let Some(retry_after) = self.0.retry_after {
+            response.insert_header((RETRY_AFTER, retry_after));
+        }
+        response.json(&self.0)
+    }
+}
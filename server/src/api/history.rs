@@ -0,0 +1,428 @@
+use actix::Addr;
+use actix_web::dev::Payload;
+use actix_web::http::header::AUTHORIZATION;
+use actix_web::{web, FromRequest, HttpRequest, HttpResponse};
+use chirp_protocol::{ErrorCode, ErrorData, MessageData};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::pin::Pin;
+use tracing::{error, info};
+
+use crate::api::{run_query, ApiError};
+use crate::db::{db_error, DbExecutor, Storage};
+use crate::server::{
+    get_enabled_user, verify_device_token, with_reactions, ChatServer, PostMessage,
+};
+use crate::utils::message_group_members;
+
+// Number of message numbers covered when the request does not set a limit
+const DEFAULT_MESSAGE_LIMIT: u64 = 50;
+const MAX_MESSAGE_LIMIT: u64 = 500;
+// Message numbers are saved as INT
+const MAX_MESSAGE_NUMBER: u64 = i32::MAX as u64;
+
+/// The user a history API request was authenticated as. The request has to be sent with
+/// `Authorization: Bearer <user ID>:<user token>`
+struct ApiUser {
+    user_id: u64,
+}
+
+impl FromRequest for ApiUser {
+    type Error = ApiError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let credentials = req
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .and_then(|value| value.split_once(':'))
+            .and_then(|(user_id, token)| Some((user_id.parse::<u64>().ok()?, token.to_string())));
+        let db = req.app_data::<web::Data<Addr<DbExecutor>>>().cloned();
+
+        Box::pin(async move {
+            let Some((user_id, user_token)) = credentials else {
+                return Err(ApiError(ErrorData::new(
+                    ErrorCode::NotAuthenticated,
+                    "Expected a bearer token in the form <user ID>:<user token>",
+                )));
+            };
+            let Some(db) = db else {
+                error!("The DB executor is missing from the app data");
+                return Err(ApiError(ErrorData::new(
+                    ErrorCode::ServerError,
+                    "The database is unavailable",
+                )));
+            };
+
+            run_query(&db, move |storage| {
+                verify_device_token(storage, user_id, &user_token)?;
+                get_enabled_user(storage, user_id)
+            })
+            .await?;
+
+            Ok(ApiUser { user_id })
+        })
+    }
+}
+
+/// Where the page of the history starts. Numbers and times are exclusive so a message sent
+/// exactly at before_time or after_time is not returned. The latest messages are returned if none is given
+#[derive(Deserialize)]
+struct HistoryQuery {
+    before: Option<u64>,
+    after: Option<u64>,
+    before_time: Option<DateTime<Utc>>,
+    after_time: Option<DateTime<Utc>>,
+    limit: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct NewApiMessage {
+    to_user: u64,
+    message: String,
+}
+
+#[derive(Serialize)]
+struct Conversation {
+    message_group: String,
+    // The other user of the conversation. Same as the requester for messages to self
+    user_id: u64,
+    last_message_number: u64,
+}
+
+/// Adds the history routes under /api
+pub fn configure_history(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/api")
+            .route("/conversations", web::get().to(list_conversations))
+            .route("/messages", web::post().to(post_message))
+            .route("/messages/{group}", web::get().to(get_history)),
+    );
+}
+
+/// Every conversation the user has a message in
+async fn list_conversations(
+    user: ApiUser,
+    db: web::Data<Addr<DbExecutor>>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = user.user_id;
+
+    let groups = run_query(&db, move |storage| {
+        storage
+            .get_user_message_groups(user_id)
+            .map_err(|e| db_error(e, "Failed to get the conversations"))
+    })
+    .await?;
+
+    let conversations: Vec<Conversation> = groups
+        .into_iter()
+        .filter_map(|(message_group, last_message_number)| {
+            let (id_1, id_2) = message_group_members(&message_group)?;
+            Some(Conversation {
+                user_id: if id_1 == user_id { id_2 } else { id_1 },
+                message_group,
+                last_message_number,
+            })
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(conversations))
+}
+
+/// A page of the history of a conversation the user is part of in descending order.
+/// Deleted messages are skipped so a page can have fewer messages than the limit
+async fn get_history(
+    user: ApiUser,
+    db: web::Data<Addr<DbExecutor>>,
+    group: web::Path<String>,
+    query: web::Query<HistoryQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let group = group.into_inner();
+    let query = query.into_inner();
+
+    match message_group_members(&group) {
+        Some((id_1, id_2)) if id_1 == user.user_id || id_2 == user.user_id => {}
+        _ => {
+            return Err(ApiError(ErrorData::new(
+                ErrorCode::NotFound,
+                "The conversation does not exist",
+            )))
+        }
+    }
+
+    if [query.after, query.before]
+        .into_iter()
+        .flatten()
+        .any(|number| number > MAX_MESSAGE_NUMBER)
+    {
+        return Err(ApiError(ErrorData::new(
+            ErrorCode::InvalidData,
+            &format!("Message numbers can not be bigger than {MAX_MESSAGE_NUMBER}"),
+        )));
+    }
+
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_MESSAGE_LIMIT)
+        .min(MAX_MESSAGE_LIMIT);
+
+    // Times are only used when no number or earlier option is given
+    let after_time = query.after_time.filter(|_| query.after.is_none());
+    let before_time = query
+        .before_time
+        .filter(|_| query.after.is_none() && query.after_time.is_none() && query.before.is_none());
+
+    let messages = run_query(&db, move |storage| {
+        let time_number = |storage: &mut dyn Storage, time: DateTime<Utc>| {
+            storage
+                .get_message_number_before(group.to_owned(), time.naive_utc())
+                .map_err(|e| db_error(e, "Failed to get the messages"))
+        };
+
+        // get_messages_from_number excludes start_at and includes end_at
+        let (start_at, end_at) = if let Some(after) = query.after {
+            (after, after.saturating_add(limit).min(MAX_MESSAGE_NUMBER))
+        } else if let Some(after_time) = query.after_time {
+            let after = time_number(storage, after_time)?;
+            (after, after.saturating_add(limit).min(MAX_MESSAGE_NUMBER))
+        } else {
+            let end_at = if let Some(before) = query.before {
+                before.saturating_sub(1)
+            } else if let Some(before_time) = query.before_time {
+                time_number(storage, before_time)?
+            } else {
+                storage.get_last_message_number(group.to_owned())
+            };
+            (end_at.saturating_sub(limit), end_at)
+        };
+
+        let mut messages = storage
+            .get_messages_from_number(group, start_at, end_at)
+            .map_err(|e| db_error(e, "Failed to get the messages"))?;
+
+        // The creation time is given by the client so a lower number does not always mean an
+        // earlier message
+        messages.retain(|message| {
+            after_time.is_none_or(|time| message.created_at > time.naive_utc())
+                && before_time.is_none_or(|time| message.created_at < time.naive_utc())
+        });
+
+        let message_ids = messages
+            .iter()
+            .map(|message| message.message_id as u64)
+            .collect();
+        let reactions = storage
+            .get_message_reactions(message_ids)
+            .map_err(|e| db_error(e, "Failed to get the reactions"))?;
+
+        Ok(with_reactions(messages, reactions))
+    })
+    .await?;
+
+    Ok(HttpResponse::Ok().json(messages))
+}
+
+/// Sends a message the same way as a WS session. The message number is picked by the server
+async fn post_message(
+    req: HttpRequest,
+    user: ApiUser,
+    server: web::Data<Addr<ChatServer>>,
+    new_message: web::Json<NewApiMessage>,
+) -> Result<HttpResponse, ApiError> {
+    let new_message = new_message.into_inner();
+
+    info!(
+        "Posting message from {} to {} through the API",
+        user.user_id, new_message.to_user
+    );
+
+    let message_data = MessageData::new_incomplete(
+        Utc::now().format("%Y-%m-%d %H:%M:%S%.3f %z").to_string(),
+        user.user_id,
+        new_message.to_user,
+        new_message.message,
+    );
+
+    let saved_message = server
+        .send(PostMessage {
+            from_user_id: user.user_id,
+            message_data,
+            ip: req.peer_addr().map(|address| address.ip()),
+        })
+        .await
+        .map_err(|e| {
+            error!("Failed to reach the chat server: {e}");
+            ErrorData::new(ErrorCode::ServerError, "The chat server is unavailable")
+        })??;
+
+    Ok(HttpResponse::Created().json(saved_message))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix::SyncArbiter;
+    use actix_web::http::header::AUTHORIZATION;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, web, App};
+    use chrono::NaiveDate;
+    use serde_json::Value;
+
+    use super::{configure_history, MAX_MESSAGE_NUMBER};
+    use crate::db::{
+        DbExecutor, MemoryStorage, MessageReaction, NewDevice, NewMessage, Storage, StorageBackend,
+        User,
+    };
+    use crate::utils::create_message_group;
+
+    /// Users 1 and 2 have messages 1 to 5, one minute apart starting at 10:01. User 3 has none
+    fn history_storage() -> StorageBackend {
+        let mut storage = MemoryStorage::new();
+        for user_id in 1..=3 {
+            storage
+                .create_new_user(
+                    User::new().update_id(user_id),
+                    NewDevice::new(user_id, "Device", &format!("token-{user_id}")),
+                )
+                .unwrap();
+        }
+
+        let group = create_message_group(1, 2);
+        for number in 1..=5 {
+            let created_at = NaiveDate::from_ymd_opt(2023, 12, 1)
+                .unwrap()
+                .and_hms_opt(10, number as u32, 0)
+                .unwrap();
+            let message = storage
+                .create_new_message(NewMessage::new(
+                    group.to_owned(),
+                    number,
+                    format!("Message {number}"),
+                    1,
+                    2,
+                    created_at,
+                ))
+                .unwrap();
+
+            if number == 2 {
+                storage
+                    .set_message_reaction(MessageReaction::new(
+                        message.message_id,
+                        2,
+                        String::from("👍"),
+                        created_at,
+                    ))
+                    .unwrap();
+            }
+        }
+        StorageBackend::Memory(storage)
+    }
+
+    /// Sends a GET request and returns the status with the JSON body
+    async fn get(uri: &str, authorization: Option<&str>) -> (StatusCode, Value) {
+        let backend = history_storage();
+        let db = SyncArbiter::start(1, move || DbExecutor(backend.clone()));
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(db))
+                .configure(configure_history),
+        )
+        .await;
+
+        let mut request = test::TestRequest::get().uri(uri);
+        if let Some(authorization) = authorization {
+            request = request.insert_header((AUTHORIZATION, authorization));
+        }
+        let response = test::call_service(&app, request.to_request()).await;
+        let status = response.status();
+        (status, test::read_body_json(response).await)
+    }
+
+    /// Gets the page as user 1 and returns the message numbers
+    async fn page_numbers(query: &str) -> Vec<u64> {
+        let (status, body) = get(
+            &format!("/api/messages/1@2{query}"),
+            Some("Bearer 1:token-1"),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        body.as_array()
+            .unwrap()
+            .iter()
+            .map(|message| message["message_number"].as_u64().unwrap())
+            .collect()
+    }
+
+    #[actix_rt::test]
+    async fn bearer_token_is_required() {
+        for authorization in [
+            None,
+            Some("Bearer 1"),
+            Some("Bearer one:token-1"),
+            Some("Basic 1:token-1"),
+            Some("Bearer 1:token-2"),
+            Some("Bearer 4:token-4"),
+        ] {
+            let (status, _) = get("/api/messages/1@2", authorization).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED, "{authorization:?}");
+        }
+
+        let (status, _) = get("/api/messages/1@2", Some("Bearer 2:token-2")).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn only_members_can_read_a_conversation() {
+        let (status, _) = get("/api/messages/1@2", Some("Bearer 3:token-3")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (status, _) = get("/api/messages/not-a-group", Some("Bearer 1:token-1")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[actix_rt::test]
+    async fn pages_exclude_their_bounds() {
+        assert_eq!(page_numbers("").await, vec![5, 4, 3, 2, 1]);
+        assert_eq!(page_numbers("?limit=2").await, vec![5, 4]);
+        assert_eq!(page_numbers("?before=3").await, vec![2, 1]);
+        assert_eq!(page_numbers("?after=3").await, vec![5, 4]);
+        assert_eq!(page_numbers("?after=3&limit=1").await, vec![4]);
+        assert_eq!(page_numbers("?after=5").await, Vec::<u64>::new());
+
+        // Message 3 was sent exactly at 10:03
+        assert_eq!(
+            page_numbers("?before_time=2023-12-01T10:03:00Z").await,
+            vec![2, 1]
+        );
+        assert_eq!(
+            page_numbers("?after_time=2023-12-01T10:03:00Z").await,
+            vec![5, 4]
+        );
+    }
+
+    #[actix_rt::test]
+    async fn messages_include_reactions() {
+        let (_, body) = get(
+            "/api/messages/1@2?after=1&limit=1",
+            Some("Bearer 1:token-1"),
+        )
+        .await;
+        assert_eq!(body[0]["reactions"][0]["emoji"], "👍");
+        assert_eq!(body[0]["reactions"][0]["user_id"], 2);
+    }
+
+    #[actix_rt::test]
+    async fn too_big_message_numbers_are_rejected() {
+        let uri = format!("/api/messages/1@2?before={}", MAX_MESSAGE_NUMBER + 1);
+        let (status, body) = get(&uri, Some("Bearer 1:token-1")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "invalid-data");
+
+        assert_eq!(
+            page_numbers(&format!("?after={MAX_MESSAGE_NUMBER}")).await,
+            Vec::<u64>::new()
+        );
+    }
+}
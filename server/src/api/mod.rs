@@ -0,0 +1,27 @@
+mod admin;
+mod error;
+mod history;
+
+use actix::Addr;
+use chirp_protocol::{ErrorCode, ErrorData};
+use tracing::error;
+
+use crate::db::{DbExecutor, RunQuery, Storage};
+
+pub use admin::configure_admin;
+pub use error::ApiError;
+pub use history::configure_history;
+
+/// Runs the query on the DB executor from an HTTP route
+async fn run_query<T, Q>(db: &Addr<DbExecutor>, query: Q) -> Result<T, ApiError>
+where
+    T: Send + 'static,
+    Q: FnOnce(&mut dyn Storage) -> Result<T, ErrorData> + Send + 'static,
+{
+    let result = db.send(RunQuery(Box::new(query))).await.map_err(|e| {
+        error!("Failed to reach the DB executor: {e}");
+        ErrorData::new(ErrorCode::ServerError, "The database is unavailable")
+    })?;
+
+    Ok(result?)
+}
@@ -0,0 +1,528 @@
+use chirp_protocol::{ContentLimits, NAME_COLUMN_LENGTH};
+use clap::Parser;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+use tracing::Level;
+
+/// Command line flags. Any flag that is given takes priority over the config file
+#[derive(Parser)]
+#[command(name = "chirp-server", version, about = "WebSocket server for Chirp")]
+pub struct Cli {
+    /// Path to the TOML config file
+    #[arg(short, long)]
+    pub config: Option<PathBuf>,
+    /// Address to listen on. Can be used multiple times
+    #[arg(short, long)]
+    pub bind: Vec<String>,
+    /// Path to the PEM encoded TLS certificate chain
+    #[arg(long)]
+    pub cert: Option<PathBuf>,
+    /// Path to the PEM encoded PKCS8 TLS private key
+    #[arg(long)]
+    pub key: Option<PathBuf>,
+    /// Seconds between each ping sent to the clients
+    #[arg(long)]
+    pub heartbeat_interval: Option<u64>,
+    /// Seconds without a response from a client before it gets disconnected
+    #[arg(long)]
+    pub client_timeout: Option<u64>,
+    /// Database to use. Falls back to the DATABASE_URL env variable
+    #[arg(long)]
+    pub database_url: Option<String>,
+    /// One of trace, debug, info, warn or error
+    #[arg(long)]
+    pub log_level: Option<String>,
+}
+
+/// A certificate that is used only for the given server names
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SniCertificate {
+    pub server_names: Vec<String>,
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    // Used for every server name that has no certificate in certificates
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    // Seconds between each check for modified certificate files. 0 to only reload on SIGHUP
+    pub reload_interval: u64,
+    pub certificates: Vec<SniCertificate>,
+    // Client certificates are verified with this CA if given
+    pub client_ca: Option<PathBuf>,
+    // Whether clients without a certificate are refused when client_ca is given
+    pub client_cert_required: bool,
+    // {Common name of a client certificate subject: User ID the certificate belongs to}
+    pub client_users: HashMap<String, u64>,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        TlsConfig {
+            cert: PathBuf::from("./server/src/tls_cert_key/cert.pem"),
+            key: PathBuf::from("./server/src/tls_cert_key/key.pem"),
+            reload_interval: 60,
+            certificates: Vec::new(),
+            client_ca: None,
+            client_cert_required: true,
+            client_users: HashMap::new(),
+        }
+    }
+}
+
+/// Timings used by every WS session for checking if the client is still connected. In seconds
+#[derive(Deserialize, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct HeartbeatConfig {
+    pub interval: u64,
+    pub client_timeout: u64,
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        HeartbeatConfig {
+            interval: 5,
+            client_timeout: 10,
+        }
+    }
+}
+
+impl HeartbeatConfig {
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval)
+    }
+
+    pub fn client_timeout(&self) -> Duration {
+        Duration::from_secs(self.client_timeout)
+    }
+}
+
+/// A token bucket. Holds up to burst requests and gets per_second requests back every second
+#[derive(Deserialize, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+pub struct Limit {
+    pub burst: u32,
+    pub per_second: f64,
+}
+
+impl Limit {
+    const fn new(burst: u32, per_second: f64) -> Self {
+        Limit { burst, per_second }
+    }
+}
+
+/// Limits of a type of request. Each WS session, account and source IP gets its own buckets
+#[derive(Deserialize, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+pub struct RequestLimits {
+    pub session: Limit,
+    pub account: Limit,
+    pub ip: Limit,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    pub enabled: bool,
+    // Limited requests in a row before the WS session gets disconnected. 0 to never disconnect
+    pub max_violations: u32,
+    // Sending messages
+    pub messaging: RequestLimits,
+    // Name and image updates
+    pub profile: RequestLimits,
+    // Creating users and pairing devices
+    pub account_creation: RequestLimits,
+    // Authenticating with a user token. The IP limit is what stops guessing tokens with new sessions
+    pub authentication: RequestLimits,
+    // Typing notifications
+    pub typing: RequestLimits,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            enabled: true,
+            max_violations: 20,
+            messaging: RequestLimits {
+                session: Limit::new(20, 5.0),
+                account: Limit::new(40, 10.0),
+                ip: Limit::new(100, 25.0),
+            },
+            profile: RequestLimits {
+                session: Limit::new(5, 0.2),
+                account: Limit::new(10, 0.2),
+                ip: Limit::new(20, 1.0),
+            },
+            account_creation: RequestLimits {
+                session: Limit::new(3, 0.05),
+                account: Limit::new(5, 0.05),
+                ip: Limit::new(10, 0.02),
+            },
+            authentication: RequestLimits {
+                session: Limit::new(3, 0.1),
+                account: Limit::new(5, 0.1),
+                ip: Limit::new(10, 0.1),
+            },
+            typing: RequestLimits {
+                session: Limit::new(10, 2.0),
+                account: Limit::new(20, 4.0),
+                ip: Limit::new(50, 10.0),
+            },
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub bind: Vec<String>,
+    pub database_url: Option<String>,
+    pub log_level: String,
+    // Bearer token of the admin API. The API is disabled if not set
+    pub admin_token: Option<String>,
+    pub tls: TlsConfig,
+    pub heartbeat: HeartbeatConfig,
+    pub rate_limit: RateLimitConfig,
+    pub validation: ContentLimits,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            bind: vec![String::from("127.0.0.1:8080")],
+            database_url: None,
+            log_level: String::from("info"),
+            admin_token: None,
+            tls: TlsConfig::default(),
+            heartbeat: HeartbeatConfig::default(),
+            rate_limit: RateLimitConfig::default(),
+            validation: ContentLimits::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Reads the config file if given, applies the command line flags on top of it and validates the result
+    pub fn load() -> Result<Self, String> {
+        let cli = Cli::parse();
+
+        let mut config = if let Some(path) = &cli.config {
+            let content = fs::read_to_string(path)
+                .map_err(|e| format!("Failed to read config file {}: {e}", path.display()))?;
+            toml::from_str(&content)
+                .map_err(|e| format!("Failed to parse config file {}: {e}", path.display()))?
+        } else {
+            Config::default()
+        };
+
+        if !cli.bind.is_empty() {
+            config.bind = cli.bind;
+        }
+        if let Some(cert) = cli.cert {
+            config.tls.cert = cert;
+        }
+        if let Some(key) = cli.key {
+            config.tls.key = key;
+        }
+        if let Some(interval) = cli.heartbeat_interval {
+            config.heartbeat.interval = interval;
+        }
+        if let Some(timeout) = cli.client_timeout {
+            config.heartbeat.client_timeout = timeout;
+        }
+        if let Some(level) = cli.log_level {
+            config.log_level = level;
+        }
+        config.database_url = cli
+            .database_url
+            .or(config.database_url)
+            .or_else(|| env::var("DATABASE_URL").ok());
+        config.admin_token = config.admin_token.or_else(|| env::var("ADMIN_TOKEN").ok());
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.bind.is_empty() {
+            return Err(String::from("At least one bind address is required"));
+        }
+        self.bind_addresses()?;
+
+        let Some(database_url) = &self.database_url else {
+            return Err(String::from(
+                "No database URL found. Set database_url or DATABASE_URL",
+            ));
+        };
+        let scheme = database_url.split_once("://").map(|(scheme, _)| scheme);
+        match scheme {
+            Some("postgres" | "postgresql" | "memory") => {}
+            #[cfg(feature = "sqlite")]
+            Some("sqlite") => {}
+            #[cfg(not(feature = "sqlite"))]
+            Some("sqlite") => {
+                return Err(String::from(
+                    "sqlite:// database URLs need the server to be built with the sqlite feature",
+                ))
+            }
+            _ => {
+                return Err(String::from(
+                    "The database URL must start with postgres://, postgresql://, sqlite:// or memory://",
+                ))
+            }
+        }
+
+        if self.heartbeat.interval == 0 {
+            return Err(String::from("Heartbeat interval must be at least 1 second"));
+        }
+        if self.heartbeat.client_timeout <= self.heartbeat.interval {
+            return Err(String::from(
+                "Client timeout must be longer than the heartbeat interval",
+            ));
+        }
+
+        self.log_level()?;
+
+        if self.validation.max_name_length == 0
+            || self.validation.max_name_length > NAME_COLUMN_LENGTH
+        {
+            return Err(format!(
+                "max_name_length must be between 1 and {NAME_COLUMN_LENGTH}"
+            ));
+        }
+        if self.validation.max_message_length == 0 {
+            return Err(String::from("max_message_length must be at least 1"));
+        }
+
+        let rate_limit = &self.rate_limit;
+        for (name, limits) in [
+            ("messaging", &rate_limit.messaging),
+            ("profile", &rate_limit.profile),
+            ("account_creation", &rate_limit.account_creation),
+            ("authentication", &rate_limit.authentication),
+            ("typing", &rate_limit.typing),
+        ] {
+            for limit in [&limits.session, &limits.account, &limits.ip] {
+                if limit.burst == 0 || limit.per_second <= 0.0 {
+                    return Err(format!(
+                        "Rate limits of {name} need a burst of at least 1 and a positive per_second"
+                    ));
+                }
+            }
+        }
+
+        if self
+            .admin_token
+            .as_ref()
+            .is_some_and(|token| token.len() < 32)
+        {
+            return Err(String::from(
+                "The admin token must be at least 32 characters long",
+            ));
+        }
+
+        let mut tls_files = vec![&self.tls.cert, &self.tls.key];
+        for sni_cert in self.tls.certificates.iter() {
+            if sni_cert.server_names.is_empty() {
+                return Err(format!(
+                    "TLS certificate {} has no server names",
+                    sni_cert.cert.display()
+                ));
+            }
+            tls_files.push(&sni_cert.cert);
+            tls_files.push(&sni_cert.key);
+        }
+        if let Some(client_ca) = &self.tls.client_ca {
+            tls_files.push(client_ca);
+        } else if !self.tls.client_users.is_empty() {
+            return Err(String::from("client_users requires client_ca to be set"));
+        }
+
+        for path in tls_files {
+            if !path.is_file() {
+                return Err(format!("TLS file {} does not exist", path.display()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Every address the server listens on
+    pub fn bind_addresses(&self) -> Result<Vec<SocketAddr>, String> {
+        let mut addresses = Vec::new();
+        for bind in self.bind.iter() {
+            let resolved = bind
+                .to_socket_addrs()
+                .map_err(|e| format!("Invalid bind address {bind}: {e}"))?;
+            addresses.extend(resolved);
+        }
+        Ok(addresses)
+    }
+
+    pub fn log_level(&self) -> Result<Level, String> {
+        Level::from_str(&self.log_level)
+            .map_err(|_| format!("Invalid log level {}", self.log_level))
+    }
+
+    pub fn database_url(&self) -> &str {
+        self.database_url.as_deref().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The default config with a database and the TLS files of the repo so it passes validation
+    fn valid_config() -> Config {
+        let tls_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("src/tls_cert_key");
+        let mut config = Config {
+            database_url: Some(String::from("postgres://localhost/chirp")),
+            ..Config::default()
+        };
+        config.tls.cert = tls_dir.join("cert.pem");
+        config.tls.key = tls_dir.join("key.pem");
+        config
+    }
+
+    #[test]
+    fn default_config_with_database_is_valid() {
+        assert!(valid_config().validate().is_ok());
+    }
+
+    #[test]
+    fn example_config_parses() {
+        let content = include_str!("../config.example.toml");
+        assert!(toml::from_str::<Config>(content).is_ok());
+    }
+
+    #[test]
+    fn database_url_is_required() {
+        let config = Config {
+            database_url: None,
+            ..valid_config()
+        };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn database_url_scheme_must_be_known() {
+        for url in ["postgresql://localhost/chirp", "memory://"] {
+            let config = Config {
+                database_url: Some(String::from(url)),
+                ..valid_config()
+            };
+            assert!(config.validate().is_ok(), "{url} was rejected");
+        }
+
+        for url in ["mysql://localhost/chirp", "localhost/chirp", ""] {
+            let config = Config {
+                database_url: Some(String::from(url)),
+                ..valid_config()
+            };
+            assert!(config.validate().is_err(), "{url} was accepted");
+        }
+
+        let config = Config {
+            database_url: Some(String::from("sqlite://chirp.db")),
+            ..valid_config()
+        };
+        assert_eq!(config.validate().is_ok(), cfg!(feature = "sqlite"));
+    }
+
+    #[test]
+    fn bind_address_is_required() {
+        let config = Config {
+            bind: Vec::new(),
+            ..valid_config()
+        };
+        assert!(config.validate().is_err());
+
+        let config = Config {
+            bind: vec![String::from("not an address")],
+            ..valid_config()
+        };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn client_timeout_must_be_longer_than_interval() {
+        let mut config = valid_config();
+        config.heartbeat.client_timeout = config.heartbeat.interval;
+        assert!(config.validate().is_err());
+
+        config.heartbeat.interval = 0;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn log_level_must_be_known() {
+        let config = Config {
+            log_level: String::from("loud"),
+            ..valid_config()
+        };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn name_length_must_fit_the_column() {
+        for (length, valid) in [(0, false), (1, true), (NAME_COLUMN_LENGTH, true)] {
+            let mut config = valid_config();
+            config.validation.max_name_length = length;
+            assert_eq!(config.validate().is_ok(), valid, "{length}");
+        }
+
+        let mut config = valid_config();
+        config.validation.max_name_length = NAME_COLUMN_LENGTH + 1;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn rate_limits_must_allow_requests() {
+        let mut config = valid_config();
+        config.rate_limit.account_creation.ip.burst = 0;
+        assert!(config.validate().is_err());
+
+        let mut config = valid_config();
+        config.rate_limit.messaging.session.per_second = 0.0;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn admin_token_must_be_long() {
+        let config = Config {
+            admin_token: Some(String::from("short")),
+            ..valid_config()
+        };
+        assert!(config.validate().is_err());
+
+        let config = Config {
+            admin_token: Some("a".repeat(32)),
+            ..valid_config()
+        };
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn tls_files_must_exist() {
+        let mut config = valid_config();
+        config.tls.key = PathBuf::from("missing.pem");
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn client_users_require_client_ca() {
+        let mut config = valid_config();
+        config.tls.client_users.insert(String::from("alice"), 1);
+        assert!(config.validate().is_err());
+    }
+}
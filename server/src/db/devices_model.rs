@@ -0,0 +1,98 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+
+use crate::db::schema::devices;
+use crate::utils::{generate_token_salt, hash_user_token, token_matches};
+
+#[derive(Queryable, Selectable, Identifiable, Clone)]
+#[diesel(primary_key(device_id))]
+pub struct Device {
+    pub device_id: i32,
+    pub user_id: i32,
+    pub device_name: String,
+    pub token_hash: String,
+    pub token_salt: String,
+    pub created_at: NaiveDateTime,
+}
+
+impl Device {
+    /// Whether the token is the one this device's hash was created from
+    pub fn verify_token(&self, token: &str) -> bool {
+        token_matches(&hash_user_token(token, &self.token_salt), &self.token_hash)
+    }
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = devices)]
+pub struct NewDevice {
+    pub user_id: i32,
+    pub device_name: String,
+    pub token_hash: String,
+    pub token_salt: String,
+}
+
+impl NewDevice {
+    /// Saves the salted hash of the token. The token itself is never stored
+    pub fn new(user_id: u64, device_name: &str, token: &str) -> Self {
+        let token_salt = generate_token_salt();
+        NewDevice {
+            user_id: user_id as i32,
+            device_name: device_name.to_string(),
+            token_hash: hash_user_token(token, &token_salt),
+            token_salt,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::NewDevice;
+    use crate::db::{MemoryStorage, Storage, User};
+    use crate::utils::{generate_token_salt, hash_user_token};
+
+    fn storage_with_device(token: &str) -> MemoryStorage {
+        let mut storage = MemoryStorage::new();
+        storage
+            .create_new_user(User::new().update_id(1), NewDevice::new(1, "Device", token))
+            .unwrap();
+        storage
+    }
+
+    #[test]
+    fn wrong_token_of_same_length_is_rejected() {
+        let mut storage = storage_with_device("token-a");
+        let device = storage.get_user_devices(1).unwrap().remove(0);
+        assert!(device.verify_token("token-a"));
+        assert!(!device.verify_token("token-b"));
+        assert!(!device.verify_token(""));
+    }
+
+    #[test]
+    fn rotated_token_stops_verifying() {
+        let mut storage = storage_with_device("old-token");
+        let device = storage.get_user_devices(1).unwrap().remove(0);
+
+        let salt = generate_token_salt();
+        let hash = hash_user_token("new-token", &salt);
+        assert_eq!(
+            storage
+                .update_device_token(device.device_id as u64, &hash, &salt)
+                .unwrap(),
+            1
+        );
+
+        let device = storage.get_user_devices(1).unwrap().remove(0);
+        assert!(!device.verify_token("old-token"));
+        assert!(device.verify_token("new-token"));
+    }
+
+    #[test]
+    fn revoked_device_is_rejected() {
+        let mut storage = storage_with_device("token");
+        let device = storage.get_user_devices(1).unwrap().remove(0);
+        assert_eq!(storage.delete_device(device.device_id as u64).unwrap(), 1);
+
+        let devices = storage.get_user_devices(1).unwrap();
+        assert!(!devices.iter().any(|device| device.verify_token("token")));
+    }
+}
@@ -0,0 +1,138 @@
+use actix::prelude::*;
+use chirp_protocol::{ErrorCode, ErrorData};
+use diesel::pg::PgConnection;
+use diesel::r2d2::{ConnectionManager, Pool, PoolError};
+use diesel::result::Error as DieselError;
+use std::time::Instant;
+use tracing::{error, info};
+
+#[cfg(feature = "sqlite")]
+use crate::db::{create_sqlite_pool, SqlitePool};
+use crate::db::{MemoryStorage, Storage};
+use crate::metrics::{DB_QUERY_DURATION, DB_QUERY_ERRORS};
+
+pub type DbPool = Pool<ConnectionManager<PgConnection>>;
+
+type Query<T> = Box<dyn FnOnce(&mut dyn Storage) -> Result<T, ErrorData> + Send>;
+
+/// Creates the connection pool that is shared by every DbExecutor
+pub fn create_pool(database_url: &str, max_size: u32) -> Result<DbPool, String> {
+    let manager = ConnectionManager::<PgConnection>::new(database_url);
+
+    Pool::builder()
+        .max_size(max_size)
+        .build(manager)
+        .map_err(|e| format!("Failed to connect to the database: {e}"))
+}
+
+/// The storage the queries of the DbExecutor run on
+#[derive(Clone)]
+pub enum StorageBackend {
+    Postgres(DbPool),
+    #[cfg(feature = "sqlite")]
+    Sqlite(SqlitePool),
+    Memory(MemoryStorage),
+}
+
+impl StorageBackend {
+    /// Selects the storage with the scheme of the database URL. `memory://` keeps all data in memory,
+    /// `sqlite://<path>` uses a SQLite file and anything else is used as a Postgres URL
+    pub fn new(database_url: &str, pool_size: u32) -> Result<Self, String> {
+        if let Some(path) = database_url.strip_prefix("sqlite://") {
+            #[cfg(feature = "sqlite")]
+            {
+                info!("Using SQLite storage at {}", path);
+                return create_sqlite_pool(path).map(StorageBackend::Sqlite);
+            }
+            #[cfg(not(feature = "sqlite"))]
+            return Err(format!(
+                "Unable to use {}. The server must be built with the sqlite feature",
+                path
+            ));
+        }
+
+        if database_url.starts_with("memory://") {
+            info!("Using in-memory storage. No data will be saved after the server stops");
+            Ok(StorageBackend::Memory(MemoryStorage::new()))
+        } else {
+            create_pool(database_url, pool_size).map(StorageBackend::Postgres)
+        }
+    }
+}
+
+/// Runs the blocking DB operations on its own thread so the ChatServer never waits for the DB.
+/// Started with a SyncArbiter so multiple queries can run at the same time
+pub struct DbExecutor(pub StorageBackend);
+
+impl Actor for DbExecutor {
+    type Context = SyncContext<Self>;
+}
+
+/// Runs the query on the storage and returns the result
+pub struct RunQuery<T>(pub Query<T>);
+
+impl<T: 'static> Message for RunQuery<T> {
+    type Result = Result<T, ErrorData>;
+}
+
+impl<T: Send + 'static> Handler<RunQuery<T>> for DbExecutor {
+    type Result = Result<T, ErrorData>;
+
+    fn handle(&mut self, msg: RunQuery<T>, _: &mut Self::Context) -> Self::Result {
+        let started = Instant::now();
+
+        let result = match &mut self.0 {
+            StorageBackend::Postgres(pool) => {
+                let mut conn = pool.get().map_err(pool_error)?;
+                (msg.0)(&mut *conn)
+            }
+            #[cfg(feature = "sqlite")]
+            StorageBackend::Sqlite(pool) => {
+                let mut conn = pool.get().map_err(pool_error)?;
+                (msg.0)(&mut *conn)
+            }
+            StorageBackend::Memory(storage) => (msg.0)(storage),
+        };
+
+        DB_QUERY_DURATION.observe(started.elapsed().as_secs_f64());
+        result
+    }
+}
+
+/// Logs the DB error and converts it to an error that can be sent to the client
+pub fn db_error(e: DieselError, message: &str) -> ErrorData {
+    error!("DB operation failed: {e}");
+    DB_QUERY_ERRORS.inc();
+    ErrorData::new(ErrorCode::ServerError, message)
+}
+
+/// Logs the pool error and converts it to an error that can be sent to the client
+fn pool_error(e: PoolError) -> ErrorData {
+    error!("Failed to get a DB connection from the pool: {e}");
+    DB_QUERY_ERRORS.inc();
+    ErrorData::new(ErrorCode::ServerError, "The database is unavailable")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StorageBackend;
+
+    #[test]
+    fn unusable_storage_is_an_error() {
+        // Fails to open the file with the sqlite feature and fails to select SQLite without it
+        let result = StorageBackend::new("sqlite:///nonexistent/dir/chirp.db", 1);
+        assert!(result.is_err());
+
+        let result = StorageBackend::new("memory://", 1);
+        assert!(matches!(result, Ok(StorageBackend::Memory(_))));
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn sqlite_file_is_created() {
+        let path = std::env::temp_dir().join(format!("chirp-test-{}.db", std::process::id()));
+        let result = StorageBackend::new(&format!("sqlite://{}", path.display()), 1);
+        assert!(matches!(result, Ok(StorageBackend::Sqlite(_))));
+        std::fs::remove_file(path).unwrap();
+    }
+}
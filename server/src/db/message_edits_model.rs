@@ -0,0 +1,45 @@
+use chirp_protocol::MessageRevision;
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+
+use crate::db::schema::message_edits;
+use crate::db::Message;
+
+#[derive(Queryable, Selectable, Identifiable, Clone)]
+#[diesel(primary_key(edit_id))]
+pub struct MessageEdit {
+    pub edit_id: i32,
+    pub message_id: i32,
+    pub message_text: String,
+    // When this version of the message was written
+    pub created_at: NaiveDateTime,
+}
+
+impl MessageEdit {
+    /// Converts to the data that is sent to the clients
+    pub fn into_revision(self) -> MessageRevision {
+        MessageRevision {
+            message: self.message_text,
+            created_at: self.created_at.to_string(),
+        }
+    }
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = message_edits)]
+pub struct NewMessageEdit {
+    pub message_id: i32,
+    pub message_text: String,
+    pub created_at: NaiveDateTime,
+}
+
+impl NewMessageEdit {
+    /// The current version of the message that is about to be replaced. None if it was deleted
+    pub fn from_message(message: &Message) -> Option<Self> {
+        Some(NewMessageEdit {
+            message_id: message.message_id,
+            message_text: message.message_text.to_owned()?,
+            created_at: message.edited_at.unwrap_or(message.created_at),
+        })
+    }
+}
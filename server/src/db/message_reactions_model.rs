@@ -0,0 +1,33 @@
+use chirp_protocol::Reaction;
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+
+use crate::db::schema::message_reactions;
+
+#[derive(Queryable, Selectable, Insertable, Identifiable, Clone)]
+#[diesel(primary_key(message_id, user_id))]
+pub struct MessageReaction {
+    pub message_id: i32,
+    pub user_id: i32,
+    pub emoji: String,
+    pub created_at: NaiveDateTime,
+}
+
+impl MessageReaction {
+    pub fn new(message_id: i32, user_id: u64, emoji: String, created_at: NaiveDateTime) -> Self {
+        MessageReaction {
+            message_id,
+            user_id: user_id as i32,
+            emoji,
+            created_at,
+        }
+    }
+
+    /// Converts to the data that is sent to the clients
+    pub fn into_reaction(self) -> Reaction {
+        Reaction {
+            user_id: self.user_id as u64,
+            emoji: self.emoji,
+        }
+    }
+}
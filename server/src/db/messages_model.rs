@@ -1,9 +1,10 @@
+use chirp_protocol::{MessageData, MessageStatus};
 use chrono::NaiveDateTime;
 use diesel::prelude::*;
 
 use crate::db::schema::messages;
 
-#[derive(Queryable, Selectable, Identifiable)]
+#[derive(Queryable, Selectable, Identifiable, Clone)]
 #[diesel(primary_key(message_group, message_number))]
 pub struct Message {
     pub message_id: i32,
@@ -13,6 +14,43 @@ pub struct Message {
     pub message_sender: i32,
     pub message_receiver: i32,
     pub created_at: NaiveDateTime,
+    // Whether a session of the receiver got the message
+    pub delivered: bool,
+    // Whether the receiver opened the chat after getting the message
+    pub read: bool,
+    // None if the message was never edited
+    pub edited_at: Option<NaiveDateTime>,
+    // Number of the message in the same group this one replies to
+    pub reply_to: Option<i32>,
+}
+
+impl Message {
+    /// How far the message got based on the delivery and read state
+    pub fn status(&self) -> MessageStatus {
+        if self.read {
+            MessageStatus::Read
+        } else if self.delivered {
+            MessageStatus::Delivered
+        } else {
+            MessageStatus::Sent
+        }
+    }
+
+    /// Converts to the data that is sent to the clients. Deleted messages have an empty text
+    pub fn into_message_data(self) -> MessageData {
+        let status = self.status();
+        MessageData {
+            created_at: self.created_at.to_string(),
+            from_user: self.message_sender as u64,
+            to_user: self.message_receiver as u64,
+            message: self.message_text.unwrap_or_default(),
+            message_number: self.message_number as u64,
+            status,
+            edited_at: self.edited_at.map(|time| time.to_string()),
+            reply_to: self.reply_to.map(|number| number as u64),
+            reactions: Vec::new(),
+        }
+    }
 }
 
 #[derive(Insertable)]
@@ -24,15 +62,17 @@ pub struct NewMessage {
     pub message_sender: i32,
     pub message_receiver: i32,
     pub created_at: NaiveDateTime,
+    pub delivered: bool,
+    pub reply_to: Option<i32>,
 }
 
 impl NewMessage {
     pub fn new(
         message_group: String,
-        message_number: usize,
+        message_number: u64,
         message_text: String,
-        message_sender: usize,
-        message_receiver: usize,
+        message_sender: u64,
+        message_receiver: u64,
         created_at: NaiveDateTime,
     ) -> Self {
         NewMessage {
@@ -42,6 +82,8 @@ impl NewMessage {
             message_sender: message_sender as i32,
             message_receiver: message_receiver as i32,
             created_at,
+            delivered: false,
+            reply_to: None,
         }
     }
 }
@@ -1,9 +1,17 @@
+mod devices_model;
+mod executor;
+mod message_edits_model;
+mod message_reactions_model;
 mod messages_model;
 mod operations;
 mod schema;
+mod storage;
 mod users_model;
 
+pub use devices_model::*;
+pub use executor::*;
+pub use message_edits_model::*;
+pub use message_reactions_model::*;
 pub use messages_model::*;
-pub use operations::*;
-pub use schema::*;
+pub use storage::*;
 pub use users_model::*;
@@ -0,0 +1,43 @@
+use diesel::{
+    delete, update, ExpressionMethods, PgConnection, QueryDsl, QueryResult, RunQueryDsl,
+    SelectableHelper,
+};
+
+use crate::db::devices_model::{Device, NewDevice};
+use crate::db::schema::devices;
+
+pub fn create_new_device(conn: &mut PgConnection, device_data: NewDevice) -> QueryResult<Device> {
+    diesel::insert_into(devices::table)
+        .values(device_data)
+        .returning(Device::as_returning())
+        .get_result(conn)
+}
+
+pub fn get_user_devices(conn: &mut PgConnection, id: u64) -> QueryResult<Vec<Device>> {
+    use crate::db::schema::devices::dsl::*;
+
+    devices
+        .filter(user_id.eq(id as i32))
+        .order(device_id.asc())
+        .select(Device::as_select())
+        .load(conn)
+}
+
+pub fn update_device_token(
+    conn: &mut PgConnection,
+    id: u64,
+    new_hash: &str,
+    new_salt: &str,
+) -> QueryResult<usize> {
+    use crate::db::schema::devices::dsl::*;
+
+    update(devices.find(id as i32))
+        .set((token_hash.eq(new_hash), token_salt.eq(new_salt)))
+        .execute(conn)
+}
+
+pub fn delete_device(conn: &mut PgConnection, id: u64) -> QueryResult<usize> {
+    use crate::db::schema::devices::dsl::*;
+
+    delete(devices.find(id as i32)).execute(conn)
+}
@@ -0,0 +1,33 @@
+use diesel::{
+    ExpressionMethods, PgConnection, QueryDsl, QueryResult, RunQueryDsl, SelectableHelper,
+};
+
+use crate::db::message_edits_model::{MessageEdit, NewMessageEdit};
+use crate::db::schema::message_edits;
+
+pub fn create_message_edit(
+    conn: &mut PgConnection,
+    edit_data: NewMessageEdit,
+) -> QueryResult<usize> {
+    diesel::insert_into(message_edits::table)
+        .values(edit_data)
+        .execute(conn)
+}
+
+pub fn get_message_edits(conn: &mut PgConnection, id: u64) -> QueryResult<Vec<MessageEdit>> {
+    use crate::db::schema::message_edits::dsl::*;
+
+    message_edits
+        .filter(message_id.eq(id as i32))
+        .order(edit_id.asc())
+        .select(MessageEdit::as_select())
+        .load(conn)
+}
+
+pub fn delete_message_edits(conn: &mut PgConnection, id: u64) -> QueryResult<usize> {
+    use crate::db::schema::message_edits::dsl::*;
+
+    diesel::delete(message_edits)
+        .filter(message_id.eq(id as i32))
+        .execute(conn)
+}
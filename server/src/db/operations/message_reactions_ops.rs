@@ -0,0 +1,57 @@
+use diesel::{
+    ExpressionMethods, PgConnection, QueryDsl, QueryResult, RunQueryDsl, SelectableHelper,
+};
+
+use crate::db::message_reactions_model::MessageReaction;
+use crate::db::schema::message_reactions;
+
+pub fn set_message_reaction(
+    conn: &mut PgConnection,
+    reaction_data: MessageReaction,
+) -> QueryResult<usize> {
+    diesel::insert_into(message_reactions::table)
+        .values(&reaction_data)
+        .on_conflict((message_reactions::message_id, message_reactions::user_id))
+        .do_update()
+        .set((
+            message_reactions::emoji.eq(&reaction_data.emoji),
+            message_reactions::created_at.eq(reaction_data.created_at),
+        ))
+        .execute(conn)
+}
+
+pub fn get_message_reactions(
+    conn: &mut PgConnection,
+    ids: Vec<u64>,
+) -> QueryResult<Vec<MessageReaction>> {
+    use crate::db::schema::message_reactions::dsl::*;
+
+    let ids: Vec<i32> = ids.into_iter().map(|id| id as i32).collect();
+
+    message_reactions
+        .filter(message_id.eq_any(ids))
+        .order(created_at.asc())
+        .select(MessageReaction::as_select())
+        .load(conn)
+}
+
+pub fn delete_message_reaction(
+    conn: &mut PgConnection,
+    id: u64,
+    reacted_by: u64,
+) -> QueryResult<usize> {
+    use crate::db::schema::message_reactions::dsl::*;
+
+    diesel::delete(message_reactions)
+        .filter(message_id.eq(id as i32))
+        .filter(user_id.eq(reacted_by as i32))
+        .execute(conn)
+}
+
+pub fn delete_message_reactions(conn: &mut PgConnection, id: u64) -> QueryResult<usize> {
+    use crate::db::schema::message_reactions::dsl::*;
+
+    diesel::delete(message_reactions)
+        .filter(message_id.eq(id as i32))
+        .execute(conn)
+}
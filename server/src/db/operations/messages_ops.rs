@@ -1,39 +1,104 @@
-use diesel::{update, ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl, SelectableHelper};
+use chrono::NaiveDateTime;
+use diesel::dsl::max;
+use diesel::{
+    delete, update, BoolExpressionMethods, ExpressionMethods, OptionalExtension, PgConnection,
+    QueryDsl, QueryResult, RunQueryDsl, SelectableHelper,
+};
 
 use crate::db::messages_model::Message;
-use crate::db::schema::messages;
+use crate::db::schema::{message_groups, messages};
 use crate::db::NewMessage;
 
-pub fn create_new_message(conn: &mut PgConnection, message_data: NewMessage) {
+pub fn create_new_message(
+    conn: &mut PgConnection,
+    message_data: NewMessage,
+) -> QueryResult<Message> {
     diesel::insert_into(messages::table)
         .values(message_data)
         .returning(Message::as_returning())
         .get_result(conn)
-        .unwrap();
 }
 
-pub fn get_last_message_number(conn: &mut PgConnection, group: String) -> usize {
+pub fn get_last_message_number(conn: &mut PgConnection, group: String) -> u64 {
     use crate::db::schema::messages::dsl::*;
 
     let result: Result<Message, diesel::result::Error> = messages
-        .filter(message_group.eq(group))
+        .filter(message_group.eq(group.to_owned()))
         .order(message_number.desc())
         .limit(1)
         .select(Message::as_select())
         .first(conn);
 
-    match result {
-        Ok(data) => data.message_number as usize,
+    let last_number = match result {
+        Ok(data) => data.message_number as u64,
         Err(_) => 0,
-    }
+    };
+
+    // The last messages of the group may have been removed
+    let removed_number: i32 = message_groups::table
+        .find(group)
+        .select(message_groups::last_message_number)
+        .first(conn)
+        .unwrap_or(0);
+
+    last_number.max(removed_number as u64)
+}
+
+/// Keeps the number as the highest one the group has used
+pub fn save_last_message_number(
+    conn: &mut PgConnection,
+    group: String,
+    number: u64,
+) -> QueryResult<usize> {
+    diesel::insert_into(message_groups::table)
+        .values((
+            message_groups::message_group.eq(group),
+            message_groups::last_message_number.eq(number as i32),
+        ))
+        .on_conflict(message_groups::message_group)
+        .do_update()
+        .set(message_groups::last_message_number.eq(number as i32))
+        .execute(conn)
+}
+
+/// Locks the row of the group until the transaction ends so its next number can be picked safely
+pub fn lock_message_group(conn: &mut PgConnection, group: String) -> QueryResult<i32> {
+    diesel::insert_into(message_groups::table)
+        .values((
+            message_groups::message_group.eq(group.to_owned()),
+            message_groups::last_message_number.eq(0),
+        ))
+        .on_conflict_do_nothing()
+        .execute(conn)?;
+
+    message_groups::table
+        .find(group)
+        .select(message_groups::last_message_number)
+        .for_update()
+        .first(conn)
+}
+
+pub fn get_message_with_number(
+    conn: &mut PgConnection,
+    group: String,
+    number: u64,
+) -> QueryResult<Option<Message>> {
+    use crate::db::schema::messages::dsl::*;
+
+    messages
+        .filter(message_group.eq(group))
+        .filter(message_number.eq(number as i32))
+        .select(Message::as_select())
+        .first(conn)
+        .optional()
 }
 
 pub fn get_messages_from_number(
     conn: &mut PgConnection,
     group: String,
-    start_at: usize,
-    end_at: usize,
-) -> Vec<Message> {
+    start_at: u64,
+    end_at: u64,
+) -> QueryResult<Vec<Message>> {
     use crate::db::schema::messages::dsl::*;
 
     messages
@@ -44,10 +109,116 @@ pub fn get_messages_from_number(
         .order(message_number.desc())
         .select(Message::as_select())
         .load(conn)
-        .unwrap()
 }
 
-pub fn delete_message_with_number(conn: &mut PgConnection, group: String, number: usize) {
+pub fn get_user_message_groups(
+    conn: &mut PgConnection,
+    user_id: u64,
+) -> QueryResult<Vec<(String, u64)>> {
+    use crate::db::schema::messages::dsl::*;
+
+    let groups: Vec<(String, Option<i32>)> = messages
+        .filter(
+            message_sender
+                .eq(user_id as i32)
+                .or(message_receiver.eq(user_id as i32)),
+        )
+        .group_by(message_group)
+        .select((message_group, max(message_number)))
+        .order(message_group.asc())
+        .load(conn)?;
+
+    Ok(groups
+        .into_iter()
+        .map(|(group, number)| (group, number.unwrap_or(0) as u64))
+        .collect())
+}
+
+pub fn get_message_number_before(
+    conn: &mut PgConnection,
+    group: String,
+    time: NaiveDateTime,
+) -> QueryResult<u64> {
+    use crate::db::schema::messages::dsl::*;
+
+    let number: Option<i32> = messages
+        .filter(message_group.eq(group))
+        .filter(created_at.lt(time))
+        .select(max(message_number))
+        .first(conn)?;
+
+    Ok(number.unwrap_or(0) as u64)
+}
+
+pub fn get_undelivered_messages(
+    conn: &mut PgConnection,
+    user_id: u64,
+) -> QueryResult<Vec<Message>> {
+    use crate::db::schema::messages::dsl::*;
+
+    messages
+        .filter(message_receiver.eq(user_id as i32))
+        .filter(delivered.eq(false))
+        .order(message_id.asc())
+        .select(Message::as_select())
+        .load(conn)
+}
+
+pub fn update_messages_delivered(
+    conn: &mut PgConnection,
+    message_ids: Vec<u64>,
+    is_delivered: bool,
+) -> QueryResult<usize> {
+    use crate::db::schema::messages::dsl::*;
+
+    let message_ids: Vec<i32> = message_ids.into_iter().map(|id| id as i32).collect();
+
+    update(messages)
+        .filter(message_id.eq_any(message_ids))
+        .set(delivered.eq(is_delivered))
+        .execute(conn)
+}
+
+pub fn update_messages_read(
+    conn: &mut PgConnection,
+    group: String,
+    receiver_id: u64,
+    up_to: u64,
+) -> QueryResult<usize> {
+    use crate::db::schema::messages::dsl::*;
+
+    // A read message was delivered as well
+    update(messages)
+        .filter(message_group.eq(group))
+        .filter(message_receiver.eq(receiver_id as i32))
+        .filter(message_number.le(up_to as i32))
+        .filter(read.eq(false))
+        .set((read.eq(true), delivered.eq(true)))
+        .execute(conn)
+}
+
+pub fn update_message_text(
+    conn: &mut PgConnection,
+    id: u64,
+    new_text: String,
+    new_edited_at: NaiveDateTime,
+) -> QueryResult<usize> {
+    use crate::db::schema::messages::dsl::*;
+
+    update(messages)
+        .filter(message_id.eq(id as i32))
+        .set((
+            message_text.eq(Some(new_text)),
+            edited_at.eq(Some(new_edited_at)),
+        ))
+        .execute(conn)
+}
+
+pub fn delete_message_with_number(
+    conn: &mut PgConnection,
+    group: String,
+    number: u64,
+) -> QueryResult<usize> {
     use crate::db::schema::messages::dsl::*;
 
     update(messages)
@@ -55,5 +226,28 @@ pub fn delete_message_with_number(conn: &mut PgConnection, group: String, number
         .filter(message_number.eq(number as i32))
         .set(message_text.eq(None::<String>))
         .execute(conn)
-        .unwrap();
+}
+
+pub fn remove_message_with_number(
+    conn: &mut PgConnection,
+    group: String,
+    number: u64,
+) -> QueryResult<usize> {
+    use crate::db::schema::messages::dsl::*;
+
+    delete(messages)
+        .filter(message_group.eq(group))
+        .filter(message_number.eq(number as i32))
+        .execute(conn)
+}
+
+/// Returns the numbers of the removed messages
+pub fn remove_message_group(conn: &mut PgConnection, group: String) -> QueryResult<Vec<u64>> {
+    use crate::db::schema::messages::dsl::*;
+
+    let numbers: Vec<i32> = delete(messages)
+        .filter(message_group.eq(group))
+        .returning(message_number)
+        .get_results(conn)?;
+    Ok(numbers.into_iter().map(|number| number as u64).collect())
 }
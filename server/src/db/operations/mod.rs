@@ -1,5 +1,11 @@
+mod devices_ops;
+mod message_edits_ops;
+mod message_reactions_ops;
 mod messages_ops;
 mod users_ops;
 
+pub use devices_ops::*;
+pub use message_edits_ops::*;
+pub use message_reactions_ops::*;
 pub use messages_ops::*;
 pub use users_ops::*;
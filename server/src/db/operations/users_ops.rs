@@ -1,60 +1,103 @@
-use diesel::{update, ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl, SelectableHelper};
+use chrono::NaiveDateTime;
+use diesel::{
+    update, ExpressionMethods, PgConnection, PgTextExpressionMethods, QueryDsl, QueryResult,
+    RunQueryDsl, SelectableHelper,
+};
 
 use crate::db::schema::users;
 use crate::db::users_model::User;
 
-pub fn create_new_user(conn: &mut PgConnection, user_data: User) {
+pub fn create_new_user(conn: &mut PgConnection, user_data: User) -> QueryResult<User> {
     diesel::insert_into(users::table)
         .values(user_data)
         .returning(User::as_returning())
         .get_result(conn)
-        .unwrap();
 }
 
-pub fn get_user_with_id(conn: &mut PgConnection, id: usize) -> Option<User> {
+pub fn get_user_with_id(conn: &mut PgConnection, id: u64) -> Option<User> {
     use crate::db::schema::users::dsl::*;
 
-    let result = users
+    users
         .filter(user_id.eq(id as i32))
         .limit(1)
         .select(User::as_select())
-        .first(conn);
+        .first(conn)
+        .ok()
+}
 
-    match result {
-        Ok(user) => Some(user),
-        Err(_) => None,
-    }
+pub fn update_user_name(conn: &mut PgConnection, id: u64, new_name: &str) -> QueryResult<usize> {
+    use crate::db::schema::users::dsl::*;
+
+    update(users.find(id as i32))
+        .set(user_name.eq(new_name))
+        .execute(conn)
 }
 
-pub fn get_user_with_token(conn: &mut PgConnection, token: String) -> Option<User> {
+pub fn update_user_image_link(
+    conn: &mut PgConnection,
+    id: u64,
+    new_image_link: Option<String>,
+) -> QueryResult<usize> {
     use crate::db::schema::users::dsl::*;
 
-    let result = users
-        .filter(user_token.eq(token))
-        .limit(1)
-        .select(User::as_select())
-        .first(conn);
+    update(users.find(id as i32))
+        .set(image_link.eq(new_image_link))
+        .execute(conn)
+}
 
-    match result {
-        Ok(user) => Some(user),
-        Err(_) => None,
+pub fn search_users(
+    conn: &mut PgConnection,
+    search: Option<String>,
+    offset: u64,
+    limit: u64,
+) -> QueryResult<Vec<User>> {
+    use crate::db::schema::users::dsl::*;
+
+    let mut query = users.into_boxed();
+    if let Some(search) = search {
+        query = query.filter(user_name.ilike(format!("%{search}%")));
     }
+
+    query
+        .order(user_id.asc())
+        .offset(offset as i64)
+        .limit(limit as i64)
+        .select(User::as_select())
+        .load(conn)
 }
 
-pub fn update_user_name(conn: &mut PgConnection, id: usize, new_name: &str) {
+pub fn update_user_disabled(
+    conn: &mut PgConnection,
+    id: u64,
+    new_disabled: bool,
+) -> QueryResult<usize> {
     use crate::db::schema::users::dsl::*;
 
     update(users.find(id as i32))
-        .set(user_name.eq(new_name))
+        .set(disabled.eq(new_disabled))
         .execute(conn)
-        .unwrap();
 }
 
-pub fn update_user_image_link(conn: &mut PgConnection, id: usize, new_image_link: Option<String>) {
+pub fn update_user_last_seen(
+    conn: &mut PgConnection,
+    id: u64,
+    new_last_seen: NaiveDateTime,
+) -> QueryResult<usize> {
     use crate::db::schema::users::dsl::*;
 
     update(users.find(id as i32))
-        .set(image_link.eq(new_image_link))
+        .set(last_seen.eq(Some(new_last_seen)))
+        .execute(conn)
+}
+
+pub fn update_user_hide_last_seen(
+    conn: &mut PgConnection,
+    id: u64,
+    new_hide_last_seen: bool,
+) -> QueryResult<usize> {
+    use crate::db::schema::users::dsl::*;
+
+    update(users.find(id as i32))
+        .set(hide_last_seen.eq(new_hide_last_seen))
         .execute(conn)
-        .unwrap();
 }
@@ -1,5 +1,46 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    devices (device_id) {
+        device_id -> Int4,
+        user_id -> Int4,
+        #[max_length = 250]
+        device_name -> Varchar,
+        #[max_length = 70]
+        token_hash -> Varchar,
+        #[max_length = 40]
+        token_salt -> Varchar,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    message_edits (edit_id) {
+        edit_id -> Int4,
+        message_id -> Int4,
+        message_text -> Text,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    message_groups (message_group) {
+        #[max_length = 40]
+        message_group -> Varchar,
+        last_message_number -> Int4,
+    }
+}
+
+diesel::table! {
+    message_reactions (message_id, user_id) {
+        message_id -> Int4,
+        user_id -> Int4,
+        #[max_length = 16]
+        emoji -> Varchar,
+        created_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
     messages (message_group, message_number) {
         message_id -> Int4,
@@ -10,6 +51,10 @@ diesel::table! {
         message_sender -> Int4,
         message_receiver -> Int4,
         created_at -> Timestamptz,
+        delivered -> Bool,
+        read -> Bool,
+        edited_at -> Nullable<Timestamptz>,
+        reply_to -> Nullable<Int4>,
     }
 }
 
@@ -19,9 +64,20 @@ diesel::table! {
         #[max_length = 250]
         user_name -> Varchar,
         image_link -> Nullable<Text>,
-        #[max_length = 70]
-        user_token -> Varchar,
+        disabled -> Bool,
+        last_seen -> Nullable<Timestamptz>,
+        hide_last_seen -> Bool,
     }
 }
 
-diesel::allow_tables_to_appear_in_same_query!(messages, users,);
+diesel::joinable!(devices -> users (user_id));
+diesel::joinable!(message_reactions -> users (user_id));
+
+diesel::allow_tables_to_appear_in_same_query!(
+    devices,
+    message_edits,
+    message_groups,
+    message_reactions,
+    messages,
+    users,
+);
@@ -0,0 +1,685 @@
+use chrono::{NaiveDateTime, Utc};
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use diesel::QueryResult;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::db::{
+    Device, Message, MessageEdit, MessageReaction, NewDevice, NewMessage, NewMessageEdit, Storage,
+    User,
+};
+
+#[derive(Default)]
+struct MemoryData {
+    // {User ID: User}
+    users: BTreeMap<i32, User>,
+    // {Device ID: Device}
+    devices: BTreeMap<i32, Device>,
+    // {(Message group, Message number): Message}
+    messages: BTreeMap<(String, i32), Message>,
+    // {Edit ID: MessageEdit}
+    message_edits: BTreeMap<i32, MessageEdit>,
+    // {(Message ID, User ID): MessageReaction}
+    message_reactions: BTreeMap<(i32, i32), MessageReaction>,
+    // {Message group: Last message number}. Only set once messages of the group are removed
+    message_groups: BTreeMap<String, i32>,
+    last_device_id: i32,
+    last_message_id: i32,
+    last_edit_id: i32,
+}
+
+impl MemoryData {
+    fn insert_device(&mut self, device_data: NewDevice) -> QueryResult<Device> {
+        if !self.users.contains_key(&device_data.user_id) {
+            return Err(DieselError::DatabaseError(
+                DatabaseErrorKind::ForeignKeyViolation,
+                Box::new(String::from("The device user does not exist")),
+            ));
+        }
+
+        self.last_device_id += 1;
+        let device = Device {
+            device_id: self.last_device_id,
+            user_id: device_data.user_id,
+            device_name: device_data.device_name,
+            token_hash: device_data.token_hash,
+            token_salt: device_data.token_salt,
+            created_at: Utc::now().naive_utc(),
+        };
+        self.devices.insert(device.device_id, device.clone());
+        Ok(device)
+    }
+
+    fn insert_message(&mut self, message_data: NewMessage) -> QueryResult<Message> {
+        let key = (
+            message_data.message_group.to_owned(),
+            message_data.message_number,
+        );
+
+        if self.messages.contains_key(&key) {
+            return Err(DieselError::DatabaseError(
+                DatabaseErrorKind::UniqueViolation,
+                Box::new(String::from("The message number is already used")),
+            ));
+        }
+
+        self.last_message_id += 1;
+        let message = Message {
+            message_id: self.last_message_id,
+            message_group: message_data.message_group,
+            message_number: message_data.message_number,
+            message_text: message_data.message_text,
+            message_sender: message_data.message_sender,
+            message_receiver: message_data.message_receiver,
+            created_at: message_data.created_at,
+            delivered: message_data.delivered,
+            read: false,
+            edited_at: None,
+            reply_to: message_data.reply_to,
+        };
+        self.messages.insert(key, message.clone());
+        Ok(message)
+    }
+
+    /// The highest number the group has used, including removed messages
+    fn last_message_number(&self, group: &str) -> i32 {
+        let removed_number = self.message_groups.get(group).copied().unwrap_or(0);
+        self.messages
+            .values()
+            .filter(|message| message.message_group == group)
+            .map(|message| message.message_number)
+            .fold(removed_number, i32::max)
+    }
+
+    /// Removes the edit history and the reactions of messages that no longer exist
+    fn remove_orphans(&mut self) {
+        let message_ids: Vec<i32> = self
+            .messages
+            .values()
+            .map(|message| message.message_id)
+            .collect();
+        self.message_edits
+            .retain(|_, edit| message_ids.contains(&edit.message_id));
+        self.message_reactions
+            .retain(|(message_id, _), _| message_ids.contains(message_id));
+    }
+}
+
+/// Keeps all the data in memory. Nothing is saved after the server stops.
+/// Clones share the same data so it can be used by every DbExecutor
+#[derive(Clone, Default)]
+pub struct MemoryStorage(Arc<Mutex<MemoryData>>);
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        MemoryStorage::default()
+    }
+
+    fn data(&self) -> MutexGuard<'_, MemoryData> {
+        self.0.lock().unwrap()
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn create_new_user(&mut self, user_data: User, device_data: NewDevice) -> QueryResult<Device> {
+        let mut data = self.data();
+
+        if data.users.contains_key(&user_data.user_id) {
+            return Err(DieselError::DatabaseError(
+                DatabaseErrorKind::UniqueViolation,
+                Box::new(String::from("The user already exists")),
+            ));
+        }
+
+        data.users.insert(user_data.user_id, user_data);
+        data.insert_device(device_data)
+    }
+
+    fn get_user_with_id(&mut self, id: u64) -> Option<User> {
+        self.data().users.get(&(id as i32)).cloned()
+    }
+
+    fn update_user_name(&mut self, id: u64, new_name: &str) -> QueryResult<usize> {
+        match self.data().users.get_mut(&(id as i32)) {
+            Some(user) => {
+                user.user_name = new_name.to_string();
+                Ok(1)
+            }
+            None => Ok(0),
+        }
+    }
+
+    fn update_user_image_link(
+        &mut self,
+        id: u64,
+        new_image_link: Option<String>,
+    ) -> QueryResult<usize> {
+        match self.data().users.get_mut(&(id as i32)) {
+            Some(user) => {
+                user.image_link = new_image_link;
+                Ok(1)
+            }
+            None => Ok(0),
+        }
+    }
+
+    fn search_users(
+        &mut self,
+        search: Option<String>,
+        offset: u64,
+        limit: u64,
+    ) -> QueryResult<Vec<User>> {
+        let search = search.map(|text| text.to_lowercase());
+
+        Ok(self
+            .data()
+            .users
+            .values()
+            .filter(|user| {
+                search
+                    .as_ref()
+                    .is_none_or(|text| user.user_name.to_lowercase().contains(text))
+            })
+            .skip(offset as usize)
+            .take(limit as usize)
+            .cloned()
+            .collect())
+    }
+
+    fn update_user_disabled(&mut self, id: u64, disabled: bool) -> QueryResult<usize> {
+        match self.data().users.get_mut(&(id as i32)) {
+            Some(user) => {
+                user.disabled = disabled;
+                Ok(1)
+            }
+            None => Ok(0),
+        }
+    }
+
+    fn update_user_last_seen(&mut self, id: u64, last_seen: NaiveDateTime) -> QueryResult<usize> {
+        match self.data().users.get_mut(&(id as i32)) {
+            Some(user) => {
+                user.last_seen = Some(last_seen);
+                Ok(1)
+            }
+            None => Ok(0),
+        }
+    }
+
+    fn update_user_hide_last_seen(&mut self, id: u64, hide: bool) -> QueryResult<usize> {
+        match self.data().users.get_mut(&(id as i32)) {
+            Some(user) => {
+                user.hide_last_seen = hide;
+                Ok(1)
+            }
+            None => Ok(0),
+        }
+    }
+
+    fn create_new_device(&mut self, device_data: NewDevice) -> QueryResult<Device> {
+        self.data().insert_device(device_data)
+    }
+
+    fn get_user_devices(&mut self, id: u64) -> QueryResult<Vec<Device>> {
+        Ok(self
+            .data()
+            .devices
+            .values()
+            .filter(|device| device.user_id == id as i32)
+            .cloned()
+            .collect())
+    }
+
+    fn update_device_token(
+        &mut self,
+        id: u64,
+        new_hash: &str,
+        new_salt: &str,
+    ) -> QueryResult<usize> {
+        match self.data().devices.get_mut(&(id as i32)) {
+            Some(device) => {
+                device.token_hash = new_hash.to_string();
+                device.token_salt = new_salt.to_string();
+                Ok(1)
+            }
+            None => Ok(0),
+        }
+    }
+
+    fn update_device_tokens(&mut self, tokens: Vec<(u64, String, String)>) -> QueryResult<usize> {
+        let mut data = self.data();
+        let mut updated = 0;
+        for (id, new_hash, new_salt) in tokens {
+            if let Some(device) = data.devices.get_mut(&(id as i32)) {
+                device.token_hash = new_hash;
+                device.token_salt = new_salt;
+                updated += 1;
+            }
+        }
+        Ok(updated)
+    }
+
+    fn delete_device(&mut self, id: u64) -> QueryResult<usize> {
+        Ok(self.data().devices.remove(&(id as i32)).map_or(0, |_| 1))
+    }
+
+    fn create_new_message(&mut self, message_data: NewMessage) -> QueryResult<Message> {
+        self.data().insert_message(message_data)
+    }
+
+    fn create_next_message(&mut self, mut message_data: NewMessage) -> QueryResult<Message> {
+        let mut data = self.data();
+        message_data.message_number = data.last_message_number(&message_data.message_group) + 1;
+        data.insert_message(message_data)
+    }
+
+    fn get_last_message_number(&mut self, group: String) -> u64 {
+        self.data().last_message_number(&group) as u64
+    }
+
+    fn get_message_with_number(
+        &mut self,
+        group: String,
+        number: u64,
+    ) -> QueryResult<Option<Message>> {
+        Ok(self.data().messages.get(&(group, number as i32)).cloned())
+    }
+
+    fn get_messages_from_number(
+        &mut self,
+        group: String,
+        start_at: u64,
+        end_at: u64,
+    ) -> QueryResult<Vec<Message>> {
+        Ok(self
+            .data()
+            .messages
+            .values()
+            .rev()
+            .filter(|message| {
+                message.message_group == group
+                    && message.message_text.is_some()
+                    && message.message_number > start_at as i32
+                    && message.message_number <= end_at as i32
+            })
+            .cloned()
+            .collect())
+    }
+
+    fn get_user_message_groups(&mut self, user_id: u64) -> QueryResult<Vec<(String, u64)>> {
+        let mut groups: BTreeMap<String, u64> = BTreeMap::new();
+
+        for message in self.data().messages.values().filter(|message| {
+            message.message_sender == user_id as i32 || message.message_receiver == user_id as i32
+        }) {
+            let last_number = groups.entry(message.message_group.to_owned()).or_default();
+            *last_number = (*last_number).max(message.message_number as u64);
+        }
+
+        Ok(groups.into_iter().collect())
+    }
+
+    fn get_message_number_before(
+        &mut self,
+        group: String,
+        time: NaiveDateTime,
+    ) -> QueryResult<u64> {
+        Ok(self
+            .data()
+            .messages
+            .values()
+            .filter(|message| message.message_group == group && message.created_at < time)
+            .map(|message| message.message_number as u64)
+            .max()
+            .unwrap_or(0))
+    }
+
+    fn get_undelivered_messages(&mut self, user_id: u64) -> QueryResult<Vec<Message>> {
+        let mut undelivered: Vec<Message> = self
+            .data()
+            .messages
+            .values()
+            .filter(|message| message.message_receiver == user_id as i32 && !message.delivered)
+            .cloned()
+            .collect();
+
+        undelivered.sort_by_key(|message| message.message_id);
+        Ok(undelivered)
+    }
+
+    fn update_messages_delivered(
+        &mut self,
+        message_ids: Vec<u64>,
+        delivered: bool,
+    ) -> QueryResult<usize> {
+        let mut updated = 0;
+        for message in self.data().messages.values_mut() {
+            if message_ids.contains(&(message.message_id as u64)) {
+                message.delivered = delivered;
+                updated += 1;
+            }
+        }
+        Ok(updated)
+    }
+
+    fn update_messages_read(
+        &mut self,
+        group: String,
+        receiver_id: u64,
+        up_to: u64,
+    ) -> QueryResult<usize> {
+        let mut updated = 0;
+        for message in self.data().messages.values_mut().filter(|message| {
+            message.message_group == group
+                && message.message_receiver == receiver_id as i32
+                && message.message_number <= up_to as i32
+                && !message.read
+        }) {
+            // A read message was delivered as well
+            message.read = true;
+            message.delivered = true;
+            updated += 1;
+        }
+        Ok(updated)
+    }
+
+    fn edit_message(
+        &mut self,
+        previous: NewMessageEdit,
+        new_text: String,
+        edited_at: NaiveDateTime,
+    ) -> QueryResult<usize> {
+        let mut data = self.data();
+        let Some(message) = data
+            .messages
+            .values_mut()
+            .find(|message| message.message_id == previous.message_id)
+        else {
+            return Ok(0);
+        };
+
+        message.message_text = Some(new_text);
+        message.edited_at = Some(edited_at);
+
+        data.last_edit_id += 1;
+        let edit = MessageEdit {
+            edit_id: data.last_edit_id,
+            message_id: previous.message_id,
+            message_text: previous.message_text,
+            created_at: previous.created_at,
+        };
+        data.message_edits.insert(edit.edit_id, edit);
+        Ok(1)
+    }
+
+    fn get_message_edits(&mut self, message_id: u64) -> QueryResult<Vec<MessageEdit>> {
+        Ok(self
+            .data()
+            .message_edits
+            .values()
+            .filter(|edit| edit.message_id == message_id as i32)
+            .cloned()
+            .collect())
+    }
+
+    fn set_message_reaction(&mut self, reaction: MessageReaction) -> QueryResult<usize> {
+        let mut data = self.data();
+        if !data
+            .messages
+            .values()
+            .any(|message| message.message_id == reaction.message_id)
+        {
+            return Err(DieselError::DatabaseError(
+                DatabaseErrorKind::ForeignKeyViolation,
+                Box::new(String::from("The reacted message does not exist")),
+            ));
+        }
+
+        data.message_reactions
+            .insert((reaction.message_id, reaction.user_id), reaction);
+        Ok(1)
+    }
+
+    fn remove_message_reaction(&mut self, message_id: u64, user_id: u64) -> QueryResult<usize> {
+        Ok(self
+            .data()
+            .message_reactions
+            .remove(&(message_id as i32, user_id as i32))
+            .map_or(0, |_| 1))
+    }
+
+    fn get_message_reactions(
+        &mut self,
+        message_ids: Vec<u64>,
+    ) -> QueryResult<Vec<MessageReaction>> {
+        let mut reactions: Vec<MessageReaction> = self
+            .data()
+            .message_reactions
+            .values()
+            .filter(|reaction| message_ids.contains(&(reaction.message_id as u64)))
+            .cloned()
+            .collect();
+
+        reactions.sort_by_key(|reaction| reaction.created_at);
+        Ok(reactions)
+    }
+
+    fn delete_message_with_number(&mut self, group: String, number: u64) -> QueryResult<usize> {
+        let mut data = self.data();
+        let Some(message) = data.messages.get_mut(&(group, number as i32)) else {
+            return Ok(0);
+        };
+
+        message.message_text = None;
+        let message_id = message.message_id;
+        data.message_edits
+            .retain(|_, edit| edit.message_id != message_id);
+        data.message_reactions
+            .retain(|(reaction_message, _), _| *reaction_message != message_id);
+        Ok(1)
+    }
+
+    fn remove_message_with_number(&mut self, group: String, number: u64) -> QueryResult<usize> {
+        let mut data = self.data();
+        let last_number = data.last_message_number(&group);
+        let removed = data.messages.remove(&(group.to_owned(), number as i32));
+        if removed.is_none() {
+            return Ok(0);
+        }
+
+        data.message_groups.insert(group, last_number);
+        data.remove_orphans();
+        Ok(1)
+    }
+
+    fn remove_message_group(&mut self, group: String) -> QueryResult<Vec<u64>> {
+        let mut data = self.data();
+        let last_number = data.last_message_number(&group);
+        let numbers: Vec<u64> = data
+            .messages
+            .keys()
+            .filter(|(message_group, _)| message_group == &group)
+            .map(|(_, number)| *number as u64)
+            .collect();
+        if numbers.is_empty() {
+            return Ok(numbers);
+        }
+
+        data.messages
+            .retain(|(message_group, _), _| message_group != &group);
+        data.message_groups.insert(group, last_number);
+        data.remove_orphans();
+        Ok(numbers)
+    }
+
+    fn check_connection(&mut self) -> QueryResult<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::MemoryStorage;
+    use crate::db::{NewDevice, NewMessage, Storage, User};
+    use crate::utils::{generate_token_salt, hash_user_token};
+
+    fn storage_with_user(user_id: u64) -> MemoryStorage {
+        let mut storage = MemoryStorage::new();
+        storage
+            .create_new_user(
+                User::new().update_id(user_id),
+                NewDevice::new(user_id, "Device", "token"),
+            )
+            .unwrap();
+        storage
+    }
+
+    fn new_message(group: &str, number: u64) -> NewMessage {
+        NewMessage::new(
+            group.to_string(),
+            number,
+            String::from("Hello"),
+            1,
+            1,
+            Utc::now().naive_utc(),
+        )
+    }
+
+    #[test]
+    fn users_are_unique() {
+        let mut storage = storage_with_user(1);
+        let result = storage.create_new_user(
+            User::new().update_id(1),
+            NewDevice::new(1, "Device", "token"),
+        );
+        assert!(result.is_err());
+        assert_eq!(storage.get_user_devices(1).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn devices_need_an_existing_user() {
+        let mut storage = storage_with_user(1);
+        assert!(storage
+            .create_new_device(NewDevice::new(2, "Device", "token"))
+            .is_err());
+
+        let device = storage
+            .create_new_device(NewDevice::new(1, "Second device", "token"))
+            .unwrap();
+        assert_eq!(storage.get_user_devices(1).unwrap().len(), 2);
+        assert_eq!(storage.delete_device(device.device_id as u64), Ok(1));
+        assert_eq!(storage.get_user_devices(1).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn every_device_token_is_updated() {
+        let mut storage = storage_with_user(1);
+        storage
+            .create_new_device(NewDevice::new(1, "Second device", "token"))
+            .unwrap();
+
+        let tokens = storage
+            .get_user_devices(1)
+            .unwrap()
+            .into_iter()
+            .map(|device| {
+                let salt = generate_token_salt();
+                let hash = hash_user_token("new token", &salt);
+                (device.device_id as u64, hash, salt)
+            })
+            .collect();
+        assert_eq!(storage.update_device_tokens(tokens), Ok(2));
+
+        for device in storage.get_user_devices(1).unwrap() {
+            assert!(!device.verify_token("token"));
+            assert!(device.verify_token("new token"));
+        }
+    }
+
+    #[test]
+    fn message_numbers_are_unique_per_group() {
+        let mut storage = storage_with_user(1);
+        assert_eq!(storage.get_last_message_number(String::from("1@1")), 0);
+
+        storage.create_new_message(new_message("1@1", 1)).unwrap();
+        storage.create_new_message(new_message("1@1", 2)).unwrap();
+        assert!(storage.create_new_message(new_message("1@1", 2)).is_err());
+        storage.create_new_message(new_message("1@2", 2)).unwrap();
+
+        assert_eq!(storage.get_last_message_number(String::from("1@1")), 2);
+        assert_eq!(storage.get_last_message_number(String::from("1@2")), 2);
+    }
+
+    #[test]
+    fn removed_message_numbers_are_not_reused() {
+        let mut storage = storage_with_user(1);
+        let group = String::from("1@1");
+        for number in 1..=3 {
+            storage
+                .create_new_message(new_message(&group, number))
+                .unwrap();
+        }
+
+        assert_eq!(
+            storage.remove_message_with_number(group.to_owned(), 3),
+            Ok(1)
+        );
+        assert_eq!(
+            storage.remove_message_with_number(group.to_owned(), 3),
+            Ok(0)
+        );
+        assert_eq!(storage.get_last_message_number(group.to_owned()), 3);
+
+        assert_eq!(
+            storage.remove_message_group(group.to_owned()),
+            Ok(vec![1, 2])
+        );
+        assert_eq!(storage.remove_message_group(group.to_owned()), Ok(vec![]));
+        assert_eq!(storage.get_last_message_number(group.to_owned()), 3);
+
+        // Other groups are not affected
+        assert_eq!(storage.get_last_message_number(String::from("1@2")), 0);
+    }
+
+    #[test]
+    fn next_message_numbers_are_unique_across_threads() {
+        let storage = storage_with_user(1);
+        storage
+            .clone()
+            .create_new_message(new_message("1@1", 1))
+            .unwrap();
+
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let mut storage = storage.clone();
+                std::thread::spawn(move || {
+                    (0..25)
+                        .map(|_| {
+                            storage
+                                .create_next_message(new_message("1@1", 0))
+                                .unwrap()
+                                .message_number
+                        })
+                        .collect::<Vec<i32>>()
+                })
+            })
+            .collect();
+
+        let mut numbers: Vec<i32> = threads
+            .into_iter()
+            .flat_map(|thread| thread.join().unwrap())
+            .collect();
+        numbers.sort();
+        assert_eq!(numbers, (2..=101).collect::<Vec<i32>>());
+
+        // Removed numbers are skipped
+        let mut storage = storage;
+        storage
+            .remove_message_with_number(String::from("1@1"), 101)
+            .unwrap();
+        let message = storage.create_next_message(new_message("1@1", 0)).unwrap();
+        assert_eq!(message.message_number, 102);
+    }
+}
@@ -0,0 +1,147 @@
+mod memory;
+mod postgres;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+use chrono::NaiveDateTime;
+use diesel::QueryResult;
+
+use crate::db::{
+    Device, Message, MessageEdit, MessageReaction, NewDevice, NewMessage, NewMessageEdit, User,
+};
+
+pub use memory::MemoryStorage;
+#[cfg(feature = "sqlite")]
+pub use sqlite::{create_sqlite_pool, SqlitePool};
+
+/// Every DB operation the server uses. Each storage backend implements it so the server
+/// does not depend on a specific database
+pub trait Storage {
+    /// Saves the user together with the first device of the user
+    fn create_new_user(&mut self, user_data: User, device_data: NewDevice) -> QueryResult<Device>;
+
+    fn get_user_with_id(&mut self, id: u64) -> Option<User>;
+
+    fn update_user_name(&mut self, id: u64, new_name: &str) -> QueryResult<usize>;
+
+    fn update_user_image_link(
+        &mut self,
+        id: u64,
+        new_image_link: Option<String>,
+    ) -> QueryResult<usize>;
+
+    /// Users ordered by ID. If a search text is given, only the users with it in their name are included
+    fn search_users(
+        &mut self,
+        search: Option<String>,
+        offset: u64,
+        limit: u64,
+    ) -> QueryResult<Vec<User>>;
+
+    fn update_user_disabled(&mut self, id: u64, disabled: bool) -> QueryResult<usize>;
+
+    /// Saves the time the user was last connected with any device
+    fn update_user_last_seen(&mut self, id: u64, last_seen: NaiveDateTime) -> QueryResult<usize>;
+
+    fn update_user_hide_last_seen(&mut self, id: u64, hide: bool) -> QueryResult<usize>;
+
+    fn create_new_device(&mut self, device_data: NewDevice) -> QueryResult<Device>;
+
+    fn get_user_devices(&mut self, id: u64) -> QueryResult<Vec<Device>>;
+
+    fn update_device_token(
+        &mut self,
+        id: u64,
+        new_hash: &str,
+        new_salt: &str,
+    ) -> QueryResult<usize>;
+
+    /// Sets the (device ID, hash, salt) of every device in one transaction so either
+    /// all tokens change or none do
+    fn update_device_tokens(&mut self, tokens: Vec<(u64, String, String)>) -> QueryResult<usize>;
+
+    fn delete_device(&mut self, id: u64) -> QueryResult<usize>;
+
+    fn create_new_message(&mut self, message_data: NewMessage) -> QueryResult<Message>;
+
+    /// Saves the message with the number after the last one of its group. The number in the
+    /// message data is ignored. Concurrent calls for the same group never get the same number
+    fn create_next_message(&mut self, message_data: NewMessage) -> QueryResult<Message>;
+
+    /// The highest message number the group has used, including removed messages. 0 if there is none
+    fn get_last_message_number(&mut self, group: String) -> u64;
+
+    /// The message with the number in the group. Deleted messages are included
+    fn get_message_with_number(
+        &mut self,
+        group: String,
+        number: u64,
+    ) -> QueryResult<Option<Message>>;
+
+    /// Messages of the group after start_at up to end_at in descending order. Deleted messages are skipped
+    fn get_messages_from_number(
+        &mut self,
+        group: String,
+        start_at: u64,
+        end_at: u64,
+    ) -> QueryResult<Vec<Message>>;
+
+    /// Every message group the user has a message in with the last message number of it
+    fn get_user_message_groups(&mut self, user_id: u64) -> QueryResult<Vec<(String, u64)>>;
+
+    /// Number of the last message of the group created before the time. 0 if there is none
+    fn get_message_number_before(&mut self, group: String, time: NaiveDateTime)
+        -> QueryResult<u64>;
+
+    /// Messages to the user that no session of the user got yet, oldest first.
+    /// Deleted messages are included so they can be marked as delivered too
+    fn get_undelivered_messages(&mut self, user_id: u64) -> QueryResult<Vec<Message>>;
+
+    fn update_messages_delivered(
+        &mut self,
+        message_ids: Vec<u64>,
+        delivered: bool,
+    ) -> QueryResult<usize>;
+
+    /// Marks the messages of the group sent to the receiver up to the number as read.
+    /// Returns the number of messages that were not read before
+    fn update_messages_read(
+        &mut self,
+        group: String,
+        receiver_id: u64,
+        up_to: u64,
+    ) -> QueryResult<usize>;
+
+    /// Replaces the text of the message and saves the replaced version in its edit history
+    fn edit_message(
+        &mut self,
+        previous: NewMessageEdit,
+        new_text: String,
+        edited_at: NaiveDateTime,
+    ) -> QueryResult<usize>;
+
+    /// Earlier versions of the message, oldest first
+    fn get_message_edits(&mut self, message_id: u64) -> QueryResult<Vec<MessageEdit>>;
+
+    /// Saves the reaction of the user on the message. Replaces the earlier reaction of the user
+    fn set_message_reaction(&mut self, reaction: MessageReaction) -> QueryResult<usize>;
+
+    /// Removes the reaction of the user on the message
+    fn remove_message_reaction(&mut self, message_id: u64, user_id: u64) -> QueryResult<usize>;
+
+    /// Reactions on the messages, oldest first
+    fn get_message_reactions(&mut self, message_ids: Vec<u64>)
+        -> QueryResult<Vec<MessageReaction>>;
+
+    /// Removes the text, the edit history and the reactions of the message. The message number stays used
+    fn delete_message_with_number(&mut self, group: String, number: u64) -> QueryResult<usize>;
+
+    /// Removes the message row completely. Its number is still not used again
+    fn remove_message_with_number(&mut self, group: String, number: u64) -> QueryResult<usize>;
+
+    /// Removes every message of the group and returns their numbers. The numbers are not used again
+    fn remove_message_group(&mut self, group: String) -> QueryResult<Vec<u64>>;
+
+    /// Runs a trivial query to check that the database is reachable
+    fn check_connection(&mut self) -> QueryResult<()>;
+}
@@ -0,0 +1,225 @@
+use chrono::NaiveDateTime;
+use diesel::pg::PgConnection;
+use diesel::{sql_query, Connection, QueryResult, RunQueryDsl};
+
+use crate::db::operations as ops;
+use crate::db::{
+    Device, Message, MessageEdit, MessageReaction, NewDevice, NewMessage, NewMessageEdit, Storage,
+    User,
+};
+
+impl Storage for PgConnection {
+    fn create_new_user(&mut self, user_data: User, device_data: NewDevice) -> QueryResult<Device> {
+        self.transaction(|conn| {
+            ops::create_new_user(conn, user_data)?;
+            ops::create_new_device(conn, device_data)
+        })
+    }
+
+    fn get_user_with_id(&mut self, id: u64) -> Option<User> {
+        ops::get_user_with_id(self, id)
+    }
+
+    fn update_user_name(&mut self, id: u64, new_name: &str) -> QueryResult<usize> {
+        ops::update_user_name(self, id, new_name)
+    }
+
+    fn update_user_image_link(
+        &mut self,
+        id: u64,
+        new_image_link: Option<String>,
+    ) -> QueryResult<usize> {
+        ops::update_user_image_link(self, id, new_image_link)
+    }
+
+    fn search_users(
+        &mut self,
+        search: Option<String>,
+        offset: u64,
+        limit: u64,
+    ) -> QueryResult<Vec<User>> {
+        ops::search_users(self, search, offset, limit)
+    }
+
+    fn update_user_disabled(&mut self, id: u64, disabled: bool) -> QueryResult<usize> {
+        ops::update_user_disabled(self, id, disabled)
+    }
+
+    fn update_user_last_seen(&mut self, id: u64, last_seen: NaiveDateTime) -> QueryResult<usize> {
+        ops::update_user_last_seen(self, id, last_seen)
+    }
+
+    fn update_user_hide_last_seen(&mut self, id: u64, hide: bool) -> QueryResult<usize> {
+        ops::update_user_hide_last_seen(self, id, hide)
+    }
+
+    fn create_new_device(&mut self, device_data: NewDevice) -> QueryResult<Device> {
+        ops::create_new_device(self, device_data)
+    }
+
+    fn get_user_devices(&mut self, id: u64) -> QueryResult<Vec<Device>> {
+        ops::get_user_devices(self, id)
+    }
+
+    fn update_device_token(
+        &mut self,
+        id: u64,
+        new_hash: &str,
+        new_salt: &str,
+    ) -> QueryResult<usize> {
+        ops::update_device_token(self, id, new_hash, new_salt)
+    }
+
+    fn update_device_tokens(&mut self, tokens: Vec<(u64, String, String)>) -> QueryResult<usize> {
+        self.transaction(|conn| {
+            let mut updated = 0;
+            for (id, new_hash, new_salt) in tokens {
+                updated += ops::update_device_token(conn, id, &new_hash, &new_salt)?;
+            }
+            Ok(updated)
+        })
+    }
+
+    fn delete_device(&mut self, id: u64) -> QueryResult<usize> {
+        ops::delete_device(self, id)
+    }
+
+    fn create_new_message(&mut self, message_data: NewMessage) -> QueryResult<Message> {
+        ops::create_new_message(self, message_data)
+    }
+
+    fn create_next_message(&mut self, mut message_data: NewMessage) -> QueryResult<Message> {
+        self.transaction(|conn| {
+            let group = message_data.message_group.to_owned();
+            ops::lock_message_group(conn, group.to_owned())?;
+
+            let number = ops::get_last_message_number(conn, group.to_owned()) + 1;
+            ops::save_last_message_number(conn, group, number)?;
+
+            message_data.message_number = number as i32;
+            ops::create_new_message(conn, message_data)
+        })
+    }
+
+    fn get_last_message_number(&mut self, group: String) -> u64 {
+        ops::get_last_message_number(self, group)
+    }
+
+    fn get_message_with_number(
+        &mut self,
+        group: String,
+        number: u64,
+    ) -> QueryResult<Option<Message>> {
+        ops::get_message_with_number(self, group, number)
+    }
+
+    fn get_messages_from_number(
+        &mut self,
+        group: String,
+        start_at: u64,
+        end_at: u64,
+    ) -> QueryResult<Vec<Message>> {
+        ops::get_messages_from_number(self, group, start_at, end_at)
+    }
+
+    fn get_user_message_groups(&mut self, user_id: u64) -> QueryResult<Vec<(String, u64)>> {
+        ops::get_user_message_groups(self, user_id)
+    }
+
+    fn get_message_number_before(
+        &mut self,
+        group: String,
+        time: NaiveDateTime,
+    ) -> QueryResult<u64> {
+        ops::get_message_number_before(self, group, time)
+    }
+
+    fn get_undelivered_messages(&mut self, user_id: u64) -> QueryResult<Vec<Message>> {
+        ops::get_undelivered_messages(self, user_id)
+    }
+
+    fn update_messages_delivered(
+        &mut self,
+        message_ids: Vec<u64>,
+        delivered: bool,
+    ) -> QueryResult<usize> {
+        ops::update_messages_delivered(self, message_ids, delivered)
+    }
+
+    fn update_messages_read(
+        &mut self,
+        group: String,
+        receiver_id: u64,
+        up_to: u64,
+    ) -> QueryResult<usize> {
+        ops::update_messages_read(self, group, receiver_id, up_to)
+    }
+
+    fn edit_message(
+        &mut self,
+        previous: NewMessageEdit,
+        new_text: String,
+        edited_at: NaiveDateTime,
+    ) -> QueryResult<usize> {
+        self.transaction(|conn| {
+            let message_id = previous.message_id as u64;
+            ops::create_message_edit(conn, previous)?;
+            ops::update_message_text(conn, message_id, new_text, edited_at)
+        })
+    }
+
+    fn get_message_edits(&mut self, message_id: u64) -> QueryResult<Vec<MessageEdit>> {
+        ops::get_message_edits(self, message_id)
+    }
+
+    fn set_message_reaction(&mut self, reaction: MessageReaction) -> QueryResult<usize> {
+        ops::set_message_reaction(self, reaction)
+    }
+
+    fn remove_message_reaction(&mut self, message_id: u64, user_id: u64) -> QueryResult<usize> {
+        ops::delete_message_reaction(self, message_id, user_id)
+    }
+
+    fn get_message_reactions(
+        &mut self,
+        message_ids: Vec<u64>,
+    ) -> QueryResult<Vec<MessageReaction>> {
+        ops::get_message_reactions(self, message_ids)
+    }
+
+    fn delete_message_with_number(&mut self, group: String, number: u64) -> QueryResult<usize> {
+        self.transaction(|conn| {
+            if let Some(message) = ops::get_message_with_number(conn, group.to_owned(), number)? {
+                ops::delete_message_edits(conn, message.message_id as u64)?;
+                ops::delete_message_reactions(conn, message.message_id as u64)?;
+            }
+            ops::delete_message_with_number(conn, group, number)
+        })
+    }
+
+    fn remove_message_with_number(&mut self, group: String, number: u64) -> QueryResult<usize> {
+        self.transaction(|conn| {
+            let last_number = ops::get_last_message_number(conn, group.to_owned());
+            let removed = ops::remove_message_with_number(conn, group.to_owned(), number)?;
+            if removed > 0 {
+                ops::save_last_message_number(conn, group, last_number)?;
+            }
+            Ok(removed)
+        })
+    }
+
+    fn remove_message_group(&mut self, group: String) -> QueryResult<Vec<u64>> {
+        self.transaction(|conn| {
+            let last_number = ops::get_last_message_number(conn, group.to_owned());
+            let removed = ops::remove_message_group(conn, group.to_owned())?;
+            if !removed.is_empty() {
+                ops::save_last_message_number(conn, group, last_number)?;
+            }
+            Ok(removed)
+        })
+    }
+
+    fn check_connection(&mut self) -> QueryResult<()> {
+        sql_query("SELECT 1").execute(self).map(|_| ())
+    }
+}
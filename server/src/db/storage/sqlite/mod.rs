@@ -0,0 +1,559 @@
+mod schema;
+
+use chrono::NaiveDateTime;
+use diesel::dsl::max;
+use diesel::r2d2::{ConnectionManager, CustomizeConnection, Error as PoolError, Pool};
+use diesel::sqlite::SqliteConnection;
+use diesel::{
+    delete, insert_into, sql_query, update, BoolExpressionMethods, Connection, ExpressionMethods,
+    OptionalExtension, QueryDsl, QueryResult, RunQueryDsl, TextExpressionMethods,
+};
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+
+use crate::db::{
+    Device, Message, MessageEdit, MessageReaction, NewDevice, NewMessage, NewMessageEdit, Storage,
+    User,
+};
+use schema::{devices, message_edits, message_groups, message_reactions, messages, users};
+
+pub type SqlitePool = Pool<ConnectionManager<SqliteConnection>>;
+
+const MIGRATIONS: EmbeddedMigrations = embed_migrations!("../migrations_sqlite");
+
+// The columns in the same order as the fields of the models
+type UserColumns = (
+    users::user_id,
+    users::user_name,
+    users::image_link,
+    users::disabled,
+    users::last_seen,
+    users::hide_last_seen,
+);
+const USER_COLUMNS: UserColumns = (
+    users::user_id,
+    users::user_name,
+    users::image_link,
+    users::disabled,
+    users::last_seen,
+    users::hide_last_seen,
+);
+
+type DeviceColumns = (
+    devices::device_id,
+    devices::user_id,
+    devices::device_name,
+    devices::token_hash,
+    devices::token_salt,
+    devices::created_at,
+);
+const DEVICE_COLUMNS: DeviceColumns = (
+    devices::device_id,
+    devices::user_id,
+    devices::device_name,
+    devices::token_hash,
+    devices::token_salt,
+    devices::created_at,
+);
+
+type MessageColumns = (
+    messages::message_id,
+    messages::message_group,
+    messages::message_number,
+    messages::message_text,
+    messages::message_sender,
+    messages::message_receiver,
+    messages::created_at,
+    messages::delivered,
+    messages::read,
+    messages::edited_at,
+    messages::reply_to,
+);
+const MESSAGE_COLUMNS: MessageColumns = (
+    messages::message_id,
+    messages::message_group,
+    messages::message_number,
+    messages::message_text,
+    messages::message_sender,
+    messages::message_receiver,
+    messages::created_at,
+    messages::delivered,
+    messages::read,
+    messages::edited_at,
+    messages::reply_to,
+);
+
+type MessageEditColumns = (
+    message_edits::edit_id,
+    message_edits::message_id,
+    message_edits::message_text,
+    message_edits::created_at,
+);
+const MESSAGE_EDIT_COLUMNS: MessageEditColumns = (
+    message_edits::edit_id,
+    message_edits::message_id,
+    message_edits::message_text,
+    message_edits::created_at,
+);
+
+type MessageReactionColumns = (
+    message_reactions::message_id,
+    message_reactions::user_id,
+    message_reactions::emoji,
+    message_reactions::created_at,
+);
+const MESSAGE_REACTION_COLUMNS: MessageReactionColumns = (
+    message_reactions::message_id,
+    message_reactions::user_id,
+    message_reactions::emoji,
+    message_reactions::created_at,
+);
+
+/// SQLite does not enforce foreign keys unless it is enabled on every connection
+#[derive(Debug)]
+struct ForeignKeys;
+
+impl CustomizeConnection<SqliteConnection, PoolError> for ForeignKeys {
+    fn on_acquire(&self, conn: &mut SqliteConnection) -> Result<(), PoolError> {
+        sql_query("PRAGMA foreign_keys = ON")
+            .execute(conn)
+            .map(|_| ())
+            .map_err(PoolError::QueryError)
+    }
+}
+
+/// Opens the SQLite file and creates or updates the tables.
+/// A single connection is used as SQLite only allows one writer at a time
+pub fn create_sqlite_pool(path: &str) -> Result<SqlitePool, String> {
+    // The pool retries until it times out so the file is opened once first to fail right away
+    SqliteConnection::establish(path)
+        .map_err(|e| format!("Failed to open the SQLite database {path}: {e}"))?
+        .run_pending_migrations(MIGRATIONS)
+        .map_err(|e| format!("Failed to run the SQLite migrations: {e}"))?;
+
+    let manager = ConnectionManager::<SqliteConnection>::new(path);
+    Pool::builder()
+        .max_size(1)
+        .connection_customizer(Box::new(ForeignKeys))
+        .build(manager)
+        .map_err(|e| format!("Failed to open the SQLite database {path}: {e}"))
+}
+
+/// Keeps the number as the highest one the group has used
+fn save_last_message_number(
+    conn: &mut SqliteConnection,
+    group: String,
+    number: u64,
+) -> QueryResult<usize> {
+    insert_into(message_groups::table)
+        .values((
+            message_groups::message_group.eq(group),
+            message_groups::last_message_number.eq(number as i32),
+        ))
+        .on_conflict(message_groups::message_group)
+        .do_update()
+        .set(message_groups::last_message_number.eq(number as i32))
+        .execute(conn)
+}
+
+impl Storage for SqliteConnection {
+    fn create_new_user(&mut self, user_data: User, device_data: NewDevice) -> QueryResult<Device> {
+        self.transaction(|conn| {
+            insert_into(users::table)
+                .values((
+                    users::user_id.eq(user_data.user_id),
+                    users::user_name.eq(user_data.user_name),
+                    users::image_link.eq(user_data.image_link),
+                    users::disabled.eq(user_data.disabled),
+                    users::last_seen.eq(user_data.last_seen),
+                    users::hide_last_seen.eq(user_data.hide_last_seen),
+                ))
+                .execute(conn)?;
+            conn.create_new_device(device_data)
+        })
+    }
+
+    fn get_user_with_id(&mut self, id: u64) -> Option<User> {
+        users::table
+            .find(id as i32)
+            .select(USER_COLUMNS)
+            .first(self)
+            .ok()
+    }
+
+    fn update_user_name(&mut self, id: u64, new_name: &str) -> QueryResult<usize> {
+        update(users::table.find(id as i32))
+            .set(users::user_name.eq(new_name))
+            .execute(self)
+    }
+
+    fn update_user_image_link(
+        &mut self,
+        id: u64,
+        new_image_link: Option<String>,
+    ) -> QueryResult<usize> {
+        update(users::table.find(id as i32))
+            .set(users::image_link.eq(new_image_link))
+            .execute(self)
+    }
+
+    fn search_users(
+        &mut self,
+        search: Option<String>,
+        offset: u64,
+        limit: u64,
+    ) -> QueryResult<Vec<User>> {
+        let mut query = users::table.into_boxed();
+        // LIKE is case insensitive in SQLite
+        if let Some(search) = search {
+            query = query.filter(users::user_name.like(format!("%{search}%")));
+        }
+
+        query
+            .order(users::user_id.asc())
+            .offset(offset as i64)
+            .limit(limit as i64)
+            .select(USER_COLUMNS)
+            .load(self)
+    }
+
+    fn update_user_disabled(&mut self, id: u64, disabled: bool) -> QueryResult<usize> {
+        update(users::table.find(id as i32))
+            .set(users::disabled.eq(disabled))
+            .execute(self)
+    }
+
+    fn update_user_last_seen(&mut self, id: u64, last_seen: NaiveDateTime) -> QueryResult<usize> {
+        update(users::table.find(id as i32))
+            .set(users::last_seen.eq(Some(last_seen)))
+            .execute(self)
+    }
+
+    fn update_user_hide_last_seen(&mut self, id: u64, hide: bool) -> QueryResult<usize> {
+        update(users::table.find(id as i32))
+            .set(users::hide_last_seen.eq(hide))
+            .execute(self)
+    }
+
+    fn create_new_device(&mut self, device_data: NewDevice) -> QueryResult<Device> {
+        insert_into(devices::table)
+            .values((
+                devices::user_id.eq(device_data.user_id),
+                devices::device_name.eq(device_data.device_name),
+                devices::token_hash.eq(device_data.token_hash),
+                devices::token_salt.eq(device_data.token_salt),
+            ))
+            .returning(DEVICE_COLUMNS)
+            .get_result(self)
+    }
+
+    fn get_user_devices(&mut self, id: u64) -> QueryResult<Vec<Device>> {
+        devices::table
+            .filter(devices::user_id.eq(id as i32))
+            .order(devices::device_id.asc())
+            .select(DEVICE_COLUMNS)
+            .load(self)
+    }
+
+    fn update_device_token(
+        &mut self,
+        id: u64,
+        new_hash: &str,
+        new_salt: &str,
+    ) -> QueryResult<usize> {
+        update(devices::table.find(id as i32))
+            .set((
+                devices::token_hash.eq(new_hash),
+                devices::token_salt.eq(new_salt),
+            ))
+            .execute(self)
+    }
+
+    fn update_device_tokens(&mut self, tokens: Vec<(u64, String, String)>) -> QueryResult<usize> {
+        self.transaction(|conn| {
+            let mut updated = 0;
+            for (id, new_hash, new_salt) in tokens {
+                updated += conn.update_device_token(id, &new_hash, &new_salt)?;
+            }
+            Ok(updated)
+        })
+    }
+
+    fn delete_device(&mut self, id: u64) -> QueryResult<usize> {
+        delete(devices::table.find(id as i32)).execute(self)
+    }
+
+    fn create_new_message(&mut self, message_data: NewMessage) -> QueryResult<Message> {
+        insert_into(messages::table)
+            .values((
+                messages::message_group.eq(message_data.message_group),
+                messages::message_number.eq(message_data.message_number),
+                messages::message_text.eq(message_data.message_text),
+                messages::message_sender.eq(message_data.message_sender),
+                messages::message_receiver.eq(message_data.message_receiver),
+                messages::created_at.eq(message_data.created_at),
+                messages::delivered.eq(message_data.delivered),
+                messages::reply_to.eq(message_data.reply_to),
+            ))
+            .returning(MESSAGE_COLUMNS)
+            .get_result(self)
+    }
+
+    fn create_next_message(&mut self, mut message_data: NewMessage) -> QueryResult<Message> {
+        // The write lock is taken right away so no other connection can pick the same number
+        self.immediate_transaction(|conn| {
+            let group = message_data.message_group.to_owned();
+            let number = conn.get_last_message_number(group.to_owned()) + 1;
+            save_last_message_number(conn, group, number)?;
+
+            message_data.message_number = number as i32;
+            conn.create_new_message(message_data)
+        })
+    }
+
+    fn get_last_message_number(&mut self, group: String) -> u64 {
+        let result: QueryResult<i32> = messages::table
+            .filter(messages::message_group.eq(group.to_owned()))
+            .order(messages::message_number.desc())
+            .select(messages::message_number)
+            .first(self);
+
+        let last_number = match result {
+            Ok(number) => number as u64,
+            Err(_) => 0,
+        };
+
+        // The last messages of the group may have been removed
+        let removed_number: i32 = message_groups::table
+            .find(group)
+            .select(message_groups::last_message_number)
+            .first(self)
+            .unwrap_or(0);
+
+        last_number.max(removed_number as u64)
+    }
+
+    fn get_message_with_number(
+        &mut self,
+        group: String,
+        number: u64,
+    ) -> QueryResult<Option<Message>> {
+        messages::table
+            .filter(messages::message_group.eq(group))
+            .filter(messages::message_number.eq(number as i32))
+            .select(MESSAGE_COLUMNS)
+            .first(self)
+            .optional()
+    }
+
+    fn get_messages_from_number(
+        &mut self,
+        group: String,
+        start_at: u64,
+        end_at: u64,
+    ) -> QueryResult<Vec<Message>> {
+        messages::table
+            .filter(messages::message_group.eq(group))
+            .filter(messages::message_text.is_not_null())
+            .filter(messages::message_number.gt(start_at as i32))
+            .filter(messages::message_number.le(end_at as i32))
+            .order(messages::message_number.desc())
+            .select(MESSAGE_COLUMNS)
+            .load(self)
+    }
+
+    fn get_user_message_groups(&mut self, user_id: u64) -> QueryResult<Vec<(String, u64)>> {
+        let groups: Vec<(String, Option<i32>)> = messages::table
+            .filter(
+                messages::message_sender
+                    .eq(user_id as i32)
+                    .or(messages::message_receiver.eq(user_id as i32)),
+            )
+            .group_by(messages::message_group)
+            .select((messages::message_group, max(messages::message_number)))
+            .order(messages::message_group.asc())
+            .load(self)?;
+
+        Ok(groups
+            .into_iter()
+            .map(|(group, number)| (group, number.unwrap_or(0) as u64))
+            .collect())
+    }
+
+    fn get_message_number_before(
+        &mut self,
+        group: String,
+        time: NaiveDateTime,
+    ) -> QueryResult<u64> {
+        let number: Option<i32> = messages::table
+            .filter(messages::message_group.eq(group))
+            .filter(messages::created_at.lt(time))
+            .select(max(messages::message_number))
+            .first(self)?;
+
+        Ok(number.unwrap_or(0) as u64)
+    }
+
+    fn get_undelivered_messages(&mut self, user_id: u64) -> QueryResult<Vec<Message>> {
+        messages::table
+            .filter(messages::message_receiver.eq(user_id as i32))
+            .filter(messages::delivered.eq(false))
+            .order(messages::message_id.asc())
+            .select(MESSAGE_COLUMNS)
+            .load(self)
+    }
+
+    fn update_messages_delivered(
+        &mut self,
+        message_ids: Vec<u64>,
+        delivered: bool,
+    ) -> QueryResult<usize> {
+        let message_ids: Vec<i32> = message_ids.into_iter().map(|id| id as i32).collect();
+
+        update(messages::table)
+            .filter(messages::message_id.eq_any(message_ids))
+            .set(messages::delivered.eq(delivered))
+            .execute(self)
+    }
+
+    fn update_messages_read(
+        &mut self,
+        group: String,
+        receiver_id: u64,
+        up_to: u64,
+    ) -> QueryResult<usize> {
+        // A read message was delivered as well
+        update(messages::table)
+            .filter(messages::message_group.eq(group))
+            .filter(messages::message_receiver.eq(receiver_id as i32))
+            .filter(messages::message_number.le(up_to as i32))
+            .filter(messages::read.eq(false))
+            .set((messages::read.eq(true), messages::delivered.eq(true)))
+            .execute(self)
+    }
+
+    fn edit_message(
+        &mut self,
+        previous: NewMessageEdit,
+        new_text: String,
+        edited_at: NaiveDateTime,
+    ) -> QueryResult<usize> {
+        self.transaction(|conn| {
+            insert_into(message_edits::table)
+                .values((
+                    message_edits::message_id.eq(previous.message_id),
+                    message_edits::message_text.eq(previous.message_text),
+                    message_edits::created_at.eq(previous.created_at),
+                ))
+                .execute(conn)?;
+
+            update(messages::table.find(previous.message_id))
+                .set((
+                    messages::message_text.eq(Some(new_text)),
+                    messages::edited_at.eq(Some(edited_at)),
+                ))
+                .execute(conn)
+        })
+    }
+
+    fn get_message_edits(&mut self, message_id: u64) -> QueryResult<Vec<MessageEdit>> {
+        message_edits::table
+            .filter(message_edits::message_id.eq(message_id as i32))
+            .order(message_edits::edit_id.asc())
+            .select(MESSAGE_EDIT_COLUMNS)
+            .load(self)
+    }
+
+    fn set_message_reaction(&mut self, reaction: MessageReaction) -> QueryResult<usize> {
+        self.transaction(|conn| {
+            delete(message_reactions::table.find((reaction.message_id, reaction.user_id)))
+                .execute(conn)?;
+
+            insert_into(message_reactions::table)
+                .values((
+                    message_reactions::message_id.eq(reaction.message_id),
+                    message_reactions::user_id.eq(reaction.user_id),
+                    message_reactions::emoji.eq(reaction.emoji),
+                    message_reactions::created_at.eq(reaction.created_at),
+                ))
+                .execute(conn)
+        })
+    }
+
+    fn remove_message_reaction(&mut self, message_id: u64, user_id: u64) -> QueryResult<usize> {
+        delete(message_reactions::table.find((message_id as i32, user_id as i32))).execute(self)
+    }
+
+    fn get_message_reactions(
+        &mut self,
+        message_ids: Vec<u64>,
+    ) -> QueryResult<Vec<MessageReaction>> {
+        let message_ids: Vec<i32> = message_ids.into_iter().map(|id| id as i32).collect();
+
+        message_reactions::table
+            .filter(message_reactions::message_id.eq_any(message_ids))
+            .order(message_reactions::created_at.asc())
+            .select(MESSAGE_REACTION_COLUMNS)
+            .load(self)
+    }
+
+    fn delete_message_with_number(&mut self, group: String, number: u64) -> QueryResult<usize> {
+        self.transaction(|conn| {
+            let message_ids = messages::table
+                .filter(messages::message_group.eq(group.to_owned()))
+                .filter(messages::message_number.eq(number as i32))
+                .select(messages::message_id);
+            delete(
+                message_edits::table.filter(message_edits::message_id.eq_any(message_ids.clone())),
+            )
+            .execute(conn)?;
+            delete(
+                message_reactions::table.filter(message_reactions::message_id.eq_any(message_ids)),
+            )
+            .execute(conn)?;
+
+            update(messages::table)
+                .filter(messages::message_group.eq(group))
+                .filter(messages::message_number.eq(number as i32))
+                .set(messages::message_text.eq(None::<String>))
+                .execute(conn)
+        })
+    }
+
+    fn remove_message_with_number(&mut self, group: String, number: u64) -> QueryResult<usize> {
+        self.transaction(|conn| {
+            let last_number = conn.get_last_message_number(group.to_owned());
+            let removed = delete(messages::table)
+                .filter(messages::message_group.eq(group.to_owned()))
+                .filter(messages::message_number.eq(number as i32))
+                .execute(conn)?;
+
+            if removed > 0 {
+                save_last_message_number(conn, group, last_number)?;
+            }
+            Ok(removed)
+        })
+    }
+
+    fn remove_message_group(&mut self, group: String) -> QueryResult<Vec<u64>> {
+        self.transaction(|conn| {
+            let last_number = conn.get_last_message_number(group.to_owned());
+            let numbers: Vec<i32> = messages::table
+                .filter(messages::message_group.eq(group.to_owned()))
+                .order(messages::message_number.asc())
+                .select(messages::message_number)
+                .load(conn)?;
+            delete(messages::table)
+                .filter(messages::message_group.eq(group.to_owned()))
+                .execute(conn)?;
+
+            if !numbers.is_empty() {
+                save_last_message_number(conn, group, last_number)?;
+            }
+            Ok(numbers.into_iter().map(|number| number as u64).collect())
+        })
+    }
+
+    fn check_connection(&mut self) -> QueryResult<()> {
+        sql_query("SELECT 1").execute(self).map(|_| ())
+    }
+}
@@ -0,0 +1,76 @@
+// SQLite version of db/schema.rs. Timestamps are saved without the time zone
+
+diesel::table! {
+    devices (device_id) {
+        device_id -> Integer,
+        user_id -> Integer,
+        device_name -> Text,
+        token_hash -> Text,
+        token_salt -> Text,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    message_edits (edit_id) {
+        edit_id -> Integer,
+        message_id -> Integer,
+        message_text -> Text,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    message_groups (message_group) {
+        message_group -> Text,
+        last_message_number -> Integer,
+    }
+}
+
+diesel::table! {
+    message_reactions (message_id, user_id) {
+        message_id -> Integer,
+        user_id -> Integer,
+        emoji -> Text,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    messages (message_id) {
+        message_id -> Integer,
+        message_group -> Text,
+        message_number -> Integer,
+        message_text -> Nullable<Text>,
+        message_sender -> Integer,
+        message_receiver -> Integer,
+        created_at -> Timestamp,
+        delivered -> Bool,
+        read -> Bool,
+        edited_at -> Nullable<Timestamp>,
+        reply_to -> Nullable<Integer>,
+    }
+}
+
+diesel::table! {
+    users (user_id) {
+        user_id -> Integer,
+        user_name -> Text,
+        image_link -> Nullable<Text>,
+        disabled -> Bool,
+        last_seen -> Nullable<Timestamp>,
+        hide_last_seen -> Bool,
+    }
+}
+
+diesel::joinable!(devices -> users (user_id));
+diesel::joinable!(message_reactions -> users (user_id));
+
+diesel::allow_tables_to_appear_in_same_query!(
+    devices,
+    message_edits,
+    message_groups,
+    message_reactions,
+    messages,
+    users,
+);
@@ -1,15 +1,21 @@
+use chirp_protocol::FullUserData;
+use chrono::NaiveDateTime;
 use diesel::prelude::*;
-use serde::{Deserialize, Serialize};
 
 use crate::db::schema::users;
 
-#[derive(Queryable, Selectable, Insertable, Identifiable, Clone, Serialize, Deserialize)]
+#[derive(Queryable, Selectable, Insertable, Identifiable, Clone)]
 #[diesel(primary_key(user_id))]
 pub struct User {
     pub user_id: i32,
     pub user_name: String,
     pub image_link: Option<String>,
-    pub user_token: String,
+    // Disabled users can not authenticate
+    pub disabled: bool,
+    // When the last session of the user disconnected. None if the user never did
+    pub last_seen: Option<NaiveDateTime>,
+    // The last seen time is not shared with other users
+    pub hide_last_seen: bool,
 }
 
 impl User {
@@ -18,33 +24,36 @@ impl User {
             user_id: 0,
             user_name: String::new(),
             image_link: None,
-            user_token: String::new(),
+            disabled: false,
+            last_seen: None,
+            hide_last_seen: false,
         }
     }
 
-    pub fn from_json(data: String) -> Self {
-        serde_json::from_str(&data).unwrap()
-    }
-
-    pub fn update_id(self, id: usize) -> Self {
+    pub fn from_user_data(user_data: FullUserData) -> Self {
         User {
-            user_id: id as i32,
-            user_name: self.user_name,
-            image_link: self.image_link,
-            user_token: self.user_token,
+            user_id: user_data.user_id as i32,
+            user_name: user_data.user_name,
+            image_link: user_data.image_link,
+            disabled: false,
+            last_seen: None,
+            hide_last_seen: false,
         }
     }
 
-    pub fn update_token(self, token: String) -> Self {
+    pub fn update_id(self, id: u64) -> Self {
         User {
-            user_id: self.user_id,
+            user_id: id as i32,
             user_name: self.user_name,
             image_link: self.image_link,
-            user_token: token,
+            disabled: self.disabled,
+            last_seen: self.last_seen,
+            hide_last_seen: self.hide_last_seen,
         }
     }
 
-    pub fn to_json(&self) -> String {
-        serde_json::to_string(self).unwrap()
+    /// Converts to the data that is sent to the clients
+    pub fn into_user_data(self) -> FullUserData {
+        FullUserData::new(self.user_id as u64, self.user_name, self.image_link)
     }
 }
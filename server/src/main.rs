@@ -1,78 +1,139 @@
+mod api;
+mod config;
 mod db;
+mod metrics;
 mod server;
 mod session;
+mod tls;
 mod utils;
 
 use actix::*;
 use actix_web::{web, App, Error, HttpRequest, HttpResponse, HttpServer};
 use actix_web_actors::ws;
+use chirp_protocol::{ErrorCode, ErrorData};
+use config::{Config, HeartbeatConfig};
+use db::{DbExecutor, RunQuery, Storage, StorageBackend};
 use dotenvy::dotenv;
-use rustls::{Certificate, PrivateKey, ServerConfig};
-use rustls_pemfile::{certs, pkcs8_private_keys};
 use server::ChatServer;
-use std::fs::File;
-use std::io::BufReader;
+use std::collections::HashMap;
 use std::time::Instant;
-use tracing::error;
+use tls::{load_rustls_config, save_client_certificate, watch_certificates, ClientCertificate};
+use tracing::{error, info};
+
+// Number of threads that run the DB queries. Each one can hold a connection from the pool
+const DB_EXECUTOR_THREADS: usize = 4;
 
 async fn chat_route(
     req: HttpRequest,
     stream: web::Payload,
     srv: web::Data<Addr<server::ChatServer>>,
+    heartbeat: web::Data<HeartbeatConfig>,
+    client_users: web::Data<HashMap<String, u64>>,
 ) -> Result<HttpResponse, Error> {
+    let cert_user = req
+        .conn_data::<ClientCertificate>()
+        .and_then(|certificate| certificate.common_name.as_ref())
+        .and_then(|common_name| client_users.get(common_name))
+        .copied();
+
     ws::start(
         session::WsChatSession {
             id: 0,
-            user_id: 0,
             hb: Instant::now(),
             addr: srv.get_ref().clone(),
+            heartbeat: *heartbeat.get_ref(),
+            cert_user,
+            ip: req.peer_addr().map(|address| address.ip()),
         },
         &req,
         stream,
     )
 }
 
-fn load_rustls_config() -> ServerConfig {
-    let config = ServerConfig::builder()
-        .with_safe_defaults()
-        .with_no_client_auth();
-
-    let cert_file = &mut BufReader::new(File::open("./server/src/tls_cert_key/cert.pem").unwrap());
-    let key_file = &mut BufReader::new(File::open("./server/src/tls_cert_key/key.pem").unwrap());
-
-    let cert_chain = certs(cert_file)
-        .unwrap()
-        .into_iter()
-        .map(Certificate)
-        .collect();
-    let mut keys: Vec<PrivateKey> = pkcs8_private_keys(key_file)
-        .unwrap()
-        .into_iter()
-        .map(PrivateKey)
-        .collect();
-
-    if keys.is_empty() {
-        error!("Could not locate PKCS8 private keys.");
-        std::process::exit(1);
+/// Every metric in the Prometheus text format
+async fn metrics_route() -> HttpResponse {
+    match metrics::encode_metrics() {
+        Ok(metrics) => HttpResponse::Ok()
+            .content_type("text/plain; version=0.0.4")
+            .body(metrics),
+        Err(e) => {
+            error!("{e}");
+            HttpResponse::InternalServerError().finish()
+        }
     }
+}
+
+/// Healthy as long as a query can be run on the database
+async fn healthz_route(db: web::Data<Addr<DbExecutor>>) -> HttpResponse {
+    let query = RunQuery(Box::new(|storage: &mut dyn Storage| {
+        storage.check_connection().map_err(|e| {
+            error!("Health check query failed: {e}");
+            ErrorData::new(ErrorCode::ServerError, "The database is unavailable")
+        })
+    }));
 
-    config.with_single_cert(cert_chain, keys.remove(0)).unwrap()
+    match db.send(query).await {
+        Ok(Ok(())) => HttpResponse::Ok().body("ok"),
+        Ok(Err(e)) => HttpResponse::ServiceUnavailable().body(e.message),
+        Err(e) => {
+            error!("Failed to reach the DB executor: {e}");
+            HttpResponse::ServiceUnavailable().body("The database is unavailable")
+        }
+    }
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    tracing_subscriber::fmt::init();
     dotenv().ok();
 
-    let server = ChatServer::new().start();
-    let config = load_rustls_config();
+    let config = Config::load().unwrap_or_else(|e| {
+        eprintln!("Invalid configuration: {e}");
+        std::process::exit(1);
+    });
+
+    tracing_subscriber::fmt()
+        .with_max_level(config.log_level().unwrap())
+        .init();
 
-    HttpServer::new(move || {
+    let (tls_config, cert_resolver) = load_rustls_config(&config.tls).unwrap_or_else(|e| {
+        error!("Failed to load the TLS config: {e}");
+        std::process::exit(1);
+    });
+
+    let storage = StorageBackend::new(config.database_url(), DB_EXECUTOR_THREADS as u32)
+        .unwrap_or_else(|e| {
+            error!("Failed to set up the storage: {e}");
+            std::process::exit(1);
+        });
+    let db = SyncArbiter::start(DB_EXECUTOR_THREADS, move || DbExecutor(storage.clone()));
+
+    metrics::register_metrics();
+
+    let server = ChatServer::new(db.clone(), config.rate_limit, config.validation.clone()).start();
+    let heartbeat = config.heartbeat;
+    let client_users = config.tls.client_users.clone();
+    let admin_token = config.admin_token.clone();
+
+    let mut http_server = HttpServer::new(move || {
         App::new()
             .app_data(web::Data::new(server.clone()))
+            .app_data(web::Data::new(db.clone()))
+            .app_data(web::Data::new(heartbeat))
+            .app_data(web::Data::new(client_users.clone()))
             .route("/ws/", web::get().to(chat_route))
+            .route("/metrics", web::get().to(metrics_route))
+            .route("/healthz", web::get().to(healthz_route))
+            .configure(|cfg| api::configure_admin(cfg, admin_token.clone()))
+            .configure(api::configure_history)
     })
-    .bind_rustls_021("127.0.0.1:8080", config)?
-    .run()
-    .await
+    .on_connect(save_client_certificate);
+
+    for address in config.bind_addresses().unwrap() {
+        info!("Listening on {}", address);
+        http_server = http_server.bind_rustls_021(address, tls_config.clone())?;
+    }
+
+    watch_certificates(cert_resolver, config.tls.clone());
+
+    http_server.run().await
 }
@@ -0,0 +1,72 @@
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_histogram, register_int_counter, register_int_gauge, Encoder, Histogram, IntCounter,
+    IntGauge, TextEncoder,
+};
+
+// Every metric is registered on the default registry the first time it gets used
+
+/// Number of connected WS sessions, authenticated or not
+pub static SESSIONS: Lazy<IntGauge> =
+    Lazy::new(|| register_int_gauge!("chirp_sessions", "Number of connected WS sessions").unwrap());
+
+/// Number of users with at least one authenticated WS session
+pub static USER_SESSIONS: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "chirp_user_sessions",
+        "Number of users with at least one authenticated WS session"
+    )
+    .unwrap()
+});
+
+pub static MESSAGES_SENT: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "chirp_messages_sent_total",
+        "Number of chat messages saved and sent to the receiver"
+    )
+    .unwrap()
+});
+
+pub static DB_QUERY_DURATION: Lazy<Histogram> = Lazy::new(|| {
+    register_histogram!(
+        "chirp_db_query_duration_seconds",
+        "Time taken by each query on the DB executor, including getting a connection"
+    )
+    .unwrap()
+});
+
+pub static DB_QUERY_ERRORS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "chirp_db_query_errors_total",
+        "Number of DB queries and connection checkouts that failed"
+    )
+    .unwrap()
+});
+
+pub static HEARTBEAT_DISCONNECTS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "chirp_heartbeat_disconnects_total",
+        "Number of WS sessions closed for not answering the heartbeat in time"
+    )
+    .unwrap()
+});
+
+/// Registers every metric so they are included in the output before their first update
+pub fn register_metrics() {
+    Lazy::force(&SESSIONS);
+    Lazy::force(&USER_SESSIONS);
+    Lazy::force(&MESSAGES_SENT);
+    Lazy::force(&DB_QUERY_DURATION);
+    Lazy::force(&DB_QUERY_ERRORS);
+    Lazy::force(&HEARTBEAT_DISCONNECTS);
+}
+
+/// Every registered metric in the Prometheus text format
+pub fn encode_metrics() -> Result<String, String> {
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&prometheus::gather(), &mut buffer)
+        .map_err(|e| format!("Failed to encode metrics: {e}"))?;
+
+    String::from_utf8(buffer).map_err(|e| format!("Failed to encode metrics: {e}"))
+}
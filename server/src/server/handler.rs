@@ -1,415 +1,1471 @@
 use actix::prelude::*;
-use chrono::NaiveDateTime;
-use diesel::pg::PgConnection;
-use diesel::prelude::*;
+use chirp_protocol::{
+    ContentLimits, DeleteMessage, EditMessage, ErrorCode, ErrorData, FullUserData, ImageUpdate,
+    MessageData, MessageHistory, MessageHistoryRequest, MessageReceipt, MessageStatus,
+    MessageSyncRequest, NameUpdate, PairDevice, PairingCode, Presence, PresenceStatus, Reaction,
+    ReactionRequest, ReactionUpdate, ServerEvent, TypingUpdate, UserIDs,
+};
+use chrono::{DateTime, NaiveDateTime, Utc};
 use rand::rngs::ThreadRng;
 use rand::Rng;
 use std::collections::HashMap;
-use std::env;
+use std::time::{Duration, Instant};
 use tracing::{error, info};
 
+use crate::config::RateLimitConfig;
 use crate::db::{
-    create_new_message, create_new_user, delete_message_with_number, get_last_message_number,
-    get_messages_from_number, get_user_with_id, get_user_with_token, update_user_image_link,
-    update_user_name, NewMessage, User,
+    db_error, DbExecutor, Device, MessageReaction, NewDevice, NewMessage, NewMessageEdit, RunQuery,
+    Storage, User,
 };
-use crate::server::{
-    DeleteMessage, IDInfo, ImageUpdate, Message, MessageData, NameUpdate, SendUserData,
-    SyncMessage, SyncMessageData, WSData,
+use crate::metrics::{MESSAGES_SENT, SESSIONS, USER_SESSIONS};
+use crate::server::{CloseSession, IDInfo, Message, RateLimiter, RequestKind};
+use crate::utils::{
+    create_message_group, generate_pairing_code, generate_token_salt, generate_user_token,
+    hash_user_token,
 };
-use crate::utils::{create_message_group, generate_user_token};
+
+// How long a pairing code can be used for adding a new device
+const PAIRING_CODE_LIFETIME: Duration = Duration::from_secs(300);
+
+// Name of the device that gets created together with a new user
+const DEFAULT_DEVICE_NAME: &str = "Primary device";
+
+/// The result of a processed request. Requests that use the DB finish once the query is done
+pub type RequestResult = PendingResult<()>;
+
+/// The result of work on the ChatServer that has to wait for the DB
+pub type PendingResult<R> = ResponseActFuture<ChatServer, Result<R, ErrorData>>;
 
 pub struct ChatServer {
     // {WS session ID: (IDInfo, WS Receiver)}
     pub sessions: HashMap<usize, (IDInfo, Recipient<Message>)>,
-    // The gui side has 1 WS session per user added
-    // 1 owner session + 1 for every single users added for chatting
-    // If there are 2 users chatting with each other, there will be total 4 sessions
-    // user 1: [user 1/owner session, a WS session containing user 2 ID]
-    // user 2: [user 2/owner session, a WS session containing user 1 ID]
-    // {User ID: [All the sessions this user added including owner session]}
-    pub user_session: HashMap<usize, Vec<WSData>>,
+    // The gui side has a single WS session per client that carries every chat
+    // {User ID: [All the WS session IDs the user is connected with]}
+    pub user_session: HashMap<u64, Vec<usize>>,
+    // {WS session ID: Receiver used for closing the WS session}
+    pub session_closers: HashMap<usize, Recipient<CloseSession>>,
+    // {Pairing code: (User ID, Expiry time)}
+    pub pairing_codes: HashMap<String, (u64, Instant)>,
     pub rng: ThreadRng,
-    conn: PgConnection,
+    pub rate_limiter: RateLimiter,
+    // Checked before any user content is saved
+    content_limits: ContentLimits,
+    // Every DB operation runs on the executor so the routing never waits for the DB
+    db: Addr<DbExecutor>,
 }
 
 impl ChatServer {
-    pub fn new() -> ChatServer {
+    pub fn new(
+        db: Addr<DbExecutor>,
+        rate_limit: RateLimitConfig,
+        content_limits: ContentLimits,
+    ) -> ChatServer {
         info!("New Chat Server getting created");
 
-        let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-
-        let conn = PgConnection::establish(&database_url)
-            .unwrap_or_else(|_| panic!("Error connecting to {}", database_url));
-
         ChatServer {
             sessions: HashMap::new(),
             user_session: HashMap::new(),
+            session_closers: HashMap::new(),
+            pairing_codes: HashMap::new(),
             rng: rand::thread_rng(),
-            conn,
+            rate_limiter: RateLimiter::new(rate_limit),
+            content_limits,
+            db,
         }
     }
 
-    /// Send a message to another WS session
-    pub fn send_message(&mut self, message_data: MessageData) {
-        let from_user_id;
+    /// Runs the query on the DB executor and continues with the result on the ChatServer
+    fn run_query<T, R, Q, F>(&self, query: Q, then: F) -> PendingResult<R>
+    where
+        T: Send + 'static,
+        R: 'static,
+        Q: FnOnce(&mut dyn Storage) -> Result<T, ErrorData> + Send + 'static,
+        F: FnOnce(&mut ChatServer, T) -> Result<R, ErrorData> + 'static,
+    {
+        let request = self.db.send(RunQuery(Box::new(query)));
+
+        Box::pin(request.into_actor(self).map(move |result, act, _| {
+            let data = result.map_err(|e| {
+                error!("Failed to reach the DB executor: {e}");
+                ErrorData::new(ErrorCode::ServerError, "The database is unavailable")
+            })??;
+            then(act, data)
+        }))
+    }
 
-        if let Some(from_user) =
-            get_user_with_token(&mut self.conn, message_data.user_token.to_owned())
-        {
-            from_user_id = from_user.user_id as usize;
-        } else {
-            error!("Invalid user token received. Discarding request");
-            return;
-        }
+    /// Wraps the result of a request that did not need the DB
+    pub fn finished<R: 'static>(result: Result<R, ErrorData>) -> PendingResult<R> {
+        Box::pin(fut::ready(result))
+    }
 
-        let send_message_data = message_data.to_json();
-        let user_message = message_data.message.to_owned();
-        let to_user_id = message_data.to_user;
-        let mut conn_found = false;
-        let message_group = create_message_group(from_user_id, to_user_id);
-        let message_number = message_data.message_number;
+    /// Updates the session gauges after sessions or user_session changed
+    pub fn update_session_metrics(&self) {
+        SESSIONS.set(self.sessions.len() as i64);
+        USER_SESSIONS.set(self.user_session.len() as i64);
+    }
 
-        let created_at =
-            NaiveDateTime::parse_from_str(&message_data.created_at, "%Y-%m-%d %H:%M:%S%.6f")
-                .unwrap();
+    /// Sends an event to a single WS session
+    pub fn send_event(&self, ws_id: usize, event: ServerEvent) {
+        if let Some((_, receiver_ws)) = self.sessions.get(&ws_id) {
+            receiver_ws.do_send(Message(event))
+        };
+    }
 
-        let new_message_data = NewMessage::new(
-            message_group,
-            message_number,
-            user_message,
-            from_user_id,
-            to_user_id,
-            created_at,
+    /// Sends the error of a failed request back to the WS session it came from
+    pub fn send_error(&self, ws_id: usize, error_data: ErrorData) {
+        error!(
+            "Request {:?} from WS session {} failed: {}",
+            error_data.command, ws_id, error_data.message
         );
+        self.send_event(ws_id, ServerEvent::Error(error_data));
+    }
 
-        info!("Sending message from {} to {}", from_user_id, to_user_id);
+    /// Sends an event to every WS session of a user except the given one
+    pub fn send_to_user(&self, user_id: u64, except: Option<usize>, event: ServerEvent) {
+        if let Some(ws_ids) = self.user_session.get(&user_id) {
+            for ws_id in ws_ids {
+                if Some(*ws_id) != except {
+                    self.send_event(*ws_id, event.clone());
+                }
+            }
+        } else {
+            info!("No active session id found with the User ID {user_id}");
+        }
+    }
 
-        create_new_message(&mut self.conn, new_message_data);
+    /// Sends an event to every WS session that has added the user as a contact except the given one.
+    /// The other devices of the user are included
+    fn send_to_contacts(&self, user_id: u64, except: usize, event: ServerEvent) {
+        for (ws_id, (id_info, receiver_ws)) in self.sessions.iter() {
+            if *ws_id != except && id_info.contacts.contains(&user_id) {
+                receiver_ws.do_send(Message(event.clone()));
+            }
+        }
+    }
 
-        if from_user_id == to_user_id {
-            info!("From and to users are the same. Stopping sending.");
+    /// Sends the messages the user got while offline to the newly bound session
+    fn send_undelivered_messages(&self, ws_id: usize, undelivered: Vec<MessageData>) {
+        if undelivered.is_empty() {
             return;
         }
 
-        // If a Gui Client adds 10 users for chatting, there will be 10 + owner = 11 WS sessions
-        // We store every single session of an owner in a vec. So it goes like this to find the proper
-        // session and the receiver
-        // Get all the sessions of the to_user => Out of all of them find the sessions
-        // that has the user id of the from_user. Here from_user = a WS session with from_user id that
-        // was added on the to_user's side => Get the receiver => Send message
-        //
-        // If there is no from_user session inside the list of to_user, to_user hasn't
-        // added from_user as a new chat yet. So in this case, get all the sessions of the to_user
-        // => Find the session that has the user id to_user which will always be the owner session
-        // => send a request to the owner
-        if let Some(receiver_ws_data) = self.user_session.get(&to_user_id) {
-            for i in receiver_ws_data {
-                if i.user_id == from_user_id {
-                    conn_found = true;
-                    let ws_id = i.ws_id;
-                    if let Some(receiver_data) = self.sessions.get(&ws_id) {
-                        receiver_data
-                            .1
-                            .do_send(Message(format!("/message {}", send_message_data)))
-                    }
-                    break;
-                }
+        info!(
+            "Sending {} undelivered messages to WS session {}",
+            undelivered.len(),
+            ws_id
+        );
+        let receiver_id = self.session_owner(ws_id);
+        // {Sender User ID: Last delivered message number}
+        let mut delivered: HashMap<u64, u64> = HashMap::new();
+
+        for message_data in undelivered {
+            let last_number = delivered.entry(message_data.from_user).or_default();
+            *last_number = (*last_number).max(message_data.message_number);
+            self.send_event(ws_id, ServerEvent::Message(message_data));
+        }
+
+        for (sender_id, message_number) in delivered {
+            if sender_id == receiver_id {
+                continue;
             }
+            self.send_to_user(
+                sender_id,
+                None,
+                ServerEvent::MessageReceipt(MessageReceipt {
+                    user_id: receiver_id,
+                    message_number,
+                    status: MessageStatus::Delivered,
+                }),
+            );
+        }
+    }
 
-            if !conn_found {
-                info!("Client session exists but the user was not added. Sending request to add the user");
-                for i in receiver_ws_data {
-                    if i.user_id == to_user_id {
-                        let ws_id = i.ws_id;
-                        if let Some(receiver_data) = self.sessions.get(&ws_id) {
-                            let user_data = get_user_with_id(&mut self.conn, from_user_id)
-                                .unwrap()
-                                .update_token(String::new())
-                                .to_json();
-
-                            receiver_data
-                                .1
-                                .do_send(Message(format!("/new-user-message {}", user_data)))
-                        }
-                        break;
-                    }
+    /// Updates the delivery state of a saved message without waiting for the result
+    fn set_message_delivered(&self, message_id: u64, delivered: bool) {
+        self.db
+            .do_send(RunQuery(Box::new(move |storage: &mut dyn Storage| {
+                storage
+                    .update_messages_delivered(vec![message_id], delivered)
+                    .map(|_| ())
+                    .map_err(|e| db_error(e, "Failed to update the message delivery"))
+            })));
+    }
+
+    /// Closes the WS sessions of a user except the given one. If a device is given, only the sessions
+    /// of that device are closed. The sessions are unbound right away so requests that are already
+    /// queued from them do not get processed
+    pub fn close_user_sessions(
+        &mut self,
+        user_id: u64,
+        device_id: Option<u64>,
+        except: Option<usize>,
+        reason: ErrorData,
+    ) {
+        self.track_presence(user_id, |act| {
+            let Some(ws_ids) = act.user_session.remove(&user_id) else {
+                return;
+            };
+
+            for ws_id in ws_ids {
+                let Some((id_info, _)) = act.sessions.get_mut(&ws_id) else {
+                    continue;
+                };
+
+                let other_device = device_id.is_some_and(|id| id != id_info.device_id);
+                if Some(ws_id) == except || other_device {
+                    act.user_session.entry(user_id).or_default().push(ws_id);
+                    continue;
+                }
+
+                info!("Closing WS session {} of User ID {}", ws_id, user_id);
+                id_info.reset();
+                if let Some(closer) = act.session_closers.get(&ws_id) {
+                    closer.do_send(CloseSession(reason.clone()));
                 }
             }
-        } else {
-            info!("No active session id found with the User ID {to_user_id}");
-        }
+        });
+        self.update_session_metrics();
     }
 
-    /// Creates, saves and broadcasts the new user to the relevant session
-    pub fn create_new_user(&mut self, ws_id: usize, other_data: String) {
-        let mut user_id = self.rng.gen_range(1..=2_147_483_647) as usize;
+    /// Issues a new token to the device. Every other session using the old token gets closed
+    pub fn rotate_token(&mut self, ws_id: usize, user_id: u64) -> RequestResult {
+        let device_id = self.session_device(ws_id);
         let user_token = generate_user_token();
+        let token_salt = generate_token_salt();
+        let token_hash = hash_user_token(&user_token, &token_salt);
 
-        while get_user_with_id(&mut self.conn, user_id).is_some() {
-            info!("Generated user ID already exist. Creating a new ID");
-            user_id = self.rng.gen_range(1..=2_147_483_647) as usize;
-        }
+        info!(
+            "Rotating token of device {} of User ID {}",
+            device_id, user_id
+        );
 
-        info!("Creating new user with User ID {user_id}");
+        self.run_query(
+            move |storage| {
+                storage
+                    .update_device_token(device_id, &token_hash, &token_salt)
+                    .map_err(|e| db_error(e, "Failed to rotate the token"))
+            },
+            move |act, _| {
+                act.close_user_sessions(
+                    user_id,
+                    Some(device_id),
+                    Some(ws_id),
+                    ErrorData::new(ErrorCode::InvalidToken, "The user token was rotated"),
+                );
+                act.send_event(
+                    ws_id,
+                    ServerEvent::TokenRotated(UserIDs::new(user_id, user_token)),
+                );
+                Ok(())
+            },
+        )
+    }
 
-        let user_data = User::from_json(other_data)
-            .update_id(user_id)
-            .update_token(user_token.to_owned());
+    /// Removes the device of the session from the account and closes every session using its token
+    pub fn revoke_token(&mut self, ws_id: usize, user_id: u64) -> RequestResult {
+        let device_id = self.session_device(ws_id);
+
+        info!("Revoking device {} of User ID {}", device_id, user_id);
+
+        self.run_query(
+            move |storage| {
+                storage
+                    .delete_device(device_id)
+                    .map_err(|e| db_error(e, "Failed to revoke the token"))
+            },
+            move |act, _| {
+                act.close_user_sessions(
+                    user_id,
+                    Some(device_id),
+                    None,
+                    ErrorData::new(ErrorCode::InvalidToken, "The user token was revoked"),
+                );
+                Ok(())
+            },
+        )
+    }
 
-        create_new_user(&mut self.conn, user_data);
+    /// Binds the user and the device to the session so it can receive events and send requests
+    fn bind_session(&mut self, ws_id: usize, user_id: u64, device_id: u64, hide_last_seen: bool) {
+        self.track_presence(user_id, |act| {
+            if let Some((id_info, _)) = act.sessions.get_mut(&ws_id) {
+                id_info.owner_id = user_id;
+                id_info.device_id = device_id;
+                id_info.hide_last_seen = hide_last_seen;
+                id_info.contacts.insert(user_id);
+                act.user_session
+                    .entry(user_id)
+                    .or_insert(Vec::new())
+                    .push(ws_id);
+            }
+        });
+        self.update_session_metrics();
+    }
 
-        let id_data = IDInfo {
-            user_id,
-            owner_id: user_id,
-            user_token,
+    /// Removes the user binding of a session so it can be bound to a different user
+    fn unbind_session(&mut self, ws_id: usize) {
+        let owner_id = self.session_owner(ws_id);
+        self.track_presence(owner_id, |act| {
+            if let Some(ws_ids) = act.user_session.get_mut(&owner_id) {
+                ws_ids.retain(|id| id != &ws_id);
+                if ws_ids.is_empty() {
+                    act.user_session.remove(&owner_id);
+                }
+            }
+            if let Some((id_info, _)) = act.sessions.get_mut(&ws_id) {
+                id_info.reset();
+            }
+        });
+        self.update_session_metrics();
+    }
+
+    /// The presence of the user based on its sessions
+    pub fn user_presence(&self, user_id: u64) -> PresenceStatus {
+        let Some(ws_ids) = self.user_session.get(&user_id) else {
+            return PresenceStatus::Offline;
         };
 
-        let ws_data = WSData::new(user_id, ws_id);
+        let all_away = ws_ids.iter().all(|ws_id| {
+            self.sessions
+                .get(ws_id)
+                .is_some_and(|(id_info, _)| id_info.away)
+        });
 
-        self.user_session
-            .entry(user_id)
-            .or_insert(Vec::new())
-            .push(ws_data);
-
-        if let Some(entry) = self.sessions.get_mut(&ws_id) {
-            let (id_info, receiver_ws) = entry;
-            *id_info = id_data.clone();
-            receiver_ws.do_send(Message(format!("/update-user-id {}", id_data.to_json())))
+        if all_away {
+            PresenceStatus::Away
+        } else {
+            PresenceStatus::Online
         }
     }
 
-    /// Reconnect with an existing user and save necessary session information
-    pub fn reconnect_user(&mut self, ws_id: usize, mut id_data: IDInfo) {
-        let owner_id;
+    /// Whether the user hides the last seen time. Only known while the user has a session
+    fn hides_last_seen(&self, user_id: u64) -> bool {
+        self.user_session
+            .get(&user_id)
+            .and_then(|ws_ids| ws_ids.first())
+            .and_then(|ws_id| self.sessions.get(ws_id))
+            .is_some_and(|(id_info, _)| id_info.hide_last_seen)
+    }
 
-        if let Some(owner_data) = get_user_with_token(&mut self.conn, id_data.user_token.clone()) {
-            owner_id = owner_data.user_id as usize;
-        } else {
-            error!("Invalid user token received. Discarding request");
-            return;
-        };
+    /// Runs the change and tells the contacts of the user if its presence changed because of it.
+    /// The last seen time is saved once the last session of the user is gone
+    pub fn track_presence<R>(&mut self, user_id: u64, change: impl FnOnce(&mut Self) -> R) -> R {
+        let before = self.user_presence(user_id);
+        let hide_last_seen = self.hides_last_seen(user_id);
 
-        let user_id = id_data.user_id;
-        id_data.update_owner_id(owner_id);
+        let result = change(self);
 
-        info!(
-            "Reconnecting with User ID {} with owner ID {}",
-            user_id, owner_id
-        );
+        let status = self.user_presence(user_id);
+        if status == before {
+            return result;
+        }
 
-        if let Some(user_data) = get_user_with_id(&mut self.conn, user_id) {
-            let ws_data = WSData::new(user_id, ws_id);
+        info!("User ID {} is now {:?}", user_id, status);
 
-            let session_data = self.user_session.entry(owner_id).or_insert(Vec::new());
-            if !session_data.contains(&ws_data) {
-                session_data.push(ws_data);
+        let mut last_seen = None;
+        if status == PresenceStatus::Offline {
+            let now = Utc::now().naive_utc();
+            self.save_last_seen(user_id, now);
+            if !hide_last_seen {
+                last_seen = Some(now);
             }
+        }
 
-            if let Some(entry) = self.sessions.get_mut(&ws_id) {
-                let (id_info, receiver_ws) = entry;
-                *id_info = id_data.clone();
+        self.send_presence(user_id, status, last_seen);
+        result
+    }
 
-                receiver_ws.do_send(Message(format!(
-                    "/reconnect-success {}",
-                    user_data.update_token(String::new()).to_json()
-                )));
+    /// Sends the presence of the user to every session that has added it as a contact.
+    /// The sessions of the user itself are skipped
+    fn send_presence(
+        &self,
+        user_id: u64,
+        status: PresenceStatus,
+        last_seen: Option<NaiveDateTime>,
+    ) {
+        let event = ServerEvent::Presence(presence_data(user_id, status, last_seen));
+
+        for (id_info, receiver_ws) in self.sessions.values() {
+            if id_info.owner_id != user_id && id_info.contacts.contains(&user_id) {
+                receiver_ws.do_send(Message(event.clone()));
             }
-        } else {
-            error!("Unable to reconnect with a non-existing user")
         }
     }
 
-    /// Sends a user profile data to a client
-    pub fn send_user_data(&mut self, ws_id: usize, user_data: SendUserData) {
-        if get_user_with_token(&mut self.conn, user_data.user_token).is_none() {
-            error!("Invalid user token received. Discarding request");
-            return;
+    /// Saves when the user was last seen without waiting for the result
+    fn save_last_seen(&self, user_id: u64, last_seen: NaiveDateTime) {
+        self.db
+            .do_send(RunQuery(Box::new(move |storage: &mut dyn Storage| {
+                storage
+                    .update_user_last_seen(user_id, last_seen)
+                    .map(|_| ())
+                    .map_err(|e| db_error(e, "Failed to update the last seen time"))
+            })));
+    }
+
+    /// Marks the session as online or away
+    pub fn set_presence(
+        &mut self,
+        ws_id: usize,
+        owner_id: u64,
+        status: PresenceStatus,
+    ) -> Result<(), ErrorData> {
+        if status == PresenceStatus::Offline {
+            return Err(ErrorData::new(
+                ErrorCode::InvalidData,
+                "A session can only be set as online or away",
+            ));
         }
 
-        let id = user_data.user_id;
+        self.track_presence(owner_id, |act| {
+            if let Some((id_info, _)) = act.sessions.get_mut(&ws_id) {
+                id_info.away = status == PresenceStatus::Away;
+            }
+        });
+        Ok(())
+    }
 
-        info!("Sending User ID {} profile data", id);
-        if let Some(user_data) = get_user_with_id(&mut self.conn, id) {
-            let user_data = user_data.update_token(String::new()).to_json();
-            if let Some((_, receiver_ws)) = self.sessions.get(&ws_id) {
-                receiver_ws.do_send(Message(format!("/get-user-data {}", user_data)))
-            };
-        } else {
-            let empty_user = User::new().to_json();
-            if let Some((_, receiver_ws)) = self.sessions.get(&ws_id) {
-                receiver_ws.do_send(Message(format!("/get-user-data {}", empty_user)))
-            };
+    /// Saves whether contacts can see when the user was last online
+    pub fn hide_last_seen(&mut self, owner_id: u64, hide: bool) -> RequestResult {
+        info!("Setting hide last seen of User ID {} to {}", owner_id, hide);
+
+        self.run_query(
+            move |storage| {
+                storage
+                    .update_user_hide_last_seen(owner_id, hide)
+                    .map_err(|e| db_error(e, "Failed to update the last seen privacy"))
+            },
+            move |act, _| {
+                if let Some(ws_ids) = act.user_session.get(&owner_id) {
+                    for ws_id in ws_ids {
+                        if let Some((id_info, _)) = act.sessions.get_mut(ws_id) {
+                            id_info.hide_last_seen = hide;
+                        }
+                    }
+                }
+                Ok(())
+            },
+        )
+    }
+
+    /// Get the user ID the session is authenticated as. 0 if not authenticated
+    pub fn session_owner(&self, ws_id: usize) -> u64 {
+        self.sessions
+            .get(&ws_id)
+            .map(|(id_info, _)| id_info.owner_id)
+            .unwrap_or(0)
+    }
+
+    /// Refuses to bind the session to a user the client certificate does not belong to
+    fn check_cert_user(&self, ws_id: usize, user_id: Option<u64>) -> Result<(), ErrorData> {
+        let cert_user = self
+            .sessions
+            .get(&ws_id)
+            .and_then(|(id_info, _)| id_info.cert_user);
+
+        match cert_user {
+            Some(cert_user) if Some(cert_user) != user_id => Err(ErrorData::new(
+                ErrorCode::NotAuthenticated,
+                "The client certificate belongs to a different user",
+            )),
+            _ => Ok(()),
         }
     }
 
-    /// Updates user name of a user
-    pub fn user_name_update(&mut self, update_data: NameUpdate) {
-        let user_id;
+    /// Takes a token from the rate limits of the session, its account and its IP.
+    /// The session is closed if it keeps sending requests while limited
+    pub fn limit_request(&mut self, ws_id: usize, kind: RequestKind) -> Result<(), ErrorData> {
+        let Some((id_info, _)) = self.sessions.get(&ws_id) else {
+            return Ok(());
+        };
+        let owner_id = Some(id_info.owner_id).filter(|id| *id != 0);
+
+        let Err(retry_after) = self
+            .rate_limiter
+            .check(kind, Some(ws_id), owner_id, id_info.ip)
+        else {
+            self.rate_limiter.clear_violations(ws_id);
+            return Ok(());
+        };
 
-        if let Some(user_data) = get_user_with_token(&mut self.conn, update_data.user_token) {
-            user_id = user_data.user_id as usize;
-        } else {
-            error!("Invalid user token received. Discarding request");
-            return;
+        let error_data = ErrorData::rate_limited(retry_after);
+        if self.rate_limiter.add_violation(ws_id) {
+            info!("Closing WS session {} for exceeding the rate limits", ws_id);
+            if let Some(closer) = self.session_closers.get(&ws_id) {
+                closer.do_send(CloseSession(error_data.clone()));
+            }
         }
+        Err(error_data)
+    }
 
-        let new_name = update_data.new_name;
+    /// Checks the name and image link of a new user
+    fn check_user_content(&self, name: &str, image_link: &Option<String>) -> Result<(), ErrorData> {
+        check_content(self.content_limits.validate_user_name(name))?;
+        if let Some(link) = image_link {
+            check_content(self.content_limits.validate_image_link(link))?;
+        }
+        Ok(())
+    }
 
-        info!("Updating name of user {} to {new_name}", user_id);
+    /// Get the device ID the session is authenticated with. 0 if not authenticated
+    fn session_device(&self, ws_id: usize) -> u64 {
+        self.sessions
+            .get(&ws_id)
+            .map(|(id_info, _)| id_info.device_id)
+            .unwrap_or(0)
+    }
 
-        update_user_name(&mut self.conn, user_id, &new_name);
+    /// Verifies the token and authenticates the session as the device owner
+    pub fn authenticate(&mut self, ws_id: usize, id_data: UserIDs) -> RequestResult {
+        if self.session_owner(ws_id) != 0 {
+            return Self::finished(Err(ErrorData::new(
+                ErrorCode::InvalidRequest,
+                "The session is already authenticated",
+            )));
+        }
 
-        // broadcast the name update to every active session that has added this user id
-        for (id, session_data) in self.user_session.iter() {
-            if id != &user_id {
-                for session in session_data {
-                    if session.user_id == user_id {
-                        if let Some(data) = self.sessions.get(&session.ws_id) {
-                            let receiver = &data.1;
-                            receiver.do_send(Message(format!("/name-updated {new_name}")));
-                        }
-                    }
-                }
+        if let Err(e) = self.check_cert_user(ws_id, Some(id_data.user_id)) {
+            return Self::finished(Err(e));
+        }
+
+        self.run_query(
+            move |storage| {
+                let device = verify_device_token(storage, id_data.user_id, &id_data.user_token)?;
+                let user_data = get_enabled_user(storage, id_data.user_id)?;
+                let undelivered = take_undelivered_messages(storage, id_data.user_id)?;
+                Ok((device, user_data, undelivered))
+            },
+            move |act, (device, user_data, undelivered)| {
+                let user_id = user_data.user_id as u64;
+
+                info!(
+                    "Authenticated WS session {} as User ID {} with device {}",
+                    ws_id, user_id, device.device_id
+                );
+
+                act.bind_session(
+                    ws_id,
+                    user_id,
+                    device.device_id as u64,
+                    user_data.hide_last_seen,
+                );
+                act.send_event(
+                    ws_id,
+                    ServerEvent::Authenticated(user_data.into_user_data()),
+                );
+                act.send_undelivered_messages(ws_id, undelivered);
+                Ok(())
+            },
+        )
+    }
+
+    /// Creates a pairing code that lets a new device join the account of the user
+    pub fn create_pairing_code(&mut self, ws_id: usize, user_id: u64) -> Result<(), ErrorData> {
+        let now = Instant::now();
+        self.pairing_codes
+            .retain(|_, (_, expires_at)| *expires_at > now);
+
+        let mut code = generate_pairing_code();
+        while self.pairing_codes.contains_key(&code) {
+            code = generate_pairing_code();
+        }
+
+        info!("Creating a pairing code for User ID {}", user_id);
+
+        self.pairing_codes
+            .insert(code.to_owned(), (user_id, now + PAIRING_CODE_LIFETIME));
+        self.send_event(
+            ws_id,
+            ServerEvent::PairingCode(PairingCode {
+                code,
+                expires_in: PAIRING_CODE_LIFETIME.as_secs(),
+            }),
+        );
+        Ok(())
+    }
+
+    /// Adds the session as a new device of the account the pairing code was created for
+    pub fn pair_device(&mut self, ws_id: usize, pair_data: PairDevice) -> RequestResult {
+        let user_id = match self.pairing_codes.remove(&pair_data.code) {
+            Some((user_id, expires_at)) if expires_at > Instant::now() => user_id,
+            _ => {
+                return Self::finished(Err(ErrorData::new(
+                    ErrorCode::InvalidData,
+                    "Invalid or expired pairing code",
+                )))
             }
+        };
+
+        if let Err(e) = self.check_cert_user(ws_id, Some(user_id)) {
+            return Self::finished(Err(e));
         }
+
+        let user_token = generate_user_token();
+        let device_data = NewDevice::new(user_id, &pair_data.device_name, &user_token);
+
+        self.run_query(
+            move |storage| {
+                let user_data = get_enabled_user(storage, user_id)?;
+                let device = storage
+                    .create_new_device(device_data)
+                    .map_err(|e| db_error(e, "Failed to create the device"))?;
+                let undelivered = take_undelivered_messages(storage, user_id)?;
+                Ok((device, user_data, undelivered))
+            },
+            move |act, (device, user_data, undelivered)| {
+                info!(
+                    "Paired WS session {} as device {} of User ID {}",
+                    ws_id, device.device_id, user_id
+                );
+
+                // A session that was already using a different account switches to the paired one
+                act.unbind_session(ws_id);
+                act.bind_session(
+                    ws_id,
+                    user_id,
+                    device.device_id as u64,
+                    user_data.hide_last_seen,
+                );
+                act.send_event(
+                    ws_id,
+                    ServerEvent::DevicePaired(UserIDs::new(user_id, user_token)),
+                );
+                act.send_event(
+                    ws_id,
+                    ServerEvent::Authenticated(user_data.into_user_data()),
+                );
+                act.send_undelivered_messages(ws_id, undelivered);
+                Ok(())
+            },
+        )
     }
 
-    /// Updates image link of a user
-    pub fn image_link_update(&mut self, update_data: ImageUpdate) {
-        let user_id;
+    /// Send a message to every device of the receiver and the other devices of the sender
+    pub fn send_message(
+        &mut self,
+        ws_id: usize,
+        from_user_id: u64,
+        message_data: MessageData,
+    ) -> RequestResult {
+        Box::pin(
+            self.deliver_message(Some(ws_id), from_user_id, message_data)
+                .map(|result, _, _| result.map(|_| ())),
+        )
+    }
 
-        if let Some(user_data) =
-            get_user_with_token(&mut self.conn, update_data.user_token.to_owned())
-        {
-            user_id = user_data.user_id as usize;
-        } else {
-            error!("Invalid user token received. Discarding request");
-            return;
+    /// Saves the message and sends it to every session of both users except the given one.
+    /// The next free message number is used if the message has none. If the receiver has no
+    /// session, the message is kept as undelivered and sent once the receiver connects.
+    /// Returns the saved message
+    pub fn deliver_message(
+        &mut self,
+        except: Option<usize>,
+        from_user_id: u64,
+        mut message_data: MessageData,
+    ) -> PendingResult<MessageData> {
+        if let Err(e) = check_content(self.content_limits.validate_message(&message_data.message)) {
+            return Self::finished(Err(e));
         }
 
-        let image_update_data = update_data.to_json();
-        let new_link = update_data.image_link;
+        let created_at =
+            match DateTime::parse_from_str(&message_data.created_at, "%Y-%m-%d %H:%M:%S%.3f %z") {
+                Ok(time) => time.naive_utc(),
+                Err(_) => {
+                    return Self::finished(Err(ErrorData::new(
+                        ErrorCode::InvalidData,
+                        "Invalid message time",
+                    )))
+                }
+            };
 
-        info!("Updating image link of user {} to {new_link:?}", user_id);
-        update_user_image_link(&mut self.conn, user_id, new_link);
-
-        // broadcast the image update update to every active session that has added this user id
-        for (id, session_data) in self.user_session.iter() {
-            if id != &user_id {
-                for session in session_data {
-                    if session.user_id == user_id {
-                        if let Some(data) = self.sessions.get(&session.ws_id) {
-                            let receiver = &data.1;
-                            receiver
-                                .do_send(Message(format!("/image-updated {image_update_data}")));
-                        }
+        message_data.created_at = created_at.to_string();
+        message_data.from_user = from_user_id;
+        // Only edit_message can mark a message as edited
+        message_data.edited_at = None;
+        // Reactions can only be added to a saved message
+        message_data.reactions.clear();
+
+        let to_user_id = message_data.to_user;
+        let message_group = create_message_group(from_user_id, to_user_id);
+
+        let mut new_message_data = NewMessage::new(
+            message_group.to_owned(),
+            message_data.message_number,
+            message_data.message.to_owned(),
+            from_user_id,
+            to_user_id,
+            created_at,
+        );
+        new_message_data.delivered = self.user_session.contains_key(&to_user_id);
+        new_message_data.reply_to = message_data.reply_to.map(|number| number as i32);
+
+        self.run_query(
+            move |storage| {
+                if storage.get_user_with_id(to_user_id).is_none() {
+                    return Err(ErrorData::new(
+                        ErrorCode::NotFound,
+                        "The receiver user does not exist",
+                    ));
+                }
+
+                // 0 lets the storage pick the next number of the chat
+                let pick_number = new_message_data.message_number == 0;
+
+                // A reply can only quote an earlier message of the same chat. A saved message
+                // is always earlier than the one that gets the next number
+                if let Some(reply_to) = new_message_data.reply_to {
+                    let target = storage
+                        .get_message_with_number(message_group, reply_to as u64)
+                        .map_err(|e| db_error(e, "Failed to get the replied message"))?;
+
+                    if target.is_none()
+                        || (!pick_number && reply_to >= new_message_data.message_number)
+                    {
+                        return Err(ErrorData::new(
+                            ErrorCode::NotFound,
+                            "The replied message does not exist",
+                        ));
                     }
                 }
-            }
+
+                let saved_message = if pick_number {
+                    storage.create_next_message(new_message_data)
+                } else {
+                    storage.create_new_message(new_message_data)
+                };
+                saved_message.map_err(|e| db_error(e, "Failed to save the message"))
+            },
+            move |act, saved_message| {
+                info!("Sending message from {} to {}", from_user_id, to_user_id);
+                MESSAGES_SENT.inc();
+
+                message_data.message_number = saved_message.message_number as u64;
+
+                // The receiver connected or disconnected while the message was being saved
+                let receiver_online = act.user_session.contains_key(&to_user_id);
+                if receiver_online != saved_message.delivered {
+                    act.set_message_delivered(saved_message.message_id as u64, receiver_online);
+                }
+                message_data.status = if receiver_online {
+                    MessageStatus::Delivered
+                } else {
+                    MessageStatus::Sent
+                };
+
+                // The session that sent the message only gets the receipt
+                if let Some(ws_id) = except {
+                    act.send_event(
+                        ws_id,
+                        ServerEvent::MessageReceipt(MessageReceipt {
+                            user_id: to_user_id,
+                            message_number: message_data.message_number,
+                            status: message_data.status,
+                        }),
+                    );
+                }
+
+                act.send_to_user(
+                    from_user_id,
+                    except,
+                    ServerEvent::Message(message_data.clone()),
+                );
+
+                if from_user_id == to_user_id {
+                    info!("From and to users are the same. Stopping sending.");
+                    return Ok(message_data);
+                }
+
+                act.send_to_user(to_user_id, None, ServerEvent::Message(message_data.clone()));
+                Ok(message_data)
+            },
+        )
+    }
+
+    /// Creates, saves and broadcasts the new user to the relevant session
+    pub fn create_new_user(&mut self, ws_id: usize, user_data: FullUserData) -> RequestResult {
+        if self.session_owner(ws_id) != 0 {
+            return Self::finished(Err(ErrorData::new(
+                ErrorCode::InvalidRequest,
+                "The session is already authenticated",
+            )));
+        }
+
+        // A certificate that belongs to a user can not be used for creating another one
+        if let Err(e) = self.check_cert_user(ws_id, None) {
+            return Self::finished(Err(e));
+        }
+
+        if let Err(e) = self.check_user_content(&user_data.user_name, &user_data.image_link) {
+            return Self::finished(Err(e));
         }
+
+        let user_token = generate_user_token();
+        let user_data = User::from_user_data(user_data);
+
+        self.run_query(
+            move |storage| {
+                let mut rng = rand::thread_rng();
+                let mut user_id = rng.gen_range(1..=2_147_483_647) as u64;
+
+                while storage.get_user_with_id(user_id).is_some() {
+                    info!("Generated user ID already exist. Creating a new ID");
+                    user_id = rng.gen_range(1..=2_147_483_647) as u64;
+                }
+
+                info!("Creating new user with User ID {user_id}");
+
+                let user_data = user_data.update_id(user_id);
+                let device_data = NewDevice::new(user_id, DEFAULT_DEVICE_NAME, &user_token);
+
+                storage
+                    .create_new_user(user_data, device_data)
+                    .map(|device| (user_id, user_token, device))
+                    .map_err(|e| db_error(e, "Failed to create the user"))
+            },
+            move |act, (user_id, user_token, device)| {
+                act.bind_session(ws_id, user_id, device.device_id as u64, false);
+                act.send_event(
+                    ws_id,
+                    ServerEvent::UpdateUserId(UserIDs::new(user_id, user_token)),
+                );
+                Ok(())
+            },
+        )
     }
 
-    pub fn send_message_number(&mut self, ws_id: usize, id_data: IDInfo) {
-        let owner_id;
+    /// Reconnect with an existing user and add it as a contact of the session
+    pub fn reconnect_user(&mut self, ws_id: usize, owner_id: u64, user_id: u64) -> RequestResult {
+        info!(
+            "Reconnecting with User ID {} with owner ID {}",
+            user_id, owner_id
+        );
 
-        if let Some(user_data) = get_user_with_token(&mut self.conn, id_data.user_token) {
-            owner_id = user_data.user_id as usize;
-        } else {
-            error!("Invalid user token received. Discarding request");
-            return;
+        self.run_query(
+            move |storage| {
+                storage.get_user_with_id(user_id).ok_or_else(|| {
+                    ErrorData::new(
+                        ErrorCode::NotFound,
+                        "Unable to reconnect with a non-existing user",
+                    )
+                })
+            },
+            move |act, user_data| {
+                let status = act.user_presence(user_id);
+                let last_seen = user_data
+                    .last_seen
+                    .filter(|_| status == PresenceStatus::Offline && !user_data.hide_last_seen);
+
+                if let Some((id_info, receiver_ws)) = act.sessions.get_mut(&ws_id) {
+                    id_info.contacts.insert(user_id);
+                    receiver_ws.do_send(Message(ServerEvent::ReconnectSuccess(
+                        user_data.into_user_data(),
+                    )));
+
+                    // The owner always knows its own presence
+                    if user_id != owner_id {
+                        receiver_ws.do_send(Message(ServerEvent::Presence(presence_data(
+                            user_id, status, last_seen,
+                        ))));
+                    }
+                }
+                Ok(())
+            },
+        )
+    }
+
+    /// Sends a user profile data to a client
+    pub fn send_user_data(&mut self, ws_id: usize, id: u64) -> RequestResult {
+        info!("Sending User ID {} profile data", id);
+
+        self.run_query(
+            move |storage| Ok(storage.get_user_with_id(id)),
+            move |act, user_data| {
+                let user_data = user_data.unwrap_or_else(User::new).into_user_data();
+                act.send_event(ws_id, ServerEvent::GetUserData(user_data));
+                Ok(())
+            },
+        )
+    }
+
+    /// Updates user name of a user
+    pub fn user_name_update(
+        &mut self,
+        ws_id: usize,
+        user_id: u64,
+        update_data: NameUpdate,
+    ) -> RequestResult {
+        let new_name = update_data.new_name;
+
+        if let Err(e) = check_content(self.content_limits.validate_user_name(&new_name)) {
+            return Self::finished(Err(e));
         }
 
-        let message_group = create_message_group(owner_id, id_data.user_id);
+        info!("Updating name of user {} to {new_name}", user_id);
+
+        self.run_query(
+            {
+                let new_name = new_name.to_owned();
+                move |storage| {
+                    storage
+                        .update_user_name(user_id, &new_name)
+                        .map_err(|e| db_error(e, "Failed to update the name"))
+                }
+            },
+            move |act, _| {
+                // broadcast the name update to every active session that has added this user id
+                act.send_to_contacts(
+                    user_id,
+                    ws_id,
+                    ServerEvent::NameUpdated { user_id, new_name },
+                );
+                Ok(())
+            },
+        )
+    }
 
-        info!("Sending message number of group {}", message_group);
+    /// Updates image link of a user
+    pub fn image_link_update(
+        &mut self,
+        ws_id: usize,
+        user_id: u64,
+        update_data: ImageUpdate,
+    ) -> RequestResult {
+        let new_link = update_data.image_link;
 
-        let last_message_number = get_last_message_number(&mut self.conn, message_group);
+        if let Some(link) = &new_link {
+            if let Err(e) = check_content(self.content_limits.validate_image_link(link)) {
+                return Self::finished(Err(e));
+            }
+        }
 
-        if let Some((_, receiver_ws)) = self.sessions.get(&ws_id) {
-            receiver_ws.do_send(Message(format!("/message-number {last_message_number}")));
-        };
+        info!("Updating image link of user {} to {new_link:?}", user_id);
+
+        self.run_query(
+            {
+                let new_link = new_link.clone();
+                move |storage| {
+                    storage
+                        .update_user_image_link(user_id, new_link)
+                        .map_err(|e| db_error(e, "Failed to update the image link"))
+                }
+            },
+            move |act, _| {
+                // broadcast the image update to every active session that has added this user id
+                act.send_to_contacts(
+                    user_id,
+                    ws_id,
+                    ServerEvent::ImageUpdated {
+                        user_id,
+                        image_link: new_link,
+                    },
+                );
+                Ok(())
+            },
+        )
     }
 
-    pub fn sync_message(&mut self, ws_id: usize, sync_data: SyncMessage) {
-        let owner_id;
+    pub fn send_message_number(
+        &mut self,
+        ws_id: usize,
+        owner_id: u64,
+        user_id: u64,
+    ) -> RequestResult {
+        let message_group = create_message_group(owner_id, user_id);
 
-        if let Some(user_data) = get_user_with_token(&mut self.conn, sync_data.user_token) {
-            owner_id = user_data.user_id as usize;
-        } else {
-            error!("Invalid user token received. Discarding request");
-            return;
+        info!("Sending message number of group {}", message_group);
+
+        self.run_query(
+            move |storage| Ok(storage.get_last_message_number(message_group)),
+            move |act, last_message_number| {
+                act.send_event(
+                    ws_id,
+                    ServerEvent::MessageNumber {
+                        user_id,
+                        message_number: last_message_number,
+                    },
+                );
+                Ok(())
+            },
+        )
+    }
+
+    pub fn sync_message(
+        &mut self,
+        ws_id: usize,
+        owner_id: u64,
+        sync_data: MessageSyncRequest,
+    ) -> RequestResult {
+        if sync_data.start_at > sync_data.end_at {
+            return Self::finished(Err(ErrorData::new(
+                ErrorCode::InvalidData,
+                "Sync start number is bigger than the end number",
+            )));
         }
 
         let group_name = create_message_group(owner_id, sync_data.user_id);
 
         info!("Sending sync message data of group {}", group_name);
 
-        let gathered_message_data = get_messages_from_number(
-            &mut self.conn,
-            group_name,
-            sync_data.start_at,
-            sync_data.end_at,
-        );
+        self.run_query(
+            move |storage| {
+                let gathered_message_data = storage
+                    .get_messages_from_number(group_name, sync_data.start_at, sync_data.end_at)
+                    .map_err(|e| db_error(e, "Failed to get the messages"))?;
 
-        if gathered_message_data.is_empty() {
-            return;
-        }
+                if gathered_message_data.is_empty() {
+                    return Ok(Vec::new());
+                }
 
-        let message_data: Vec<MessageData> = gathered_message_data
-            .into_iter()
-            .map(|msg| MessageData {
-                created_at: msg.created_at.to_string(),
-                from_user: msg.message_sender as usize,
-                to_user: msg.message_receiver as usize,
-                message: msg.message_text.unwrap(),
-                message_number: msg.message_number as usize,
-                user_token: String::new(),
-            })
-            .collect();
+                let message_ids = gathered_message_data
+                    .iter()
+                    .map(|message| message.message_id as u64)
+                    .collect();
+                let reactions = storage
+                    .get_message_reactions(message_ids)
+                    .map_err(|e| db_error(e, "Failed to get the message reactions"))?;
+
+                Ok(with_reactions(gathered_message_data, reactions))
+            },
+            move |act, message_data| {
+                if message_data.is_empty() {
+                    return Ok(());
+                }
 
-        let to_send = SyncMessageData::new_json(message_data);
+                act.send_event(
+                    ws_id,
+                    ServerEvent::SyncMessage {
+                        user_id: sync_data.user_id,
+                        message_data,
+                    },
+                );
+                Ok(())
+            },
+        )
+    }
 
-        if let Some((_, receiver_ws)) = self.sessions.get(&ws_id) {
-            receiver_ws.do_send(Message(format!("/sync-message {}", to_send)))
+    /// Tells the active sessions of the other user of the chat that the owner started or stopped
+    /// typing. Only sessions that added the owner as a contact get it. Nothing is saved and
+    /// offline users never find out
+    pub fn relay_typing(&self, owner_id: u64, update: TypingUpdate) -> Result<(), ErrorData> {
+        if update.user_id == owner_id {
+            return Ok(());
+        }
+
+        let Some(ws_ids) = self.user_session.get(&update.user_id) else {
+            return Ok(());
         };
-    }
 
-    pub fn delete_message(&mut self, deletion_data: DeleteMessage) {
-        let owner_id;
+        let event = ServerEvent::Typing(TypingUpdate {
+            user_id: owner_id,
+            typing: update.typing,
+        });
+        for ws_id in ws_ids {
+            if let Some((id_info, receiver_ws)) = self.sessions.get(ws_id) {
+                if id_info.contacts.contains(&owner_id) {
+                    receiver_ws.do_send(Message(event.clone()));
+                }
+            }
+        }
+        Ok(())
+    }
 
-        if let Some(user_data) =
-            get_user_with_token(&mut self.conn, deletion_data.user_token.to_owned())
-        {
-            owner_id = user_data.user_id as usize;
-        } else {
-            error!("Invalid user token received. Discarding request");
-            return;
+    /// Marks the messages the owner received in the chat as read and tells the sender
+    pub fn mark_messages_read(&mut self, owner_id: u64, receipt: MessageReceipt) -> RequestResult {
+        if receipt.status != MessageStatus::Read {
+            return Self::finished(Err(ErrorData::new(
+                ErrorCode::InvalidData,
+                "Only read receipts can be sent. Delivery is tracked by the server",
+            )));
         }
 
-        let to_send = deletion_data.to_json();
+        let group_name = create_message_group(owner_id, receipt.user_id);
+        let message_number = receipt.message_number;
+
+        self.run_query(
+            move |storage| {
+                storage
+                    .update_messages_read(group_name, owner_id, message_number)
+                    .map_err(|e| db_error(e, "Failed to update the read messages"))
+            },
+            move |act, updated| {
+                // Nothing new was read so the sender already knows
+                if updated == 0 || owner_id == receipt.user_id {
+                    return Ok(());
+                }
+
+                info!(
+                    "User ID {} read {} messages from User ID {}",
+                    owner_id, updated, receipt.user_id
+                );
+
+                act.send_to_user(
+                    receipt.user_id,
+                    None,
+                    ServerEvent::MessageReceipt(MessageReceipt {
+                        user_id: owner_id,
+                        message_number,
+                        status: MessageStatus::Read,
+                    }),
+                );
+                Ok(())
+            },
+        )
+    }
+
+    pub fn delete_message(
+        &mut self,
+        ws_id: usize,
+        owner_id: u64,
+        deletion_data: DeleteMessage,
+    ) -> RequestResult {
         let group_name = create_message_group(owner_id, deletion_data.user_id);
+        let message_number = deletion_data.message_number;
 
         info!(
             "Processing a delete message request for group {}",
             group_name
         );
 
-        delete_message_with_number(&mut self.conn, group_name, deletion_data.message_number);
+        self.run_query(
+            move |storage| {
+                let deleted = storage
+                    .delete_message_with_number(group_name, message_number)
+                    .map_err(|e| db_error(e, "Failed to delete the message"))?;
+
+                if deleted == 0 {
+                    return Err(ErrorData::new(
+                        ErrorCode::NotFound,
+                        "The message does not exist",
+                    ));
+                }
+                Ok(())
+            },
+            move |act, _| {
+                // Other devices of the owner see the chat from the owner's side
+                act.send_to_user(
+                    owner_id,
+                    Some(ws_id),
+                    ServerEvent::DeleteMessage(DeleteMessage {
+                        user_id: deletion_data.user_id,
+                        message_number,
+                    }),
+                );
+
+                if owner_id == deletion_data.user_id {
+                    return Ok(());
+                }
 
-        if owner_id == deletion_data.user_id {
-            return;
+                // From the receiver's side the other user of the chat is the owner
+                act.send_to_user(
+                    deletion_data.user_id,
+                    None,
+                    ServerEvent::DeleteMessage(DeleteMessage {
+                        user_id: owner_id,
+                        message_number,
+                    }),
+                );
+                Ok(())
+            },
+        )
+    }
+
+    /// Replaces the text of a message the owner sent. The replaced text is kept in the edit history
+    pub fn edit_message(
+        &mut self,
+        ws_id: usize,
+        owner_id: u64,
+        edit_data: EditMessage,
+    ) -> RequestResult {
+        if let Err(e) = check_content(self.content_limits.validate_message(&edit_data.message)) {
+            return Self::finished(Err(e));
         }
 
-        if let Some(user_sessions) = self.user_session.get(&deletion_data.user_id) {
-            for session in user_sessions {
-                if session.user_id == owner_id {
-                    if let Some((_, receiver_ws)) = self.sessions.get(&session.ws_id) {
-                        receiver_ws.do_send(Message(format!("/delete-message {}", to_send)));
-                        break;
+        let group_name = create_message_group(owner_id, edit_data.user_id);
+        let message_number = edit_data.message_number;
+        let edited_at = Utc::now().naive_utc();
+
+        info!(
+            "Processing an edit message request for group {}",
+            group_name
+        );
+
+        self.run_query(
+            {
+                let new_text = edit_data.message.to_owned();
+                move |storage| {
+                    let message = get_saved_message(storage, group_name, message_number)?;
+
+                    if message.message_sender != owner_id as i32 {
+                        return Err(ErrorData::new(
+                            ErrorCode::InvalidRequest,
+                            "Only the sender can edit the message",
+                        ));
                     }
+
+                    // Deleted messages are filtered out by get_saved_message
+                    let previous = NewMessageEdit::from_message(&message).ok_or_else(|| {
+                        ErrorData::new(ErrorCode::ServerError, "The message has no text to edit")
+                    })?;
+                    storage
+                        .edit_message(previous, new_text, edited_at)
+                        .map_err(|e| db_error(e, "Failed to edit the message"))
                 }
+            },
+            move |act, _| {
+                // Other devices of the owner see the chat from the owner's side
+                act.send_to_user(
+                    owner_id,
+                    Some(ws_id),
+                    ServerEvent::EditMessage(EditMessage {
+                        user_id: edit_data.user_id,
+                        message_number,
+                        message: edit_data.message.to_owned(),
+                    }),
+                );
+
+                if owner_id == edit_data.user_id {
+                    return Ok(());
+                }
+
+                // From the receiver's side the other user of the chat is the owner
+                act.send_to_user(
+                    edit_data.user_id,
+                    None,
+                    ServerEvent::EditMessage(EditMessage {
+                        user_id: owner_id,
+                        message_number,
+                        message: edit_data.message,
+                    }),
+                );
+                Ok(())
+            },
+        )
+    }
+
+    /// Sends the earlier versions of an edited message of the chat
+    pub fn send_message_history(
+        &mut self,
+        ws_id: usize,
+        owner_id: u64,
+        history_data: MessageHistoryRequest,
+    ) -> RequestResult {
+        let group_name = create_message_group(owner_id, history_data.user_id);
+        let message_number = history_data.message_number;
+
+        info!(
+            "Sending edit history of message {} of group {}",
+            message_number, group_name
+        );
+
+        self.run_query(
+            move |storage| {
+                let message = get_saved_message(storage, group_name, message_number)?;
+                storage
+                    .get_message_edits(message.message_id as u64)
+                    .map_err(|e| db_error(e, "Failed to get the edit history"))
+            },
+            move |act, edits| {
+                act.send_event(
+                    ws_id,
+                    ServerEvent::MessageHistory(MessageHistory {
+                        user_id: history_data.user_id,
+                        message_number,
+                        revisions: edits.into_iter().map(|edit| edit.into_revision()).collect(),
+                    }),
+                );
+                Ok(())
+            },
+        )
+    }
+
+    /// Sets or removes the reaction of the owner on a message of the chat and sends it to both users
+    pub fn react_to_message(
+        &mut self,
+        ws_id: usize,
+        owner_id: u64,
+        reaction_data: ReactionRequest,
+    ) -> RequestResult {
+        if let Some(emoji) = &reaction_data.emoji {
+            if let Err(e) = check_content(self.content_limits.validate_reaction(emoji)) {
+                return Self::finished(Err(e));
             }
         }
+
+        let group_name = create_message_group(owner_id, reaction_data.user_id);
+        let message_number = reaction_data.message_number;
+        let created_at = Utc::now().naive_utc();
+
+        info!(
+            "Processing a reaction request for message {} of group {}",
+            message_number, group_name
+        );
+
+        self.run_query(
+            {
+                let emoji = reaction_data.emoji.to_owned();
+                move |storage| {
+                    let message = get_saved_message(storage, group_name, message_number)?;
+
+                    match emoji {
+                        Some(emoji) => storage.set_message_reaction(MessageReaction::new(
+                            message.message_id,
+                            owner_id,
+                            emoji,
+                            created_at,
+                        )),
+                        None => {
+                            storage.remove_message_reaction(message.message_id as u64, owner_id)
+                        }
+                    }
+                    .map_err(|e| db_error(e, "Failed to save the reaction"))
+                }
+            },
+            move |act, _| {
+                // Other devices of the owner see the chat from the owner's side
+                act.send_to_user(
+                    owner_id,
+                    Some(ws_id),
+                    ServerEvent::Reaction(ReactionUpdate {
+                        user_id: reaction_data.user_id,
+                        message_number,
+                        reacted_by: owner_id,
+                        emoji: reaction_data.emoji.to_owned(),
+                    }),
+                );
+
+                if owner_id == reaction_data.user_id {
+                    return Ok(());
+                }
+
+                // From the receiver's side the other user of the chat is the owner
+                act.send_to_user(
+                    reaction_data.user_id,
+                    None,
+                    ServerEvent::Reaction(ReactionUpdate {
+                        user_id: owner_id,
+                        message_number,
+                        reacted_by: owner_id,
+                        emoji: reaction_data.emoji,
+                    }),
+                );
+                Ok(())
+            },
+        )
+    }
+}
+
+/// Get the device of the user the token belongs to
+pub fn verify_device_token(
+    storage: &mut dyn Storage,
+    user_id: u64,
+    token: &str,
+) -> Result<Device, ErrorData> {
+    let devices = storage
+        .get_user_devices(user_id)
+        .map_err(|e| db_error(e, "Failed to get the devices"))?;
+
+    devices
+        .into_iter()
+        .find(|device| device.verify_token(token))
+        .ok_or_else(|| ErrorData::new(ErrorCode::InvalidToken, "Invalid user token"))
+}
+
+/// Get the messages the user received while offline and mark them as delivered.
+/// Deleted messages are marked but not returned
+fn take_undelivered_messages(
+    storage: &mut dyn Storage,
+    user_id: u64,
+) -> Result<Vec<MessageData>, ErrorData> {
+    let undelivered = storage
+        .get_undelivered_messages(user_id)
+        .map_err(|e| db_error(e, "Failed to get the undelivered messages"))?;
+
+    if undelivered.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let message_ids = undelivered
+        .iter()
+        .map(|message| message.message_id as u64)
+        .collect();
+    storage
+        .update_messages_delivered(message_ids, true)
+        .map_err(|e| db_error(e, "Failed to update the message delivery"))?;
+
+    Ok(undelivered
+        .into_iter()
+        .filter(|message| message.message_text.is_some())
+        .map(|message| message.into_message_data())
+        .collect())
+}
+
+/// Converts the messages to the data that is sent to the clients along with their reactions
+pub fn with_reactions(
+    messages: Vec<crate::db::Message>,
+    reactions: Vec<MessageReaction>,
+) -> Vec<MessageData> {
+    let mut reactions_of: HashMap<i32, Vec<Reaction>> = HashMap::new();
+    for reaction in reactions {
+        reactions_of
+            .entry(reaction.message_id)
+            .or_default()
+            .push(reaction.into_reaction());
+    }
+
+    messages
+        .into_iter()
+        .map(|message| {
+            let reactions = reactions_of.remove(&message.message_id).unwrap_or_default();
+            let mut message_data = message.into_message_data();
+            message_data.reactions = reactions;
+            message_data
+        })
+        .collect()
+}
+
+/// Creates the presence of a user that is sent to the clients
+fn presence_data(
+    user_id: u64,
+    status: PresenceStatus,
+    last_seen: Option<NaiveDateTime>,
+) -> Presence {
+    Presence {
+        user_id,
+        status,
+        last_seen: last_seen
+            .map(|time| DateTime::<Utc>::from_naive_utc_and_offset(time, Utc).to_rfc3339()),
+    }
+}
+
+/// Get a message of the group that was not deleted
+fn get_saved_message(
+    storage: &mut dyn Storage,
+    group: String,
+    number: u64,
+) -> Result<crate::db::Message, ErrorData> {
+    storage
+        .get_message_with_number(group, number)
+        .map_err(|e| db_error(e, "Failed to get the message"))?
+        .filter(|message| message.message_text.is_some())
+        .ok_or_else(|| ErrorData::new(ErrorCode::NotFound, "The message does not exist"))
+}
+
+/// Converts a failed content check to an error that can be sent to the client
+fn check_content(result: Result<(), String>) -> Result<(), ErrorData> {
+    result.map_err(|e| ErrorData::new(ErrorCode::InvalidData, &e))
+}
+
+/// Get the user if it exists and was not disabled
+pub fn get_enabled_user(storage: &mut dyn Storage, user_id: u64) -> Result<User, ErrorData> {
+    let user_data = storage
+        .get_user_with_id(user_id)
+        .ok_or_else(|| ErrorData::new(ErrorCode::NotFound, "The user does not exist"))?;
+
+    if user_data.disabled {
+        return Err(ErrorData::new(
+            ErrorCode::AccountDisabled,
+            "The account was disabled",
+        ));
     }
+    Ok(user_data)
 }
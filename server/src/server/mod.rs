@@ -1,7 +1,14 @@
 mod handler;
-mod json_models;
+mod models;
+mod rate_limit;
+#[cfg(test)]
+mod tests;
 mod websocket;
 
-pub use handler::ChatServer;
-pub use json_models::*;
-pub use websocket::{Connect, Disconnect, HandleRequest, Message};
+pub use handler::{get_enabled_user, verify_device_token, with_reactions, ChatServer};
+pub use models::*;
+pub use rate_limit::{RateLimiter, RequestKind};
+pub use websocket::{
+    CloseSession, CloseUserSessions, Connect, Disconnect, HandleRequest, Message, MessagesRemoved,
+    PostMessage,
+};
@@ -0,0 +1,39 @@
+use std::collections::HashSet;
+use std::net::IpAddr;
+
+#[derive(Clone)]
+pub struct IDInfo {
+    // The user the session is authenticated as. 0 until authenticated
+    pub owner_id: u64,
+    // The device the session is authenticated with. 0 until authenticated
+    pub device_id: u64,
+    // Every user the client has added for chatting, including the owner
+    pub contacts: HashSet<u64>,
+    // The user the TLS client certificate of the connection belongs to
+    pub cert_user: Option<u64>,
+    // Address the client connected from. Used for the rate limits
+    pub ip: Option<IpAddr>,
+    // The client marked the session as idle
+    pub away: bool,
+    // Whether the owner hides the last seen time from contacts. Set when the session is bound
+    pub hide_last_seen: bool,
+}
+
+impl IDInfo {
+    pub fn new(cert_user: Option<u64>, ip: Option<IpAddr>) -> Self {
+        IDInfo {
+            owner_id: 0,
+            device_id: 0,
+            contacts: HashSet::new(),
+            cert_user,
+            ip,
+            away: false,
+            hide_last_seen: false,
+        }
+    }
+
+    /// Removes the user binding. The client certificate and IP stay the same as the connection does
+    pub fn reset(&mut self) {
+        *self = IDInfo::new(self.cert_user, self.ip);
+    }
+}
@@ -0,0 +1,405 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use crate::config::{Limit, RateLimitConfig, RequestLimits};
+
+/// Types of requests that are limited separately
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RequestKind {
+    Messaging,
+    Profile,
+    AccountCreation,
+    Authentication,
+    Typing,
+}
+
+impl RateLimitConfig {
+    fn limits(&self, kind: RequestKind) -> &RequestLimits {
+        match kind {
+            RequestKind::Messaging => &self.messaging,
+            RequestKind::Profile => &self.profile,
+            RequestKind::AccountCreation => &self.account_creation,
+            RequestKind::Authentication => &self.authentication,
+            RequestKind::Typing => &self.typing,
+        }
+    }
+}
+
+struct TokenBucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl TokenBucket {
+    fn full(limit: &Limit) -> Self {
+        TokenBucket {
+            tokens: limit.burst as f64,
+            updated_at: Instant::now(),
+        }
+    }
+
+    /// Adds the tokens gained since the last update
+    fn refill(&mut self, limit: &Limit, now: Instant) {
+        let elapsed = now.duration_since(self.updated_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.per_second).min(limit.burst as f64);
+        self.updated_at = now;
+    }
+
+    /// Time until the bucket has a token. Zero if it has one
+    fn wait_time(&self, limit: &Limit) -> Duration {
+        if self.tokens >= 1.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64((1.0 - self.tokens) / limit.per_second)
+        }
+    }
+
+    fn is_full(&self, limit: &Limit) -> bool {
+        self.tokens >= limit.burst as f64
+    }
+}
+
+/// Buckets of every key of a single scope
+struct Buckets<K>(HashMap<(K, RequestKind), TokenBucket>);
+
+impl<K: Eq + Hash + Copy> Buckets<K> {
+    fn refill(&mut self, key: K, kind: RequestKind, limit: &Limit, now: Instant) -> Duration {
+        let bucket = self
+            .0
+            .entry((key, kind))
+            .or_insert_with(|| TokenBucket::full(limit));
+        bucket.refill(limit, now);
+        bucket.wait_time(limit)
+    }
+
+    fn take(&mut self, key: K, kind: RequestKind) {
+        if let Some(bucket) = self.0.get_mut(&(key, kind)) {
+            bucket.tokens -= 1.0;
+        }
+    }
+
+    /// Removes the buckets that are full as they are the same as a new one
+    fn prune(&mut self, config: &RateLimitConfig, limit_of: fn(&RequestLimits) -> &Limit) {
+        let now = Instant::now();
+        self.0.retain(|(_, kind), bucket| {
+            let limit = limit_of(config.limits(*kind));
+            bucket.refill(limit, now);
+            !bucket.is_full(limit)
+        });
+    }
+}
+
+impl<K> Default for Buckets<K> {
+    fn default() -> Self {
+        Buckets(HashMap::new())
+    }
+}
+
+/// Token bucket limits for each WS session, account and source IP
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    sessions: Buckets<usize>,
+    accounts: Buckets<u64>,
+    ips: Buckets<IpAddr>,
+    // {WS session ID: Limited requests in a row}
+    violations: HashMap<usize, u32>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        RateLimiter {
+            config,
+            sessions: Buckets::default(),
+            accounts: Buckets::default(),
+            ips: Buckets::default(),
+            violations: HashMap::new(),
+        }
+    }
+
+    /// Takes a token from every bucket the request belongs to. Nothing is taken if any of them is
+    /// empty and the seconds until the request can be retried are returned instead.
+    /// The scopes that are None are not checked
+    pub fn check(
+        &mut self,
+        kind: RequestKind,
+        ws_id: Option<usize>,
+        user_id: Option<u64>,
+        ip: Option<IpAddr>,
+    ) -> Result<(), u64> {
+        if !self.config.enabled {
+            return Ok(());
+        }
+
+        let limits = *self.config.limits(kind);
+        let now = Instant::now();
+        let mut wait = Duration::ZERO;
+
+        if let Some(ws_id) = ws_id {
+            wait = wait.max(self.sessions.refill(ws_id, kind, &limits.session, now));
+        }
+        if let Some(user_id) = user_id {
+            wait = wait.max(self.accounts.refill(user_id, kind, &limits.account, now));
+        }
+        if let Some(ip) = ip {
+            wait = wait.max(self.ips.refill(ip, kind, &limits.ip, now));
+        }
+
+        if !wait.is_zero() {
+            return Err(wait.as_secs_f64().ceil() as u64);
+        }
+
+        if let Some(ws_id) = ws_id {
+            self.sessions.take(ws_id, kind);
+        }
+        if let Some(user_id) = user_id {
+            self.accounts.take(user_id, kind);
+        }
+        if let Some(ip) = ip {
+            self.ips.take(ip, kind);
+        }
+        Ok(())
+    }
+
+    /// Counts a limited request of the session. True if the session should be disconnected
+    pub fn add_violation(&mut self, ws_id: usize) -> bool {
+        let violations = self.violations.entry(ws_id).or_default();
+        *violations += 1;
+        self.config.max_violations != 0 && *violations >= self.config.max_violations
+    }
+
+    /// Called when a request of the session was not limited
+    pub fn clear_violations(&mut self, ws_id: usize) {
+        self.violations.remove(&ws_id);
+    }
+
+    /// Removes everything about a disconnected session
+    pub fn remove_session(&mut self, ws_id: usize) {
+        self.sessions.0.retain(|(id, _), _| *id != ws_id);
+        self.violations.remove(&ws_id);
+    }
+
+    /// Removes the full buckets of accounts and IPs so they do not keep growing
+    pub fn prune(&mut self) {
+        self.accounts.prune(&self.config, |limits| &limits.account);
+        self.ips.prune(&self.config, |limits| &limits.ip);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    const IP: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    /// Messaging allows 2 requests per session and gets a token back every 2 seconds.
+    /// The account and IP limits are high enough to never be hit
+    fn limiter(max_violations: u32) -> RateLimiter {
+        let mut config = RateLimitConfig {
+            max_violations,
+            ..RateLimitConfig::default()
+        };
+        config.messaging = RequestLimits {
+            session: Limit {
+                burst: 2,
+                per_second: 0.5,
+            },
+            account: Limit {
+                burst: 100,
+                per_second: 100.0,
+            },
+            ip: Limit {
+                burst: 100,
+                per_second: 100.0,
+            },
+        };
+        RateLimiter::new(config)
+    }
+
+    /// Moves the last update of every bucket back as if the time had passed
+    fn pass_time<K>(buckets: &mut Buckets<K>, time: Duration) {
+        for bucket in buckets.0.values_mut() {
+            bucket.updated_at -= time;
+        }
+    }
+
+    #[test]
+    fn bucket_refills_up_to_burst() {
+        let limit = Limit {
+            burst: 4,
+            per_second: 2.0,
+        };
+        let start = Instant::now();
+        let mut bucket = TokenBucket {
+            tokens: 0.0,
+            updated_at: start,
+        };
+
+        bucket.refill(&limit, start + Duration::from_secs(1));
+        assert_eq!(bucket.tokens, 2.0);
+        assert!(!bucket.is_full(&limit));
+
+        bucket.refill(&limit, start + Duration::from_secs(60));
+        assert_eq!(bucket.tokens, 4.0);
+        assert!(bucket.is_full(&limit));
+    }
+
+    #[test]
+    fn wait_time_is_until_the_next_token() {
+        let limit = Limit {
+            burst: 1,
+            per_second: 0.25,
+        };
+        let mut bucket = TokenBucket {
+            tokens: 0.5,
+            updated_at: Instant::now(),
+        };
+        assert_eq!(bucket.wait_time(&limit), Duration::from_secs(2));
+
+        bucket.tokens = 1.0;
+        assert_eq!(bucket.wait_time(&limit), Duration::ZERO);
+    }
+
+    #[test]
+    fn requests_are_limited_after_the_burst() {
+        let mut limiter = limiter(0);
+        assert_eq!(
+            limiter.check(RequestKind::Messaging, Some(1), Some(1), Some(IP)),
+            Ok(())
+        );
+        assert_eq!(
+            limiter.check(RequestKind::Messaging, Some(1), Some(1), Some(IP)),
+            Ok(())
+        );
+        // A token comes back every 2 seconds
+        assert_eq!(
+            limiter.check(RequestKind::Messaging, Some(1), Some(1), Some(IP)),
+            Err(2)
+        );
+
+        // Other sessions and kinds have their own buckets
+        assert_eq!(
+            limiter.check(RequestKind::Messaging, Some(2), Some(1), Some(IP)),
+            Ok(())
+        );
+        assert_eq!(
+            limiter.check(RequestKind::Profile, Some(1), Some(1), Some(IP)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn limited_requests_take_no_tokens() {
+        let mut limiter = limiter(0);
+        limiter.config.messaging.account.burst = 1;
+
+        assert!(limiter
+            .check(RequestKind::Messaging, Some(1), Some(1), None)
+            .is_ok());
+        assert!(limiter
+            .check(RequestKind::Messaging, Some(1), Some(1), None)
+            .is_err());
+        // The session bucket was not used by the limited request
+        assert!(limiter
+            .check(RequestKind::Messaging, Some(1), None, None)
+            .is_ok());
+    }
+
+    #[test]
+    fn requests_are_allowed_again_after_refill() {
+        let mut limiter = limiter(0);
+        for _ in 0..2 {
+            assert!(limiter
+                .check(RequestKind::Messaging, Some(1), None, None)
+                .is_ok());
+        }
+        assert!(limiter
+            .check(RequestKind::Messaging, Some(1), None, None)
+            .is_err());
+
+        pass_time(&mut limiter.sessions, Duration::from_secs(2));
+        assert!(limiter
+            .check(RequestKind::Messaging, Some(1), None, None)
+            .is_ok());
+        assert!(limiter
+            .check(RequestKind::Messaging, Some(1), None, None)
+            .is_err());
+    }
+
+    #[test]
+    fn disabled_limiter_allows_everything() {
+        let mut limiter = limiter(0);
+        limiter.config.enabled = false;
+        for _ in 0..10 {
+            assert!(limiter
+                .check(RequestKind::Messaging, Some(1), Some(1), Some(IP))
+                .is_ok());
+        }
+    }
+
+    #[test]
+    fn violations_in_a_row_disconnect() {
+        let mut limiter = limiter(3);
+        assert!(!limiter.add_violation(1));
+        assert!(!limiter.add_violation(1));
+        // Other sessions are counted separately
+        assert!(!limiter.add_violation(2));
+        assert!(limiter.add_violation(1));
+
+        limiter.clear_violations(2);
+        assert!(!limiter.add_violation(2));
+        assert!(!limiter.add_violation(2));
+        assert!(limiter.add_violation(2));
+    }
+
+    #[test]
+    fn zero_max_violations_never_disconnects() {
+        let mut limiter = limiter(0);
+        for _ in 0..100 {
+            assert!(!limiter.add_violation(1));
+        }
+    }
+
+    #[test]
+    fn prune_removes_only_full_buckets() {
+        let mut limiter = limiter(0);
+        assert!(limiter
+            .check(RequestKind::Messaging, Some(1), Some(1), Some(IP))
+            .is_ok());
+        assert!(limiter
+            .check(RequestKind::Messaging, Some(1), Some(2), None)
+            .is_ok());
+
+        pass_time(&mut limiter.accounts, Duration::from_secs(1));
+        pass_time(&mut limiter.ips, Duration::from_secs(1));
+        let bucket = limiter
+            .accounts
+            .0
+            .get_mut(&(2, RequestKind::Messaging))
+            .unwrap();
+        bucket.tokens = 0.0;
+        bucket.updated_at = Instant::now();
+        limiter.prune();
+
+        // Account 1 and the IP refilled while the emptied bucket of account 2 did not
+        assert!(!limiter
+            .accounts
+            .0
+            .contains_key(&(1, RequestKind::Messaging)));
+        assert!(limiter
+            .accounts
+            .0
+            .contains_key(&(2, RequestKind::Messaging)));
+        assert!(limiter.ips.0.is_empty());
+        // Session buckets are only removed when the session disconnects
+        assert!(limiter
+            .sessions
+            .0
+            .contains_key(&(1, RequestKind::Messaging)));
+
+        limiter.remove_session(1);
+        assert!(limiter.sessions.0.is_empty());
+    }
+}
@@ -0,0 +1,357 @@
+use actix::prelude::*;
+use chirp_protocol::{
+    ClientRequest, ContentLimits, ErrorCode, FullUserData, MessageData, ServerEvent, UserIDs,
+};
+use std::net::{IpAddr, Ipv4Addr};
+
+use crate::config::RateLimitConfig;
+use crate::db::{DbExecutor, MemoryStorage, StorageBackend};
+use crate::server::{
+    ChatServer, CloseSession, Connect, Disconnect, HandleRequest, Message as SessionEvent,
+};
+
+const CREATED_AT: &str = "2023-12-01 10:00:00.000 +0000";
+
+/// Stands in for a WS session and keeps every event the ChatServer sent to it
+#[derive(Default)]
+struct TestClient {
+    events: Vec<ServerEvent>,
+}
+
+impl Actor for TestClient {
+    type Context = Context<Self>;
+}
+
+impl Handler<SessionEvent> for TestClient {
+    type Result = ();
+
+    fn handle(&mut self, msg: SessionEvent, _: &mut Context<Self>) {
+        self.events.push(msg.0);
+    }
+}
+
+impl Handler<CloseSession> for TestClient {
+    type Result = ();
+
+    fn handle(&mut self, _: CloseSession, _: &mut Context<Self>) {}
+}
+
+/// Takes the events received so far. Events sent before it are always handled first
+#[derive(Message)]
+#[rtype(result = "Vec<ServerEvent>")]
+struct TakeEvents;
+
+impl Handler<TakeEvents> for TestClient {
+    type Result = MessageResult<TakeEvents>;
+
+    fn handle(&mut self, _: TakeEvents, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(std::mem::take(&mut self.events))
+    }
+}
+
+struct TestSession {
+    ws_id: usize,
+    client: Addr<TestClient>,
+}
+
+impl TestSession {
+    async fn connect(server: &Addr<ChatServer>) -> Self {
+        Self::connect_from(server, None).await
+    }
+
+    async fn connect_from(server: &Addr<ChatServer>, ip: Option<IpAddr>) -> Self {
+        let client = TestClient::default().start();
+        let ws_id = server
+            .send(Connect {
+                addr: client.clone().recipient(),
+                closer: client.clone().recipient(),
+                cert_user: None,
+                ip,
+            })
+            .await
+            .unwrap();
+        TestSession { ws_id, client }
+    }
+
+    /// Sends the request and returns the events of this session once it is processed
+    async fn request(&self, server: &Addr<ChatServer>, request: ClientRequest) -> Vec<ServerEvent> {
+        server
+            .send(HandleRequest {
+                ws_id: self.ws_id,
+                request,
+            })
+            .await
+            .unwrap();
+        self.events().await
+    }
+
+    async fn events(&self) -> Vec<ServerEvent> {
+        self.client.send(TakeEvents).await.unwrap()
+    }
+
+    /// Creates a new user with this session and returns its IDs
+    async fn create_user(&self, server: &Addr<ChatServer>, name: &str) -> UserIDs {
+        let user_data = FullUserData::new(0, name.to_string(), None);
+        let events = self
+            .request(server, ClientRequest::CreateNewUser(user_data))
+            .await;
+
+        events
+            .into_iter()
+            .find_map(|event| match event {
+                ServerEvent::UpdateUserId(ids) => Some(ids),
+                _ => None,
+            })
+            .expect("No user was created")
+    }
+}
+
+fn start_server() -> Addr<ChatServer> {
+    let backend = StorageBackend::Memory(MemoryStorage::new());
+    let db = SyncArbiter::start(1, move || DbExecutor(backend.clone()));
+    ChatServer::new(db, RateLimitConfig::default(), ContentLimits::default()).start()
+}
+
+fn text_message(to_user: u64, message_number: u64, text: &str) -> ClientRequest {
+    let mut message_data =
+        MessageData::new_incomplete(CREATED_AT.to_string(), 0, to_user, text.to_string());
+    message_data.message_number = message_number;
+    ClientRequest::Message(message_data)
+}
+
+fn received_messages(events: &[ServerEvent]) -> Vec<&MessageData> {
+    events
+        .iter()
+        .filter_map(|event| match event {
+            ServerEvent::Message(message_data) => Some(message_data),
+            _ => None,
+        })
+        .collect()
+}
+
+fn error_code(events: &[ServerEvent]) -> Option<ErrorCode> {
+    events.iter().find_map(|event| match event {
+        ServerEvent::Error(error_data) => Some(error_data.code),
+        _ => None,
+    })
+}
+
+#[actix_rt::test]
+async fn requests_need_authentication() {
+    let server = start_server();
+    let session = TestSession::connect(&server).await;
+
+    let events = session.request(&server, text_message(1, 1, "Hello")).await;
+    assert_eq!(error_code(&events), Some(ErrorCode::NotAuthenticated));
+}
+
+#[actix_rt::test]
+async fn authenticate_with_user_token() {
+    let server = start_server();
+    let ids = TestSession::connect(&server)
+        .await
+        .create_user(&server, "Alice")
+        .await;
+
+    let session = TestSession::connect(&server).await;
+    let events = session
+        .request(&server, ClientRequest::Authenticate(ids.clone()))
+        .await;
+    let user_data = events.into_iter().find_map(|event| match event {
+        ServerEvent::Authenticated(user_data) => Some(user_data),
+        _ => None,
+    });
+    assert_eq!(
+        user_data.map(|data| data.user_name),
+        Some(String::from("Alice"))
+    );
+
+    let events = session
+        .request(&server, ClientRequest::Authenticate(ids.clone()))
+        .await;
+    assert_eq!(error_code(&events), Some(ErrorCode::InvalidRequest));
+
+    let wrong_token = UserIDs::new(ids.user_id, String::from("wrong token"));
+    let session = TestSession::connect(&server).await;
+    let events = session
+        .request(&server, ClientRequest::Authenticate(wrong_token))
+        .await;
+    assert_eq!(error_code(&events), Some(ErrorCode::InvalidToken));
+}
+
+#[actix_rt::test]
+async fn messages_are_delivered_and_numbered_per_chat() {
+    let server = start_server();
+    let alice = TestSession::connect(&server).await;
+    let alice_ids = alice.create_user(&server, "Alice").await;
+    let bob = TestSession::connect(&server).await;
+    let bob_ids = bob.create_user(&server, "Bob").await;
+
+    for number in 1..=2 {
+        alice
+            .request(&server, text_message(bob_ids.user_id, number, "Hello"))
+            .await;
+
+        let events = bob.events().await;
+        let received = received_messages(&events);
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].message_number, number);
+        assert_eq!(received[0].from_user, alice_ids.user_id);
+    }
+
+    // A number can only be used once in a chat
+    let events = alice
+        .request(&server, text_message(bob_ids.user_id, 2, "Hello"))
+        .await;
+    assert_eq!(error_code(&events), Some(ErrorCode::ServerError));
+    assert!(received_messages(&bob.events().await).is_empty());
+
+    // Messages to self are a separate chat
+    alice
+        .request(&server, text_message(alice_ids.user_id, 1, "Note"))
+        .await;
+
+    for (user_id, last_number) in [(bob_ids.user_id, 2), (alice_ids.user_id, 1)] {
+        let events = alice
+            .request(&server, ClientRequest::MessageNumber(user_id))
+            .await;
+        let message_number = events.into_iter().find_map(|event| match event {
+            ServerEvent::MessageNumber { message_number, .. } => Some(message_number),
+            _ => None,
+        });
+        assert_eq!(message_number, Some(last_number));
+    }
+}
+
+#[actix_rt::test]
+async fn disconnected_sessions_get_no_events() {
+    let server = start_server();
+    let alice = TestSession::connect(&server).await;
+    alice.create_user(&server, "Alice").await;
+    let bob = TestSession::connect(&server).await;
+    let bob_ids = bob.create_user(&server, "Bob").await;
+    server.send(Disconnect { id: bob.ws_id }).await.unwrap();
+
+    alice
+        .request(&server, text_message(bob_ids.user_id, 1, "Are you there?"))
+        .await;
+    assert!(bob.events().await.is_empty());
+}
+
+#[actix_rt::test]
+async fn undelivered_messages_are_sent_on_reconnect() {
+    let server = start_server();
+    let alice = TestSession::connect(&server).await;
+    let alice_ids = alice.create_user(&server, "Alice").await;
+    let bob = TestSession::connect(&server).await;
+    let bob_ids = bob.create_user(&server, "Bob").await;
+    server.send(Disconnect { id: bob.ws_id }).await.unwrap();
+
+    alice
+        .request(&server, text_message(bob_ids.user_id, 1, "Are you there?"))
+        .await;
+
+    let bob = TestSession::connect(&server).await;
+    let events = bob
+        .request(&server, ClientRequest::Authenticate(bob_ids.clone()))
+        .await;
+    let received = received_messages(&events);
+    assert_eq!(received.len(), 1);
+    assert_eq!(received[0].message, "Are you there?");
+    assert_eq!(received[0].from_user, alice_ids.user_id);
+
+    // The message is only pushed once
+    let bob = TestSession::connect(&server).await;
+    let events = bob
+        .request(&server, ClientRequest::Authenticate(bob_ids))
+        .await;
+    assert!(received_messages(&events).is_empty());
+}
+
+#[actix_rt::test]
+async fn rotated_and_revoked_tokens_are_rejected() {
+    let server = start_server();
+    let alice = TestSession::connect(&server).await;
+    let old_ids = alice.create_user(&server, "Alice").await;
+
+    let events = alice.request(&server, ClientRequest::RotateToken).await;
+    let new_ids = events
+        .into_iter()
+        .find_map(|event| match event {
+            ServerEvent::TokenRotated(ids) => Some(ids),
+            _ => None,
+        })
+        .expect("The token was not rotated");
+
+    let session = TestSession::connect(&server).await;
+    let events = session
+        .request(&server, ClientRequest::Authenticate(old_ids))
+        .await;
+    assert_eq!(error_code(&events), Some(ErrorCode::InvalidToken));
+
+    let events = session
+        .request(&server, ClientRequest::Authenticate(new_ids.clone()))
+        .await;
+    assert_eq!(error_code(&events), None);
+
+    alice.request(&server, ClientRequest::RevokeToken).await;
+    let session = TestSession::connect(&server).await;
+    let events = session
+        .request(&server, ClientRequest::Authenticate(new_ids))
+        .await;
+    assert_eq!(error_code(&events), Some(ErrorCode::InvalidToken));
+}
+
+#[actix_rt::test]
+async fn server_picks_the_number_of_new_messages() {
+    let server = start_server();
+    let alice = TestSession::connect(&server).await;
+    alice.create_user(&server, "Alice").await;
+    let bob = TestSession::connect(&server).await;
+    let bob_ids = bob.create_user(&server, "Bob").await;
+
+    for number in 1..=2 {
+        let events = alice
+            .request(&server, text_message(bob_ids.user_id, 0, "Hello"))
+            .await;
+        let receipt = events.iter().find_map(|event| match event {
+            ServerEvent::MessageReceipt(receipt) => Some(receipt.message_number),
+            _ => None,
+        });
+        assert_eq!(receipt, Some(number));
+
+        let events = bob.events().await;
+        assert_eq!(received_messages(&events)[0].message_number, number);
+    }
+}
+
+#[actix_rt::test]
+async fn authentication_is_rate_limited_per_ip() {
+    let server = start_server();
+    let ip = Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)));
+    let burst = RateLimitConfig::default().authentication.ip.burst;
+
+    // Every guess comes from a new session so only the IP limit applies
+    let wrong_token = UserIDs::new(1, String::from("wrong token"));
+    for _ in 0..burst {
+        let session = TestSession::connect_from(&server, ip).await;
+        let events = session
+            .request(&server, ClientRequest::Authenticate(wrong_token.clone()))
+            .await;
+        assert_eq!(error_code(&events), Some(ErrorCode::InvalidToken));
+    }
+
+    let session = TestSession::connect_from(&server, ip).await;
+    let events = session
+        .request(&server, ClientRequest::Authenticate(wrong_token.clone()))
+        .await;
+    assert_eq!(error_code(&events), Some(ErrorCode::RateLimited));
+
+    // Other IPs are not affected
+    let other_ip = Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2)));
+    let session = TestSession::connect_from(&server, other_ip).await;
+    let events = session
+        .request(&server, ClientRequest::Authenticate(wrong_token))
+        .await;
+    assert_eq!(error_code(&events), Some(ErrorCode::InvalidToken));
+}
@@ -1,20 +1,35 @@
 use actix::prelude::*;
+use chirp_protocol::{
+    ClientRequest, DeleteMessage, ErrorCode, ErrorData, MessageData, ServerEvent,
+};
 use rand::Rng;
+use std::net::IpAddr;
+use std::time::Duration;
 use tracing::info;
 
-use crate::server::{
-    ChatServer, CommunicationType, DeleteMessage, IDInfo, ImageUpdate, MessageData, NameUpdate,
-    SendUserData, SyncMessage,
-};
+use crate::server::{ChatServer, IDInfo, RequestKind};
+use crate::utils::message_group_members;
+
+// How often the full rate limit buckets of accounts and IPs are removed
+const RATE_LIMIT_PRUNE_INTERVAL: Duration = Duration::from_secs(60);
 
 #[derive(Message)]
 #[rtype(result = "()")]
-pub struct Message(pub String);
+pub struct Message(pub ServerEvent);
+
+/// Tells a WS session to send the reason and close the connection
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct CloseSession(pub ErrorData);
 
 #[derive(Message)]
 #[rtype(usize)]
 pub struct Connect {
     pub addr: Recipient<Message>,
+    pub closer: Recipient<CloseSession>,
+    // The user the TLS client certificate of the connection belongs to
+    pub cert_user: Option<u64>,
+    pub ip: Option<IpAddr>,
 }
 
 #[derive(Message)]
@@ -23,16 +38,46 @@ pub struct Disconnect {
     pub id: usize,
 }
 
+/// Closes every WS session of the user with the reason
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct CloseUserSessions {
+    pub user_id: u64,
+    pub reason: ErrorData,
+}
+
+/// Tells both users of the message group that the messages were removed
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct MessagesRemoved {
+    pub group: String,
+    pub message_numbers: Vec<u64>,
+}
+
+/// Sends a message that did not come from a WS session. Returns the saved message
+#[derive(Message)]
+#[rtype(result = "Result<MessageData, ErrorData>")]
+pub struct PostMessage {
+    pub from_user_id: u64,
+    pub message_data: MessageData,
+    pub ip: Option<IpAddr>,
+}
+
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct HandleRequest {
     pub ws_id: usize,
-    pub data: String,
-    pub comm_type: CommunicationType,
+    pub request: ClientRequest,
 }
 
 impl Actor for ChatServer {
     type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(RATE_LIMIT_PRUNE_INTERVAL, |act, _| {
+            act.rate_limiter.prune();
+        });
+    }
 }
 
 impl Handler<Connect> for ChatServer {
@@ -44,8 +89,10 @@ impl Handler<Connect> for ChatServer {
         while self.sessions.contains_key(&id) {
             id = self.rng.gen::<u32>() as usize;
         }
-        let id_data = IDInfo::new();
+        let id_data = IDInfo::new(msg.cert_user, msg.ip);
         self.sessions.insert(id, (id_data, msg.addr));
+        self.session_closers.insert(id, msg.closer);
+        self.update_session_metrics();
         id
     }
 }
@@ -54,60 +101,182 @@ impl Handler<Disconnect> for ChatServer {
     type Result = ();
 
     fn handle(&mut self, msg: Disconnect, _: &mut Context<Self>) {
-        let id_data = &self.sessions.get(&msg.id).unwrap().0;
+        let Some((id_data, _)) = self.sessions.get(&msg.id) else {
+            return;
+        };
         info!(
-            "WS Session {} disconnected. Removing session data related to user {} belonging to owner {}",
-            msg.id, id_data.user_id, id_data.owner_id
+            "WS Session {} disconnected. Removing session data belonging to owner {}",
+            msg.id, id_data.owner_id
         );
 
-        if let Some(sessions) = self.user_session.get_mut(&id_data.owner_id) {
-            sessions.retain(|i| i.ws_id != msg.id);
-            if self.user_session[&id_data.owner_id].is_empty() {
-                self.user_session.remove(&id_data.owner_id);
+        let owner_id = id_data.owner_id;
+        self.track_presence(owner_id, |act| {
+            if let Some(sessions) = act.user_session.get_mut(&owner_id) {
+                sessions.retain(|ws_id| ws_id != &msg.id);
+                if sessions.is_empty() {
+                    act.user_session.remove(&owner_id);
+                }
             }
-        }
-        self.sessions.remove(&msg.id);
+            act.sessions.remove(&msg.id);
+        });
+        self.session_closers.remove(&msg.id);
+        self.rate_limiter.remove_session(msg.id);
+        self.update_session_metrics();
     }
 }
 
-impl Handler<HandleRequest> for ChatServer {
+impl Handler<CloseUserSessions> for ChatServer {
     type Result = ();
 
-    fn handle(&mut self, msg: HandleRequest, _: &mut Context<Self>) {
-        match msg.comm_type {
-            CommunicationType::SendMessage => {
-                let message_data = MessageData::new_from_json(&msg.data);
-                self.send_message(message_data);
-            }
-            CommunicationType::SendUserData => {
-                let user_data = SendUserData::new_from_json(&msg.data);
-                self.send_user_data(msg.ws_id, user_data)
-            }
-            CommunicationType::CreateNewUser => self.create_new_user(msg.ws_id, msg.data),
-            CommunicationType::UpdateName => {
-                let update_data = NameUpdate::new_from_json(&msg.data);
-                self.user_name_update(update_data)
-            }
-            CommunicationType::UpdateImageLink => {
-                let update_data = ImageUpdate::new_from_json(&msg.data);
-                self.image_link_update(update_data)
-            }
-            CommunicationType::ReconnectUser => {
-                let id_data = IDInfo::new_from_json(msg.data);
-                self.reconnect_user(msg.ws_id, id_data);
-            }
-            CommunicationType::SendMessageNumber => {
-                let id_data = IDInfo::new_from_json(msg.data);
-                self.send_message_number(msg.ws_id, id_data)
+    fn handle(&mut self, msg: CloseUserSessions, _: &mut Context<Self>) {
+        self.close_user_sessions(msg.user_id, None, None, msg.reason);
+    }
+}
+
+impl Handler<MessagesRemoved> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: MessagesRemoved, _: &mut Context<Self>) {
+        let Some((id_1, id_2)) = message_group_members(&msg.group) else {
+            return;
+        };
+
+        for message_number in msg.message_numbers {
+            // Each user sees the chat with the other user of the group
+            self.send_to_user(
+                id_1,
+                None,
+                ServerEvent::DeleteMessage(DeleteMessage {
+                    user_id: id_2,
+                    message_number,
+                }),
+            );
+
+            if id_1 != id_2 {
+                self.send_to_user(
+                    id_2,
+                    None,
+                    ServerEvent::DeleteMessage(DeleteMessage {
+                        user_id: id_1,
+                        message_number,
+                    }),
+                );
             }
-            CommunicationType::SyncMessage => {
-                let sync_data = SyncMessage::new_from_json(&msg.data);
-                self.sync_message(msg.ws_id, sync_data);
+        }
+    }
+}
+
+impl Handler<PostMessage> for ChatServer {
+    type Result = ResponseActFuture<Self, Result<MessageData, ErrorData>>;
+
+    fn handle(&mut self, msg: PostMessage, _: &mut Context<Self>) -> Self::Result {
+        if let Err(retry_after) =
+            self.rate_limiter
+                .check(RequestKind::Messaging, None, Some(msg.from_user_id), msg.ip)
+        {
+            return ChatServer::finished(Err(ErrorData::rate_limited(retry_after)));
+        }
+
+        self.deliver_message(None, msg.from_user_id, msg.message_data)
+    }
+}
+
+impl Handler<HandleRequest> for ChatServer {
+    type Result = ResponseActFuture<Self, ()>;
+
+    fn handle(&mut self, msg: HandleRequest, _: &mut Context<Self>) -> Self::Result {
+        let command = msg.request.command_name();
+        let ws_id = msg.ws_id;
+        let owner_id = self.session_owner(ws_id);
+
+        let limited = request_kind(&msg.request)
+            .map(|kind| self.limit_request(ws_id, kind))
+            .unwrap_or(Ok(()));
+
+        // Until the session is authenticated, only the auth requests are processed
+        let result = if let Err(e) = limited {
+            ChatServer::finished(Err(e))
+        } else if owner_id == 0 && !msg.request.is_auth_request() {
+            ChatServer::finished(Err(ErrorData::new(
+                ErrorCode::NotAuthenticated,
+                "The session must authenticate first",
+            )))
+        } else {
+            match msg.request {
+                ClientRequest::Authenticate(id_data) => self.authenticate(ws_id, id_data),
+                ClientRequest::CreateNewUser(user_data) => self.create_new_user(ws_id, user_data),
+                ClientRequest::Message(message_data) => {
+                    self.send_message(ws_id, owner_id, message_data)
+                }
+                ClientRequest::GetUserData(user_id) => self.send_user_data(ws_id, user_id),
+                ClientRequest::NameUpdated(update_data) => {
+                    self.user_name_update(ws_id, owner_id, update_data)
+                }
+                ClientRequest::ImageUpdated(update_data) => {
+                    self.image_link_update(ws_id, owner_id, update_data)
+                }
+                ClientRequest::ReconnectUser(user_id) => {
+                    self.reconnect_user(ws_id, owner_id, user_id)
+                }
+                ClientRequest::MessageNumber(user_id) => {
+                    self.send_message_number(ws_id, owner_id, user_id)
+                }
+                ClientRequest::SyncMessage(sync_data) => {
+                    self.sync_message(ws_id, owner_id, sync_data)
+                }
+                ClientRequest::DeleteMessage(deletion_data) => {
+                    self.delete_message(ws_id, owner_id, deletion_data)
+                }
+                ClientRequest::EditMessage(edit_data) => {
+                    self.edit_message(ws_id, owner_id, edit_data)
+                }
+                ClientRequest::MessageHistory(history_data) => {
+                    self.send_message_history(ws_id, owner_id, history_data)
+                }
+                ClientRequest::React(reaction_data) => {
+                    self.react_to_message(ws_id, owner_id, reaction_data)
+                }
+                ClientRequest::MessageReceipt(receipt) => {
+                    self.mark_messages_read(owner_id, receipt)
+                }
+                ClientRequest::Typing(update) => {
+                    ChatServer::finished(self.relay_typing(owner_id, update))
+                }
+                ClientRequest::SetPresence(status) => {
+                    ChatServer::finished(self.set_presence(ws_id, owner_id, status))
+                }
+                ClientRequest::HideLastSeen(hide) => self.hide_last_seen(owner_id, hide),
+                ClientRequest::RotateToken => self.rotate_token(ws_id, owner_id),
+                ClientRequest::RevokeToken => self.revoke_token(ws_id, owner_id),
+                ClientRequest::CreatePairingCode => {
+                    ChatServer::finished(self.create_pairing_code(ws_id, owner_id))
+                }
+                ClientRequest::PairDevice(pair_data) => self.pair_device(ws_id, pair_data),
             }
-            CommunicationType::DeleteMessage => {
-                let data = DeleteMessage::from_json(&msg.data);
-                self.delete_message(data);
+        };
+
+        Box::pin(result.map(move |result, act, _| {
+            if let Err(error_data) = result {
+                act.send_error(ws_id, error_data.for_command(command));
             }
+        }))
+    }
+}
+
+/// The rate limit the request counts against. None if it is not limited
+fn request_kind(request: &ClientRequest) -> Option<RequestKind> {
+    match request {
+        ClientRequest::Message(_) | ClientRequest::EditMessage(_) | ClientRequest::React(_) => {
+            Some(RequestKind::Messaging)
+        }
+        ClientRequest::NameUpdated(_) | ClientRequest::ImageUpdated(_) => {
+            Some(RequestKind::Profile)
+        }
+        ClientRequest::CreateNewUser(_) | ClientRequest::PairDevice(_) => {
+            Some(RequestKind::AccountCreation)
         }
+        ClientRequest::Authenticate(_) => Some(RequestKind::Authentication),
+        ClientRequest::Typing(_) => Some(RequestKind::Typing),
+        _ => None,
     }
 }
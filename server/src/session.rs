@@ -1,26 +1,29 @@
 use actix::prelude::*;
 use actix_web_actors::ws;
-use std::time::{Duration, Instant};
-use tracing::info;
+use chirp_protocol::{ClientRequest, ErrorCode, ErrorData, ServerEvent};
+use std::net::IpAddr;
+use std::time::Instant;
+use tracing::{error, info};
 
-use crate::server::{ChatServer, CommunicationType, Connect, Disconnect, HandleRequest, Message};
-
-const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
-
-const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+use crate::config::HeartbeatConfig;
+use crate::metrics::HEARTBEAT_DISCONNECTS;
+use crate::server::{ChatServer, CloseSession, Connect, Disconnect, HandleRequest, Message};
 
 pub struct WsChatSession {
     pub id: usize,
-    pub user_id: usize,
     pub hb: Instant,
     pub addr: Addr<ChatServer>,
+    pub heartbeat: HeartbeatConfig,
+    pub cert_user: Option<u64>,
+    pub ip: Option<IpAddr>,
 }
 
 impl WsChatSession {
     fn hb(&self, ctx: &mut ws::WebsocketContext<Self>) {
-        ctx.run_interval(HEARTBEAT_INTERVAL, |act, ctx| {
-            if Instant::now().duration_since(act.hb) > CLIENT_TIMEOUT {
+        ctx.run_interval(self.heartbeat.interval(), |act, ctx| {
+            if Instant::now().duration_since(act.hb) > act.heartbeat.client_timeout() {
                 info!("Websocket Client heartbeat failed, disconnecting!");
+                HEARTBEAT_DISCONNECTS.inc();
                 act.addr.do_send(Disconnect { id: act.id });
                 ctx.stop();
                 return;
@@ -39,7 +42,10 @@ impl Actor for WsChatSession {
         let addr = ctx.address();
         self.addr
             .send(Connect {
-                addr: addr.recipient(),
+                addr: addr.clone().recipient(),
+                closer: addr.recipient(),
+                cert_user: self.cert_user,
+                ip: self.ip,
             })
             .into_actor(self)
             .then(|res, act, ctx| {
@@ -64,7 +70,21 @@ impl Handler<Message> for WsChatSession {
     type Result = ();
 
     fn handle(&mut self, msg: Message, ctx: &mut Self::Context) {
-        ctx.text(msg.0);
+        ctx.text(msg.0.to_json());
+    }
+}
+
+impl Handler<CloseSession> for WsChatSession {
+    type Result = ();
+
+    fn handle(&mut self, msg: CloseSession, ctx: &mut Self::Context) {
+        let reason = ws::CloseReason {
+            code: ws::CloseCode::Policy,
+            description: Some(msg.0.message.to_owned()),
+        };
+        ctx.text(ServerEvent::Error(msg.0).to_json());
+        ctx.close(Some(reason));
+        ctx.stop();
     }
 }
 
@@ -86,60 +106,33 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsChatSession {
             ws::Message::Pong(_) => {
                 self.hb = Instant::now();
             }
-            ws::Message::Text(text) => {
-                let m = text.trim();
-                if m.starts_with('/') {
-                    let v: Vec<&str> = m.splitn(2, ' ').collect();
-                    match v[0] {
-                        "/create-new-user" => self.addr.do_send(HandleRequest {
-                            ws_id: self.id,
-                            data: v[1].to_string(),
-                            comm_type: CommunicationType::CreateNewUser,
-                        }),
-                        "/reconnect-user" => self.addr.do_send(HandleRequest {
-                            ws_id: self.id,
-                            data: v[1].to_string(),
-                            comm_type: CommunicationType::ReconnectUser,
-                        }),
-                        "/get-user-data" => self.addr.do_send(HandleRequest {
-                            ws_id: self.id,
-                            data: v[1].to_string(),
-                            comm_type: CommunicationType::SendUserData,
-                        }),
-                        "/message" => self.addr.do_send(HandleRequest {
-                            ws_id: self.id,
-                            data: v[1].to_string(),
-                            comm_type: CommunicationType::SendMessage,
-                        }),
-                        "/name-updated" => self.addr.do_send(HandleRequest {
-                            ws_id: self.id,
-                            data: v[1].to_string(),
-                            comm_type: CommunicationType::UpdateName,
-                        }),
-                        "/image-updated" => self.addr.do_send(HandleRequest {
-                            ws_id: self.id,
-                            data: v[1].to_string(),
-                            comm_type: CommunicationType::UpdateImageLink,
-                        }),
-                        "/message-number" => self.addr.do_send(HandleRequest {
-                            ws_id: self.id,
-                            data: v[1].to_string(),
-                            comm_type: CommunicationType::SendMessageNumber,
-                        }),
-                        "/sync-message" => self.addr.do_send(HandleRequest {
-                            ws_id: self.id,
-                            data: v[1].to_string(),
-                            comm_type: CommunicationType::SyncMessage,
-                        }),
-                        "/delete-message" => self.addr.do_send(HandleRequest {
-                            ws_id: self.id,
-                            data: v[1].to_string(),
-                            comm_type: CommunicationType::DeleteMessage,
-                        }),
-                        _ => ctx.text(format!("!!! unknown command: {m:?}")),
-                    }
+            ws::Message::Text(text) => match ClientRequest::from_json(text.trim()) {
+                // Requests of a session are processed one at a time so they finish in order
+                Ok(request) => self
+                    .addr
+                    .send(HandleRequest {
+                        ws_id: self.id,
+                        request,
+                    })
+                    .into_actor(self)
+                    .then(|res, _, ctx| {
+                        if res.is_err() {
+                            ctx.stop();
+                        }
+                        fut::ready(())
+                    })
+                    .wait(ctx),
+                Err(e) => {
+                    error!("Failed to parse request from WS session {}: {e}", self.id);
+                    let error_data = ErrorData {
+                        code: ErrorCode::InvalidRequest,
+                        message: e.to_string(),
+                        command: ClientRequest::command_from_raw(&text),
+                        retry_after: None,
+                    };
+                    ctx.notify(Message(ServerEvent::Error(error_data)));
                 }
-            }
+            },
             ws::Message::Binary(_) => println!("Unexpected binary"),
             ws::Message::Close(reason) => {
                 ctx.close(reason);
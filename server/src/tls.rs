@@ -0,0 +1,260 @@
+use actix_tls::accept::rustls_0_21::TlsStream;
+use actix_web::dev::Extensions;
+use actix_web::rt::net::TcpStream;
+use rustls::server::{
+    AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient, ClientHello,
+    ResolvesServerCert,
+};
+use rustls::sign::{any_supported_type, CertifiedKey};
+use rustls::{Certificate, PrivateKey, RootCertStore, ServerConfig};
+use rustls_pemfile::{certs, pkcs8_private_keys};
+use std::any::Any;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tracing::{error, info};
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+use crate::config::TlsConfig;
+
+struct LoadedCerts {
+    // Used when the client does not send a server name or no certificate matches it
+    default: Arc<CertifiedKey>,
+    // {Server name: Certificate}
+    by_name: HashMap<String, Arc<CertifiedKey>>,
+}
+
+impl LoadedCerts {
+    fn load(tls: &TlsConfig) -> Result<Self, String> {
+        let default = Arc::new(load_certified_key(&tls.cert, &tls.key)?);
+        let mut by_name = HashMap::new();
+
+        for sni_cert in tls.certificates.iter() {
+            let certified_key = Arc::new(load_certified_key(&sni_cert.cert, &sni_cert.key)?);
+            for name in sni_cert.server_names.iter() {
+                by_name.insert(name.to_lowercase(), certified_key.clone());
+            }
+        }
+
+        Ok(LoadedCerts { default, by_name })
+    }
+}
+
+/// Selects the certificate by the server name the client asked for.
+/// The certificates can be swapped without affecting the existing connections
+pub struct CertResolver {
+    certs: RwLock<LoadedCerts>,
+}
+
+impl CertResolver {
+    fn new(tls: &TlsConfig) -> Result<Self, String> {
+        Ok(CertResolver {
+            certs: RwLock::new(LoadedCerts::load(tls)?),
+        })
+    }
+
+    /// Loads every certificate again. The current ones are kept if any of them fails to load
+    pub fn reload(&self, tls: &TlsConfig) {
+        match LoadedCerts::load(tls) {
+            Ok(loaded) => {
+                *self.certs.write().unwrap() = loaded;
+                info!("TLS certificates reloaded");
+            }
+            Err(e) => {
+                error!("Failed to reload the TLS certificates. Keeping the current ones: {e}")
+            }
+        }
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        let certs = self.certs.read().unwrap();
+
+        let matched = client_hello
+            .server_name()
+            .and_then(|name| certs.by_name.get(&name.to_lowercase()));
+
+        Some(matched.unwrap_or(&certs.default).clone())
+    }
+}
+
+fn load_certified_key(cert_path: &Path, key_path: &Path) -> Result<CertifiedKey, String> {
+    let cert_file = File::open(cert_path)
+        .map_err(|e| format!("Failed to open {}: {e}", cert_path.display()))?;
+    let key_file =
+        File::open(key_path).map_err(|e| format!("Failed to open {}: {e}", key_path.display()))?;
+
+    let cert_chain: Vec<Certificate> = certs(&mut BufReader::new(cert_file))
+        .map_err(|e| format!("Failed to read certificates: {e}"))?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+    let mut keys: Vec<PrivateKey> = pkcs8_private_keys(&mut BufReader::new(key_file))
+        .map_err(|e| format!("Failed to read private keys: {e}"))?
+        .into_iter()
+        .map(PrivateKey)
+        .collect();
+
+    if cert_chain.is_empty() {
+        return Err(format!("No certificate found in {}", cert_path.display()));
+    }
+
+    if keys.is_empty() {
+        return Err(format!(
+            "Could not locate PKCS8 private keys in {}",
+            key_path.display()
+        ));
+    }
+
+    let signing_key = any_supported_type(&keys.remove(0))
+        .map_err(|_| format!("Unsupported private key in {}", key_path.display()))?;
+
+    Ok(CertifiedKey::new(cert_chain, signing_key))
+}
+
+/// Loads the CA the client certificates are verified with. None if no client CA is configured
+fn client_ca_roots(tls: &TlsConfig) -> Result<Option<RootCertStore>, String> {
+    let Some(ca_path) = &tls.client_ca else {
+        return Ok(None);
+    };
+
+    let ca_file =
+        File::open(ca_path).map_err(|e| format!("Failed to open {}: {e}", ca_path.display()))?;
+    let ca_certs = certs(&mut BufReader::new(ca_file))
+        .map_err(|e| format!("Failed to read client CA certificates: {e}"))?;
+
+    let mut roots = RootCertStore::empty();
+    for ca_cert in ca_certs {
+        roots.add(&Certificate(ca_cert)).map_err(|e| {
+            format!(
+                "Invalid client CA certificate in {}: {e}",
+                ca_path.display()
+            )
+        })?;
+    }
+
+    if roots.is_empty() {
+        return Err(format!("No certificate found in {}", ca_path.display()));
+    }
+
+    info!(
+        "Client certificates are verified with {}",
+        ca_path.display()
+    );
+
+    Ok(Some(roots))
+}
+
+pub fn load_rustls_config(tls: &TlsConfig) -> Result<(ServerConfig, Arc<CertResolver>), String> {
+    let resolver = Arc::new(CertResolver::new(tls)?);
+
+    let builder = ServerConfig::builder().with_safe_defaults();
+    let builder = match client_ca_roots(tls)? {
+        None => builder.with_no_client_auth(),
+        Some(roots) if tls.client_cert_required => {
+            builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots).boxed())
+        }
+        Some(roots) => builder
+            .with_client_cert_verifier(AllowAnyAnonymousOrAuthenticatedClient::new(roots).boxed()),
+    };
+    let config = builder.with_cert_resolver(resolver.clone());
+
+    Ok((config, resolver))
+}
+
+/// Last modification time of every certificate and key file
+fn modified_times(tls: &TlsConfig) -> Vec<Option<SystemTime>> {
+    let mut paths = vec![&tls.cert, &tls.key];
+    for sni_cert in tls.certificates.iter() {
+        paths.push(&sni_cert.cert);
+        paths.push(&sni_cert.key);
+    }
+
+    paths
+        .into_iter()
+        .map(|path| fs::metadata(path).and_then(|data| data.modified()).ok())
+        .collect()
+}
+
+/// Reloads the certificates on SIGHUP and whenever any of the files gets modified
+pub fn watch_certificates(resolver: Arc<CertResolver>, tls: TlsConfig) {
+    #[cfg(unix)]
+    {
+        use actix_rt::signal::unix::{signal, SignalKind};
+
+        let resolver = resolver.clone();
+        let tls = tls.clone();
+        actix_rt::spawn(async move {
+            let mut hangup = match signal(SignalKind::hangup()) {
+                Ok(hangup) => hangup,
+                Err(e) => {
+                    error!("Failed to listen for SIGHUP: {e}");
+                    return;
+                }
+            };
+
+            while hangup.recv().await.is_some() {
+                info!("SIGHUP received. Reloading TLS certificates");
+                resolver.reload(&tls);
+            }
+        });
+    }
+
+    if tls.reload_interval == 0 {
+        return;
+    }
+
+    actix_rt::spawn(async move {
+        let mut interval = actix_rt::time::interval(Duration::from_secs(tls.reload_interval));
+        let mut last_modified = modified_times(&tls);
+
+        loop {
+            interval.tick().await;
+
+            let modified = modified_times(&tls);
+            if modified != last_modified {
+                info!("TLS certificate files changed. Reloading");
+                resolver.reload(&tls);
+                last_modified = modified;
+            }
+        }
+    });
+}
+
+/// The verified certificate the client connected with
+#[derive(Clone)]
+pub struct ClientCertificate {
+    pub common_name: Option<String>,
+}
+
+/// Saves the client certificate of a new connection so the WS session can get it
+pub fn save_client_certificate(connection: &dyn Any, data: &mut Extensions) {
+    let Some(tls_stream) = connection.downcast_ref::<TlsStream<TcpStream>>() else {
+        return;
+    };
+
+    let (_, tls_connection) = tls_stream.get_ref();
+    let Some(certificate) = tls_connection
+        .peer_certificates()
+        .and_then(|certificates| certificates.first())
+    else {
+        return;
+    };
+
+    let common_name = X509Certificate::from_der(&certificate.0)
+        .ok()
+        .and_then(|(_, parsed)| {
+            parsed
+                .subject()
+                .iter_common_name()
+                .next()
+                .and_then(|name| name.as_str().ok())
+                .map(String::from)
+        });
+
+    data.insert(ClientCertificate { common_name });
+}
@@ -1,22 +1,65 @@
 use rand::rngs::OsRng;
 use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
 
 pub fn generate_user_token() -> String {
     let mut random_bytes = [0u8; 32];
 
     OsRng.fill_bytes(&mut random_bytes);
-    let hex_string: String = random_bytes
-        .iter()
-        .map(|byte| format!("{:02X}", byte))
-        .collect();
+    to_hex(&random_bytes)
+}
+
+pub fn generate_token_salt() -> String {
+    let mut random_bytes = [0u8; 16];
+
+    OsRng.fill_bytes(&mut random_bytes);
+    to_hex(&random_bytes)
+}
+
+/// A short code that is easy to type on another device
+pub fn generate_pairing_code() -> String {
+    const CHARSET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
 
-    hex_string
+    (0..8)
+        .map(|_| CHARSET[(OsRng.next_u32() as usize) % CHARSET.len()] as char)
+        .collect()
 }
 
-pub fn create_message_group(id_1: usize, id_2: usize) -> String {
+/// Hashes a token with the salt prepended. Only the hash and the salt get saved in the DB
+pub fn hash_user_token(token: &str, salt: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(salt.as_bytes());
+    hasher.update(token.as_bytes());
+    to_hex(&hasher.finalize())
+}
+
+/// Compares two hashes in constant time so the comparison does not leak how much matched
+pub fn token_matches(hash_1: &str, hash_2: &str) -> bool {
+    if hash_1.len() != hash_2.len() {
+        return false;
+    }
+
+    hash_1
+        .bytes()
+        .zip(hash_2.bytes())
+        .fold(0, |diff, (a, b)| diff | (a ^ b))
+        == 0
+}
+
+pub fn create_message_group(id_1: u64, id_2: u64) -> String {
     if id_1 > id_2 {
         format!("{}@{}", id_2, id_1)
     } else {
         format!("{}@{}", id_1, id_2)
     }
 }
+
+/// The two user IDs a message group was created from
+pub fn message_group_members(group: &str) -> Option<(u64, u64)> {
+    let (id_1, id_2) = group.split_once('@')?;
+    Some((id_1.parse().ok()?, id_2.parse().ok()?))
+}